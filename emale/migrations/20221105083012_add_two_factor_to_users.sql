@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT NULL;
+
+CREATE TABLE recovery_codes (
+  user_id uuid NOT NULL REFERENCES users(user_id),
+  code_hash TEXT NOT NULL,
+  used_at timestamptz NULL,
+  PRIMARY KEY(user_id, code_hash)
+);
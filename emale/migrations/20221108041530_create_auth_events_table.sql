@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE auth_events (
+  auth_event_id uuid NOT NULL,
+  event_type TEXT NOT NULL,
+  scope TEXT NOT NULL,
+  username TEXT NULL,
+  client_ip TEXT NULL,
+  lockout_seconds BIGINT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(auth_event_id)
+);
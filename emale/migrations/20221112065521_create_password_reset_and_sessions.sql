@@ -0,0 +1,19 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
+
+CREATE TABLE password_reset_tokens (
+  token_hash TEXT NOT NULL,
+  user_id uuid NOT NULL REFERENCES users(user_id),
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL,
+  used_at timestamptz NULL,
+  PRIMARY KEY(token_hash)
+);
+
+CREATE TABLE user_sessions (
+  session_id uuid NOT NULL,
+  user_id uuid NOT NULL REFERENCES users(user_id),
+  created_at timestamptz NOT NULL,
+  revoked_at timestamptz NULL,
+  PRIMARY KEY(session_id)
+);
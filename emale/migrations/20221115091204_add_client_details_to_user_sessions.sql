@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE user_sessions ADD COLUMN client_ip TEXT NULL;
+ALTER TABLE user_sessions ADD COLUMN user_agent TEXT NULL;
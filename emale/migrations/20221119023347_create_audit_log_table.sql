@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE audit_log (
+  audit_log_id uuid NOT NULL,
+  actor_user_id uuid NOT NULL REFERENCES users(user_id),
+  action TEXT NOT NULL,
+  target TEXT NULL,
+  client_ip TEXT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(audit_log_id)
+);
+
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at DESC);
+
+-- Rows can only be inserted, never changed or removed
+CREATE FUNCTION reject_audit_log_change() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+  BEFORE UPDATE OR DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION reject_audit_log_change();
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- W3C trace context of the request that enqueued the delivery,
+-- so that the worker spans can be linked back to it
+ALTER TABLE issue_delivery_queue ADD COLUMN traceparent TEXT NULL;
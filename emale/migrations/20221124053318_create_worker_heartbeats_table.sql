@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Refreshed by every running delivery worker, readiness
+-- fails when none of them has been seen recently
+CREATE TABLE worker_heartbeats(
+  worker_id uuid NOT NULL,
+  last_seen_at timestamptz NOT NULL,
+  PRIMARY KEY(worker_id)
+);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Disabled users can no longer log in, the row is kept
+-- for the audit log and the newsletters they published
+ALTER TABLE users ADD COLUMN disabled_at timestamptz NULL;
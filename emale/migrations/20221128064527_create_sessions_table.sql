@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Session state, when Postgres is used as session store
+CREATE TABLE sessions (
+  session_key TEXT NOT NULL,
+  state JSONB NOT NULL,
+  expires_at timestamptz NOT NULL,
+  PRIMARY KEY(session_key)
+);
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
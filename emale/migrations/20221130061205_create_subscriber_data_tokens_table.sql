@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE subscriber_data_tokens (
+  token_hash TEXT NOT NULL,
+  subscriber_id uuid NOT NULL REFERENCES subscriber(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL,
+  PRIMARY KEY(token_hash)
+);
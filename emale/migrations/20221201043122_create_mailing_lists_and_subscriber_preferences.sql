@@ -0,0 +1,28 @@
+-- Add migration script here
+CREATE TABLE mailing_lists (
+  list_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  PRIMARY KEY(list_id)
+);
+
+INSERT INTO mailing_lists (list_id, name) VALUES ('newsletter', 'Newsletter');
+
+ALTER TABLE newsletter_issues
+  ADD COLUMN list_id TEXT NOT NULL DEFAULT 'newsletter' REFERENCES mailing_lists(list_id);
+
+-- subscribers receive every list unless they opted out of it
+CREATE TABLE subscriber_list_opt_outs (
+  subscriber_id uuid NOT NULL REFERENCES subscriber(id) ON DELETE CASCADE,
+  list_id TEXT NOT NULL REFERENCES mailing_lists(list_id),
+  PRIMARY KEY(subscriber_id, list_id)
+);
+
+-- the new address replaces `subscriber.email` once it is confirmed
+CREATE TABLE subscriber_email_changes (
+  token_hash TEXT NOT NULL,
+  subscriber_id uuid NOT NULL REFERENCES subscriber(id) ON DELETE CASCADE,
+  new_email TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL,
+  PRIMARY KEY(token_hash)
+);
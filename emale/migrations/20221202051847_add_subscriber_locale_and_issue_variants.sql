@@ -0,0 +1,13 @@
+-- Add migration script here
+ALTER TABLE subscriber ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
+
+-- translations of an issue, which is itself in the default locale
+CREATE TABLE newsletter_issue_variants (
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues(newsletter_issue_id) ON DELETE CASCADE,
+  locale TEXT NOT NULL,
+  title TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  PRIMARY KEY(newsletter_issue_id, locale)
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- private issues are left out of the public archive and feeds
+ALTER TABLE newsletter_issues ADD COLUMN is_private BOOLEAN NOT NULL DEFAULT false;
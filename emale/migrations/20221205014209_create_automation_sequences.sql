@@ -0,0 +1,40 @@
+-- Add migration script here
+CREATE TABLE automation_sequences (
+  sequence_id uuid NOT NULL,
+  name TEXT NOT NULL,
+  is_active BOOLEAN NOT NULL DEFAULT true,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(sequence_id)
+);
+
+-- steps are sent in order, `delay_days` after the subscriber confirmed
+CREATE TABLE automation_steps (
+  step_id uuid NOT NULL,
+  sequence_id uuid NOT NULL REFERENCES automation_sequences(sequence_id) ON DELETE CASCADE,
+  position INTEGER NOT NULL,
+  delay_days INTEGER NOT NULL CHECK (delay_days >= 0),
+  subject TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  PRIMARY KEY(step_id),
+  UNIQUE(sequence_id, position)
+);
+
+-- subscribers are enrolled in the active sequences when they confirm
+CREATE TABLE subscriber_sequence_progress (
+  subscriber_id uuid NOT NULL REFERENCES subscriber(id) ON DELETE CASCADE,
+  sequence_id uuid NOT NULL REFERENCES automation_sequences(sequence_id) ON DELETE CASCADE,
+  started_at timestamptz NOT NULL,
+  -- position of the last step queued, NULL before the first one
+  reached_position INTEGER NULL,
+  reached_at timestamptz NULL,
+  completed_at timestamptz NULL,
+  PRIMARY KEY(subscriber_id, sequence_id)
+);
+
+CREATE TABLE automation_delivery_queue (
+  step_id uuid NOT NULL REFERENCES automation_steps(step_id) ON DELETE CASCADE,
+  subscriber_id uuid NOT NULL REFERENCES subscriber(id) ON DELETE CASCADE,
+  enqueued_at timestamptz NOT NULL,
+  PRIMARY KEY(step_id, subscriber_id)
+);
@@ -0,0 +1,42 @@
+-- Add migration script here
+CREATE TABLE webhook_endpoints (
+  endpoint_id uuid NOT NULL,
+  url TEXT NOT NULL,
+  -- signs the deliveries, shown to the admins
+  secret TEXT NOT NULL,
+  is_active BOOLEAN NOT NULL DEFAULT true,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(endpoint_id)
+);
+
+CREATE TABLE webhook_events (
+  event_id uuid NOT NULL,
+  event_type TEXT NOT NULL,
+  -- no foreign key, the event outlives an erased subscriber
+  subscriber_id uuid NULL,
+  -- the body of the deliveries
+  payload JSONB NOT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(event_id)
+);
+
+-- the outbox, one delivery per event and endpoint
+CREATE TABLE webhook_deliveries (
+  delivery_id uuid NOT NULL,
+  event_id uuid NOT NULL REFERENCES webhook_events(event_id) ON DELETE CASCADE,
+  endpoint_id uuid NOT NULL REFERENCES webhook_endpoints(endpoint_id) ON DELETE CASCADE,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  -- NULL once delivered or given up on
+  next_attempt_at timestamptz NULL,
+  delivered_at timestamptz NULL,
+  last_status SMALLINT NULL,
+  last_error TEXT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(delivery_id)
+);
+
+CREATE INDEX webhook_deliveries_next_attempt_at_idx
+  ON webhook_deliveries (next_attempt_at)
+  WHERE next_attempt_at IS NOT NULL;
+CREATE INDEX webhook_deliveries_endpoint_id_idx
+  ON webhook_deliveries (endpoint_id, created_at DESC);
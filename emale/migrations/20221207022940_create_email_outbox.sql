@@ -0,0 +1,21 @@
+-- Add migration script here
+-- transactional emails, written in the same transaction as the change
+-- they are about and sent by the delivery worker
+CREATE TABLE email_outbox (
+  email_id uuid NOT NULL,
+  recipient TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  traceparent TEXT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  -- NULL once given up on, sent emails are deleted
+  next_attempt_at timestamptz NULL,
+  last_error TEXT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(email_id)
+);
+
+CREATE INDEX email_outbox_next_attempt_at_idx
+  ON email_outbox (next_attempt_at)
+  WHERE next_attempt_at IS NOT NULL;
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- keys are now scoped to a user (`user:<id>`) or, on anonymous routes,
+-- to a client fingerprint (`client:<hash>`)
+ALTER TABLE idempotency ADD COLUMN scope TEXT;
+UPDATE idempotency SET scope = 'user:' || user_id;
+ALTER TABLE idempotency ALTER COLUMN scope SET NOT NULL;
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_pkey;
+ALTER TABLE idempotency DROP COLUMN user_id;
+ALTER TABLE idempotency ADD PRIMARY KEY (scope, idempotency_key);
+
+-- hash of the request a key was first used with, NULL for older keys
+ALTER TABLE idempotency ADD COLUMN request_hash BYTEA;
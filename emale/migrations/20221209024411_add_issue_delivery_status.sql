@@ -0,0 +1,11 @@
+-- Add migration script here
+-- `sending` issues are dequeued by the workers, `paused` ones are left in
+-- the queue until resumed, the queue of `cancelled` ones is purged
+ALTER TABLE newsletter_issues
+  ADD COLUMN delivery_status TEXT NOT NULL DEFAULT 'sending'
+  CHECK (delivery_status IN ('sending', 'paused', 'cancelled'));
+-- counted from now on, older issues start at 0
+ALTER TABLE newsletter_issues ADD COLUMN sent_deliveries INTEGER NOT NULL DEFAULT 0;
+-- deliveries purged from the queue, NULL unless cancelled
+ALTER TABLE newsletter_issues ADD COLUMN cancelled_deliveries INTEGER;
+ALTER TABLE newsletter_issues ADD COLUMN cancelled_at timestamptz;
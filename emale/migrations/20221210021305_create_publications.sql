@@ -0,0 +1,43 @@
+-- Add migration script here
+-- Requests are routed to the publication of their host, hosts that match
+-- no publication get the default one. `base_url` and `sender_email` fall
+-- back to the application settings when NULL.
+CREATE TABLE publications (
+  publication_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  host TEXT NULL UNIQUE,
+  base_url TEXT NULL,
+  sender_email TEXT NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY(publication_id)
+);
+
+INSERT INTO publications (publication_id, name, created_at)
+VALUES ('default', 'emale', now());
+
+-- admins only manage the publications they are members of
+CREATE TABLE publication_members (
+  publication_id TEXT NOT NULL REFERENCES publications(publication_id),
+  user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+  PRIMARY KEY(publication_id, user_id)
+);
+
+INSERT INTO publication_members (publication_id, user_id)
+SELECT 'default', user_id FROM users;
+
+ALTER TABLE subscriber
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
+-- the same address can subscribe to several publications
+ALTER TABLE subscriber DROP CONSTRAINT subscriber_email_key;
+ALTER TABLE subscriber ADD CONSTRAINT subscriber_publication_email_key UNIQUE (publication_id, email);
+
+ALTER TABLE newsletter_issues
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
+ALTER TABLE issue_delivery_queue
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
+ALTER TABLE email_outbox
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
+ALTER TABLE automation_sequences
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
+ALTER TABLE webhook_endpoints
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
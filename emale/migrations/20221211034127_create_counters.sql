@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Login throttle and subscribe protection counters, for deployments
+-- without Redis. Expired rows are ignored and purged periodically.
+CREATE TABLE counters (
+  key TEXT NOT NULL,
+  value BIGINT NOT NULL,
+  expires_at timestamptz NOT NULL,
+  PRIMARY KEY(key)
+);
+
+CREATE TABLE counter_hits (
+  key TEXT NOT NULL,
+  expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX counter_hits_key_idx ON counter_hits (key, expires_at);
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- entries recorded before publications existed belong to the default one
+ALTER TABLE audit_log
+  ADD COLUMN publication_id TEXT NOT NULL DEFAULT 'default' REFERENCES publications(publication_id);
+DROP INDEX audit_log_created_at_idx;
+CREATE INDEX audit_log_publication_created_at_idx ON audit_log (publication_id, created_at DESC);
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- sessions expire after a day without use; existing ones are given a day
+-- from their creation, they will be renewed on their next request
+ALTER TABLE user_sessions ADD COLUMN last_seen_at timestamptz NULL;
+ALTER TABLE user_sessions ADD COLUMN expires_at timestamptz NULL;
+UPDATE user_sessions
+SET last_seen_at = created_at, expires_at = created_at + interval '1 day';
+ALTER TABLE user_sessions ALTER COLUMN last_seen_at SET NOT NULL;
+ALTER TABLE user_sessions ALTER COLUMN expires_at SET NOT NULL;
+CREATE INDEX user_sessions_expires_at_idx ON user_sessions (expires_at);
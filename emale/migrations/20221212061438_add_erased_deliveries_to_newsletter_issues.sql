@@ -0,0 +1,3 @@
+-- Add migration script here
+-- deliveries dropped from the queue because the subscriber erased their data
+ALTER TABLE newsletter_issues ADD COLUMN erased_deliveries INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- The TOTP secrets are encrypted with `application.totp_encryption_key`,
+-- which the database never sees: the existing secrets are set aside here
+-- and encrypted by the application on startup, see
+-- `encrypt_legacy_totp_secrets`.
+ALTER TABLE users RENAME COLUMN totp_secret TO legacy_totp_secret;
+ALTER TABLE users ADD COLUMN totp_secret BYTEA NULL;
+-- a pending secret was never confirmed, the setup is simply started again
+UPDATE users SET legacy_totp_secret = NULL WHERE totp_enabled = false;
@@ -0,0 +1,311 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::i18n::Locale;
+
+// Published issues that are not private are public: they are listed on
+// `/archive` and in the feeds, each one with a permalink to its web view.
+// Issues are the same for every subscriber, there is nothing personal
+// to strip from them.
+//
+// The web view is served from the same origin as `/admin`, the HTML of
+// an issue goes through an allow-list before it is shown there: scripts,
+// event handlers and the like are stripped.
+
+const FEED_LENGTH: i64 = 20;
+
+pub struct ArchivedIssue {
+	pub newsletter_issue_id: Uuid,
+	pub title: String,
+	pub text_content: String,
+	pub html_content: String,
+	pub published_at: DateTime<Utc>,
+}
+
+pub fn permalink(base_url: &str, newsletter_issue_id: Uuid) -> String {
+	format!("{}/archive/{}", base_url, newsletter_issue_id)
+}
+
+// Newest first, `limit` issues at most.
+#[tracing::instrument(name = "Get archived issues", skip(pool))]
+pub async fn get_archived_issues(
+	pool: &PgPool,
+	publication_id: &str,
+	limit: Option<i64>
+) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+	let issues = sqlx::query_as!(
+		ArchivedIssue,
+		r#"
+		SELECT
+			newsletter_issue_id,
+			title,
+			text_content,
+			html_content,
+			published_at::timestamptz AS "published_at!"
+		FROM newsletter_issues
+		WHERE published_at IS NOT NULL AND NOT is_private AND publication_id = $2
+		ORDER BY published_at::timestamptz DESC
+		LIMIT $1
+		"#,
+		limit,
+		publication_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the archived issues")?;
+	Ok(issues)
+}
+
+pub async fn get_feed_issues(
+	pool: &PgPool,
+	publication_id: &str
+) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+	get_archived_issues(pool, publication_id, Some(FEED_LENGTH)).await
+}
+
+// In the translation matching `locale` when there is one.
+#[tracing::instrument(name = "Get archived issue", skip(pool))]
+pub async fn get_archived_issue(
+	pool: &PgPool,
+	publication_id: &str,
+	newsletter_issue_id: Uuid,
+	locale: Locale
+) -> Result<Option<ArchivedIssue>, anyhow::Error> {
+	let issue = sqlx::query_as!(
+		ArchivedIssue,
+		r#"
+		SELECT
+			i.newsletter_issue_id,
+			COALESCE(v.title, i.title) AS "title!",
+			COALESCE(v.text_content, i.text_content) AS "text_content!",
+			COALESCE(v.html_content, i.html_content) AS "html_content!",
+			i.published_at::timestamptz AS "published_at!"
+		FROM newsletter_issues i
+		LEFT JOIN newsletter_issue_variants v ON
+			v.newsletter_issue_id = i.newsletter_issue_id AND
+			v.locale = $2
+		WHERE
+			i.newsletter_issue_id = $1 AND
+			i.published_at IS NOT NULL AND
+			NOT i.is_private AND
+			i.publication_id = $3
+		"#,
+		newsletter_issue_id,
+		locale.as_str(),
+		publication_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to retrieve the archived issue")?;
+	Ok(issue.map(|issue| ArchivedIssue {
+		html_content: sanitize_html(&issue.html_content),
+		..issue
+	}))
+}
+
+fn sanitize_html(html: &str) -> String {
+	ammonia::clean(html)
+}
+
+pub struct IssueSummary {
+	pub newsletter_issue_id: Uuid,
+	pub title: String,
+	pub published_at: Option<String>,
+	pub is_private: bool,
+	pub delivery_status: String,
+	pub pending_deliveries: i64,
+	pub sent_deliveries: i32,
+	pub cancelled_deliveries: Option<i32>,
+	pub erased_deliveries: i32,
+}
+
+// Every issue of the publication, private ones included, for the admin
+// pages.
+#[tracing::instrument(name = "Get issue summaries", skip(pool))]
+pub async fn get_issue_summaries(
+	pool: &PgPool,
+	publication_id: &str
+) -> Result<Vec<IssueSummary>, anyhow::Error> {
+	let issues = sqlx::query_as!(
+		IssueSummary,
+		r#"
+		SELECT
+			newsletter_issue_id,
+			title,
+			published_at,
+			is_private,
+			delivery_status,
+			(
+				SELECT COUNT(*) FROM issue_delivery_queue q
+				WHERE q.newsletter_issue_id = i.newsletter_issue_id
+			) AS "pending_deliveries!",
+			sent_deliveries,
+			cancelled_deliveries,
+			erased_deliveries
+		FROM newsletter_issues i
+		WHERE publication_id = $1
+		ORDER BY published_at::timestamptz DESC NULLS LAST
+		"#,
+		publication_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the issues")?;
+	Ok(issues)
+}
+
+// Returns false if the publication has no such issue.
+#[tracing::instrument(name = "Set issue visibility", skip(pool))]
+pub async fn set_issue_private(
+	pool: &PgPool,
+	publication_id: &str,
+	newsletter_issue_id: Uuid,
+	is_private: bool
+) -> Result<bool, anyhow::Error> {
+	let updated = sqlx::query!(
+		r#"
+		UPDATE newsletter_issues SET is_private = $2
+		WHERE newsletter_issue_id = $1 AND publication_id = $3
+		"#,
+		newsletter_issue_id,
+		is_private,
+		publication_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to update the issue visibility")?
+	.rows_affected();
+	Ok(updated == 1)
+}
+
+fn escape_xml(s: &str) -> String {
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+// RSS 2.0, the HTML content of the issues is escaped in `description`.
+pub fn rss_feed(base_url: &str, title: &str, issues: &[ArchivedIssue]) -> String {
+	let mut feed = format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n\
+		<channel>\n\
+		<title>{title}</title>\n\
+		<link>{link}</link>\n\
+		<description>{title}</description>\n\
+		<atom:link href=\"{link}/feed.rss\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+		title = escape_xml(title),
+		link = escape_xml(&format!("{}/archive", base_url)),
+	);
+	for issue in issues {
+		let link = escape_xml(&permalink(base_url, issue.newsletter_issue_id));
+		feed.push_str(&format!(
+			"<item>\n\
+			<title>{}</title>\n\
+			<link>{}</link>\n\
+			<guid isPermaLink=\"true\">{}</guid>\n\
+			<pubDate>{}</pubDate>\n\
+			<description>{}</description>\n\
+			</item>\n",
+			escape_xml(&issue.title),
+			link,
+			link,
+			issue.published_at.to_rfc2822(),
+			escape_xml(&issue.html_content),
+		));
+	}
+	feed.push_str("</channel>\n</rss>\n");
+	feed
+}
+
+// Atom, entries are identified by the id of the issue.
+pub fn atom_feed(base_url: &str, title: &str, issues: &[ArchivedIssue]) -> String {
+	let updated = issues
+		.iter()
+		.map(|issue| issue.published_at)
+		.max()
+		.unwrap_or_else(Utc::now);
+	let mut feed = format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+		<feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+		<id>{archive}</id>\n\
+		<title>{title}</title>\n\
+		<updated>{updated}</updated>\n\
+		<link href=\"{archive}\"/>\n\
+		<link href=\"{self_link}\" rel=\"self\" type=\"application/atom+xml\"/>\n",
+		archive = escape_xml(&format!("{}/archive", base_url)),
+		self_link = escape_xml(&format!("{}/feed.atom", base_url)),
+		title = escape_xml(title),
+		updated = updated.to_rfc3339(),
+	);
+	for issue in issues {
+		feed.push_str(&format!(
+			"<entry>\n\
+			<id>urn:uuid:{}</id>\n\
+			<title>{}</title>\n\
+			<link href=\"{}\"/>\n\
+			<updated>{}</updated>\n\
+			<author><name>{}</name></author>\n\
+			<content type=\"html\">{}</content>\n\
+			</entry>\n",
+			issue.newsletter_issue_id,
+			escape_xml(&issue.title),
+			escape_xml(&permalink(base_url, issue.newsletter_issue_id)),
+			issue.published_at.to_rfc3339(),
+			escape_xml(title),
+			escape_xml(&issue.html_content),
+		));
+	}
+	feed.push_str("</feed>\n");
+	feed
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{atom_feed, escape_xml, rss_feed, ArchivedIssue};
+	use chrono::{TimeZone, Utc};
+	use uuid::Uuid;
+
+	fn issue() -> ArchivedIssue {
+		ArchivedIssue {
+			newsletter_issue_id: Uuid::nil(),
+			title: "Tom & Jerry".into(),
+			text_content: "text".into(),
+			html_content: "<p>html</p>".into(),
+			published_at: Utc.ymd(2022, 12, 1).and_hms(4, 31, 22),
+		}
+	}
+
+	#[test]
+	fn xml_special_characters_are_escaped() {
+		assert_eq!(escape_xml(r#"<a href="x">'&'</a>"#), "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;");
+	}
+
+	#[test]
+	fn rss_items_link_to_the_permalink() {
+		let feed = rss_feed("https://example.com", "emale", &[issue()]);
+		assert!(feed.contains("<title>Tom &amp; Jerry</title>"));
+		assert!(feed.contains(&format!("<link>https://example.com/archive/{}</link>", Uuid::nil())));
+		assert!(feed.contains("<pubDate>Thu, 01 Dec 2022 04:31:22 +0000</pubDate>"));
+		assert!(feed.contains("<description>&lt;p&gt;html&lt;/p&gt;</description>"));
+	}
+
+	#[test]
+	fn atom_feeds_are_updated_with_the_latest_issue() {
+		let feed = atom_feed("https://example.com", "emale", &[issue()]);
+		assert!(feed.contains("<updated>2022-12-01T04:31:22+00:00</updated>"));
+		assert!(feed.contains(&format!("<id>urn:uuid:{}</id>", Uuid::nil())));
+		assert!(feed.contains(r#"<content type="html">&lt;p&gt;html&lt;/p&gt;</content>"#));
+	}
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditAction {
+	Login,
+	Logout,
+	PasswordChange,
+	PasswordReset,
+	NewsletterPublish,
+	IssueVisibilityChange,
+	SequenceEdit,
+	WebhookEdit,
+	IssueDeliveryChange,
+}
+
+impl AuditAction {
+	pub const ALL: [AuditAction; 9] = [
+		AuditAction::Login,
+		AuditAction::Logout,
+		AuditAction::PasswordChange,
+		AuditAction::PasswordReset,
+		AuditAction::NewsletterPublish,
+		AuditAction::IssueVisibilityChange,
+		AuditAction::SequenceEdit,
+		AuditAction::WebhookEdit,
+		AuditAction::IssueDeliveryChange,
+	];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			AuditAction::Login => "login",
+			AuditAction::Logout => "logout",
+			AuditAction::PasswordChange => "password_change",
+			AuditAction::PasswordReset => "password_reset",
+			AuditAction::NewsletterPublish => "newsletter_publish",
+			AuditAction::IssueVisibilityChange => "issue_visibility_change",
+			AuditAction::SequenceEdit => "sequence_edit",
+			AuditAction::WebhookEdit => "webhook_edit",
+			AuditAction::IssueDeliveryChange => "issue_delivery_change",
+		}
+	}
+}
+
+impl TryFrom<String> for AuditAction {
+	type Error = String;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		AuditAction::ALL
+			.into_iter()
+			.find(|a| a.as_str() == value)
+			.ok_or_else(|| format!("{} is not a known audit action", value))
+	}
+}
+
+pub struct AuditEntry {
+	pub username: String,
+	pub action: String,
+	pub target: Option<String>,
+	pub client_ip: Option<String>,
+	pub created_at: DateTime<Utc>,
+}
+
+// Takes any executor so that the entry can be written in the same
+// transaction as the action it records. Entries belong to the publication
+// the action was taken on, and are only listed to its admins.
+#[tracing::instrument(name = "Record audit entry", skip(executor))]
+pub async fn record_audit_entry<'c, E>(
+	executor: E,
+	actor: Uuid,
+	publication_id: &str,
+	action: AuditAction,
+	target: Option<&str>,
+	client_ip: Option<&str>
+) -> Result<(), sqlx::Error>
+where
+	E: PgExecutor<'c>,
+{
+	sqlx::query!(
+		r#"
+		INSERT INTO audit_log (
+			audit_log_id,
+			actor_user_id,
+			publication_id,
+			action,
+			target,
+			client_ip,
+			created_at
+		)
+		VALUES ($1, $2, $3, $4, $5, $6, now())
+		"#,
+		Uuid::new_v4(),
+		actor,
+		publication_id,
+		action.as_str(),
+		target,
+		client_ip
+	)
+	.execute(executor)
+	.await?;
+	Ok(())
+}
+
+#[tracing::instrument(name = "Get audit entries", skip(pool))]
+pub async fn get_audit_entries(
+	pool: &PgPool,
+	publication_id: &str,
+	action: Option<AuditAction>,
+	actor: Option<&str>,
+	limit: i64
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+	sqlx::query_as!(
+		AuditEntry,
+		r#"
+		SELECT
+			u.username,
+			a.action,
+			a.target,
+			a.client_ip,
+			a.created_at
+		FROM audit_log a
+		JOIN users u ON u.user_id = a.actor_user_id
+		WHERE
+			a.publication_id = $1 AND
+			($2::text IS NULL OR a.action = $2) AND
+			($3::text IS NULL OR u.username = $3)
+		ORDER BY a.created_at DESC
+		LIMIT $4
+		"#,
+		publication_id,
+		action.map(|a| a.as_str()),
+		actor,
+		limit
+	)
+	.fetch_all(pool)
+	.await
+}
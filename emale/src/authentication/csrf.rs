@@ -0,0 +1,103 @@
+use actix_http::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::{web, FromRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::Next;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+use crate::session_state::TypedSession;
+use crate::utils::error_500;
+
+pub const CSRF_TOKEN_FIELD: &str = "csrf_token";
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+#[derive(serde::Deserialize)]
+struct CsrfFormData {
+	csrf_token: Option<String>,
+}
+
+fn generate_csrf_token() -> String {
+	let mut rng = thread_rng();
+	std::iter::repeat_with(|| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(CSRF_TOKEN_LENGTH)
+		.collect()
+}
+
+// One synchronizer token per session, created the first time
+// a form is rendered and dropped when the session is purged.
+pub fn get_or_create_csrf_token(session: &TypedSession) -> Result<String, anyhow::Error> {
+	if let Some(token) = session.get_csrf_token()? {
+		return Ok(token);
+	}
+	let token = generate_csrf_token();
+	session.insert_csrf_token(&token)?;
+	Ok(token)
+}
+
+// Compares every byte so that the time taken does not leak
+// how much of the submitted token is correct.
+fn tokens_match(expected: &str, submitted: &str) -> bool {
+	expected.len() == submitted.len() && expected
+		.bytes()
+		.zip(submitted.bytes())
+		.fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn reject_request(reason: &str) -> actix_web::Error {
+	FlashMessage::error("Invalid or missing CSRF token, please try again").send();
+	let e = anyhow::anyhow!("{}", reason);
+	InternalError::from_response(e, HttpResponse::Forbidden().finish()).into()
+}
+
+// Requests that change state must carry the session token, either as a
+// `csrf_token` form field or in the `X-CSRF-Token` header.
+pub async fn reject_invalid_csrf_token(
+	mut req: ServiceRequest,
+	next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	if req.method().is_safe() {
+		return next.call(req).await;
+	}
+
+	let session = {
+		let (http_request, payload) = req.parts_mut();
+		TypedSession::from_request(http_request, payload).await
+	}?;
+	let expected = match session.get_csrf_token().map_err(error_500)? {
+		Some(token) => token,
+		None => return Err(reject_request("The session has no CSRF token")),
+	};
+
+	let header_token = req
+		.headers()
+		.get(CSRF_TOKEN_HEADER)
+		.and_then(|h| h.to_str().ok())
+		.map(|h| h.to_owned());
+	let submitted = match header_token {
+		Some(token) => Some(token),
+		None => {
+			let body = {
+				let (http_request, payload) = req.parts_mut();
+				web::Bytes::from_request(http_request, payload).await
+			}?;
+			let token = serde_urlencoded::from_bytes::<CsrfFormData>(&body)
+				.ok()
+				.and_then(|form| form.csrf_token);
+			// the body has been consumed, hand it back for the handler
+			let (_, mut payload) = actix_http::h1::Payload::create(true);
+			payload.unread_data(body);
+			req.set_payload(payload.into());
+			token
+		}
+	};
+
+	match submitted {
+		Some(token) if tokens_match(&expected, &token) => next.call(req).await,
+		Some(_) => Err(reject_request("The CSRF token does not match the session")),
+		None => Err(reject_request("The request has no CSRF token")),
+	}
+}
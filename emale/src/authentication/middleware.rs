@@ -2,12 +2,15 @@ use std::ops::Deref;
 
 use actix_http::HttpMessage;
 use actix_http::body::MessageBody;
-use actix_web::FromRequest;
+use actix_web::{web, FromRequest, HttpResponse};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web_lab::middleware::Next;
 use actix_web::error::InternalError;
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::authentication::is_session_active;
+use crate::publications::{is_member, Publication};
 use crate::session_state::TypedSession;
 use crate::utils::{error_500, see_other};
 
@@ -39,11 +42,46 @@ pub async fn reject_users(
 
 	match session.get_user_id().map_err(error_500)? {
 		Some(user_id) => {
+			let pool = req
+				.app_data::<web::Data<PgPool>>()
+				.cloned()
+				.ok_or_else(|| error_500("Missing database pool"))?;
+			// sessions revoked server side (e.g. after a password reset)
+			// are purged, as if the user had logged out
+			let is_active = match session.get_session_id().map_err(error_500)? {
+				Some(session_id) => is_session_active(session_id, user_id, &pool)
+					.await
+					.map_err(error_500)?,
+				None => false,
+			};
+			if !is_active {
+				session.log_out();
+				let response = see_other("/login");
+				let e = anyhow::anyhow!("The user session has been revoked");
+				return Err(InternalError::from_response(e, response).into());
+			}
+			// admins only manage the publications they are members of
+			let publication = {
+				let (http_request, payload) = req.parts_mut();
+				Publication::from_request(http_request, payload).await
+			}?;
+			if !is_member(&pool, &publication.publication_id, user_id)
+				.await
+				.map_err(error_500)?
+			{
+				let response = HttpResponse::Forbidden().finish();
+				let e = anyhow::anyhow!("The user is not a member of the publication");
+				return Err(InternalError::from_response(e, response).into());
+			}
 			req.extensions_mut().insert(UserId(user_id));
 			next.call(req).await
 		},
 		None => {
-			let response = see_other("/login");
+			let location = match session.get_pending_two_factor().map_err(error_500)? {
+				Some(_) => "/login/two-factor",
+				None => "/login",
+			};
+			let response = see_other(location);
 			let e = anyhow::anyhow!("The user has not logged in");
 			Err(InternalError::from_response(e, response).into())
 		}
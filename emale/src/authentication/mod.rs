@@ -1,6 +1,19 @@
 pub mod password;
 pub mod middleware;
+pub mod two_factor;
+pub mod throttle;
+pub mod sessions;
+pub mod password_reset;
+pub mod csrf;
+pub mod users;
 
 pub use middleware::UserId;
-pub use password::{AuthError, Credentials, get_stored_password_hash,
-  validate_credentials, verify_pasword_hash, change_password};
\ No newline at end of file
+pub use password::{AuthError, Credentials, NewPasswordError, get_stored_password_hash,
+  validate_credentials, verify_pasword_hash, change_password, validate_new_password};
+pub use two_factor::{TwoFactorState, get_two_factor_state, validate_second_factor};
+pub use throttle::{LoginThrottle, PasswordResetThrottle};
+pub use csrf::get_or_create_csrf_token;
+pub use sessions::{UserSession, register_session, is_session_active, list_active_sessions,
+  revoke_session, revoke_user_session, revoke_other_sessions, revoke_all_sessions,
+  purge_user_sessions};
+pub use users::{create_user, disable_user, get_user_id, reset_user_password};
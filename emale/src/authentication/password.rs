@@ -1,6 +1,7 @@
 use anyhow::Context;
 use argon2::{PasswordHash, Argon2, PasswordVerifier, Algorithm, Version, Params, PasswordHasher, password_hash::SaltString};
 use secrecy::{Secret, ExposeSecret};
+use sqlx::postgres::PgExecutor;
 use sqlx::PgPool;
 use crate::telemetry::spawn_blocking_with_tracing;
 
@@ -12,6 +13,14 @@ pub enum AuthError {
 	UnexpectedError(#[from] anyhow::Error)
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum NewPasswordError {
+	#[error("Password must be longer than 12 characters and less than 128 characters")]
+	InvalidLength,
+	#[error("You entered two different new passwords - the fieid values must match")]
+	Mismatch
+}
+
 pub struct Credentials {
 	pub username: String,
 	pub password: Secret<String>
@@ -29,7 +38,7 @@ pub async fn get_stored_password_hash(
 		r#"
 		select user_id, password_hash
 		FROM users
-		where username = $1
+		where username = $1 AND disabled_at IS NULL
 		"#,
 		username,
 	)
@@ -104,12 +113,29 @@ pub async fn validate_credentials(
 	)
 }
 
+pub fn validate_new_password(
+	new_password: &Secret<String>,
+	new_password_check: &Secret<String>
+) -> Result<(), NewPasswordError> {
+	let length = new_password.expose_secret().len();
+	if length < 12 || length > 128 {
+		return Err(NewPasswordError::InvalidLength);
+	}
+	if new_password.expose_secret() != new_password_check.expose_secret() {
+		return Err(NewPasswordError::Mismatch);
+	}
+	Ok(())
+}
 
-pub async fn change_password(
+// Takes any executor so that the change can be part of a transaction.
+pub async fn change_password<'c, E>(
 	user_id: uuid::Uuid,
 	password: Secret<String>,
-	pool: &PgPool
-) -> Result<(), anyhow::Error> {
+	executor: E
+) -> Result<(), anyhow::Error>
+where
+	E: PgExecutor<'c>,
+{
 	let password_hash = spawn_blocking_with_tracing(
 		move || compute_password_hash(password)
 	)
@@ -124,14 +150,14 @@ pub async fn change_password(
 		password_hash.expose_secret(),
 		user_id
 	)
-	.execute(pool)
+	.execute(executor)
 	.await
 	.context("Failed to change user's password in database")?;
 	Ok(())
 	
 }
 
-fn compute_password_hash(
+pub(super) fn compute_password_hash(
 	password: Secret<String>
 ) -> Result<Secret<String>, anyhow::Error> {
 	let salt = SaltString::generate(&mut rand::thread_rng());
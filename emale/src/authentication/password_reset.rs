@@ -0,0 +1,140 @@
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgExecutor;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+const RESET_TOKEN_LENGTH: usize = 32;
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+pub struct ResetRecipient {
+	pub user_id: Uuid,
+	pub email: SubscriberEmail,
+}
+
+fn generate_reset_token() -> String {
+	let mut rng = thread_rng();
+	std::iter::repeat_with(|| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(RESET_TOKEN_LENGTH)
+		.collect()
+}
+
+// Only the hash is stored, a leaked table can't be used to reset passwords.
+fn hash_reset_token(token: &str) -> String {
+	hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[tracing::instrument(name = "Get reset recipient", skip(email, pool))]
+pub async fn get_reset_recipient(
+	email: &str,
+	pool: &PgPool
+) -> Result<Option<ResetRecipient>, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT user_id, email as "email!"
+		FROM users
+		WHERE lower(email) = lower($1) AND disabled_at IS NULL
+		"#,
+		email
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to perform a query to retrieve reset recipient")?;
+	match row {
+		Some(row) => {
+			let email = SubscriberEmail::parse(row.email).map_err(|e| anyhow::anyhow!(e))?;
+			Ok(Some(ResetRecipient { user_id: row.user_id, email }))
+		}
+		None => Ok(None),
+	}
+}
+
+// Issuing a new token invalidates the ones sent before. Runs in the
+// transaction that queues the email carrying the token.
+#[tracing::instrument(name = "Create password reset token", skip(transaction))]
+pub async fn create_reset_token(
+	transaction: &mut Transaction<'_, Postgres>,
+	user_id: Uuid
+) -> Result<String, anyhow::Error> {
+	let token = generate_reset_token();
+	sqlx::query!(
+		r#"
+		UPDATE password_reset_tokens
+		SET used_at = now()
+		WHERE user_id = $1 AND used_at IS NULL
+		"#,
+		user_id
+	)
+	.execute(&mut *transaction)
+	.await
+	.context("Failed to invalidate previous reset tokens")?;
+	sqlx::query!(
+		r#"
+		INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+		VALUES ($1, $2, now(), now() + make_interval(mins => $3))
+		"#,
+		hash_reset_token(&token),
+		user_id,
+		RESET_TOKEN_TTL_MINUTES as i32
+	)
+	.execute(&mut *transaction)
+	.await
+	.context("Failed to store password reset token")?;
+	Ok(token)
+}
+
+#[tracing::instrument(name = "Check password reset token", skip(token, pool))]
+pub async fn is_reset_token_valid(
+	token: &str,
+	pool: &PgPool
+) -> Result<bool, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT user_id
+		FROM password_reset_tokens
+		WHERE
+			token_hash = $1 AND
+			used_at IS NULL AND
+			expires_at > now()
+		"#,
+		hash_reset_token(token)
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to check password reset token")?;
+	Ok(row.is_some())
+}
+
+// Marks the token as used and returns its owner. A token can only be
+// consumed once, even by concurrent requests. Run it in the transaction
+// that changes the password, so that the token is not spent if that fails.
+#[tracing::instrument(name = "Consume password reset token", skip(token, executor))]
+pub async fn consume_reset_token<'c, E>(
+	token: &str,
+	executor: E
+) -> Result<Option<Uuid>, anyhow::Error>
+where
+	E: PgExecutor<'c>,
+{
+	let row = sqlx::query!(
+		r#"
+		UPDATE password_reset_tokens
+		SET used_at = now()
+		WHERE
+			token_hash = $1 AND
+			used_at IS NULL AND
+			expires_at > now()
+		RETURNING user_id
+		"#,
+		hash_reset_token(token)
+	)
+	.fetch_optional(executor)
+	.await
+	.context("Failed to consume password reset token")?;
+	Ok(row.map(|r| r.user_id))
+}
@@ -0,0 +1,219 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Sessions not used for a day expire, like the session state with the
+// default `SessionMiddleware` settings.
+const IDLE_TIMEOUT_SECONDS: f64 = 24. * 60. * 60.;
+// `is_session_active` does not write when the session was seen this
+// recently, most requests only have to read the session row.
+const LAST_SEEN_SLACK_SECONDS: f64 = 60.;
+
+pub struct UserSession {
+	pub session_id: Uuid,
+	pub created_at: DateTime<Utc>,
+	pub last_seen_at: DateTime<Utc>,
+	pub client_ip: Option<String>,
+	pub user_agent: Option<String>,
+}
+
+// Every logged in session is registered in Postgres, whatever store
+// holds the session state, so that it can be revoked server side.
+// Expired and revoked rows are deleted by `purge_user_sessions`.
+#[tracing::instrument(name = "Register user session", skip(pool))]
+pub async fn register_session(
+	user_id: Uuid,
+	client_ip: &str,
+	user_agent: Option<&str>,
+	pool: &PgPool
+) -> Result<Uuid, anyhow::Error> {
+	let session_id = Uuid::new_v4();
+	sqlx::query!(
+		r#"
+		INSERT INTO user_sessions (
+			session_id,
+			user_id,
+			created_at,
+			client_ip,
+			user_agent,
+			last_seen_at,
+			expires_at
+		)
+		VALUES ($1, $2, now(), $3, $4, now(), now() + make_interval(secs => $5))
+		"#,
+		session_id,
+		user_id,
+		client_ip,
+		user_agent,
+		IDLE_TIMEOUT_SECONDS
+	)
+	.execute(pool)
+	.await
+	.context("Failed to register user session")?;
+	Ok(session_id)
+}
+
+// An active session is seen, which pushes its expiry back.
+#[tracing::instrument(name = "Check user session", skip(pool))]
+pub async fn is_session_active(
+	session_id: Uuid,
+	user_id: Uuid,
+	pool: &PgPool
+) -> Result<bool, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		WITH active AS (
+			SELECT session_id, last_seen_at
+			FROM user_sessions
+			WHERE
+				session_id = $1 AND
+				user_id = $2 AND
+				revoked_at IS NULL AND
+				expires_at > now()
+		), seen AS (
+			UPDATE user_sessions
+			SET last_seen_at = now(), expires_at = now() + make_interval(secs => $3)
+			FROM active
+			WHERE
+				user_sessions.session_id = active.session_id AND
+				active.last_seen_at < now() - make_interval(secs => $4)
+		)
+		SELECT session_id FROM active
+		"#,
+		session_id,
+		user_id,
+		IDLE_TIMEOUT_SECONDS,
+		LAST_SEEN_SLACK_SECONDS
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to check user session")?;
+	Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Revoke user session", skip(pool))]
+pub async fn revoke_session(
+	session_id: Uuid,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	sqlx::query!(
+		r#"
+		UPDATE user_sessions
+		SET revoked_at = now()
+		WHERE session_id = $1 AND revoked_at IS NULL
+		"#,
+		session_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to revoke user session")?;
+	Ok(())
+}
+
+#[tracing::instrument(name = "List active user sessions", skip(pool))]
+pub async fn list_active_sessions(
+	user_id: Uuid,
+	pool: &PgPool
+) -> Result<Vec<UserSession>, anyhow::Error> {
+	let sessions = sqlx::query_as!(
+		UserSession,
+		r#"
+		SELECT session_id, created_at, last_seen_at, client_ip, user_agent
+		FROM user_sessions
+		WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+		ORDER BY created_at DESC
+		"#,
+		user_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to list user sessions")?;
+	Ok(sessions)
+}
+
+// Unlike `revoke_session`, checks that the session belongs to the user.
+#[tracing::instrument(name = "Revoke a session of the user", skip(pool))]
+pub async fn revoke_user_session(
+	user_id: Uuid,
+	session_id: Uuid,
+	pool: &PgPool
+) -> Result<bool, anyhow::Error> {
+	let n_updated_rows = sqlx::query!(
+		r#"
+		UPDATE user_sessions
+		SET revoked_at = now()
+		WHERE
+			session_id = $1 AND
+			user_id = $2 AND
+			revoked_at IS NULL AND
+			expires_at > now()
+		"#,
+		session_id,
+		user_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to revoke user session")?
+	.rows_affected();
+	Ok(n_updated_rows > 0)
+}
+
+#[tracing::instrument(name = "Revoke other user sessions", skip(pool))]
+pub async fn revoke_other_sessions(
+	user_id: Uuid,
+	current_session_id: Uuid,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	sqlx::query!(
+		r#"
+		UPDATE user_sessions
+		SET revoked_at = now()
+		WHERE
+			user_id = $1 AND
+			session_id <> $2 AND
+			revoked_at IS NULL
+		"#,
+		user_id,
+		current_session_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to revoke other user sessions")?;
+	Ok(())
+}
+
+#[tracing::instrument(name = "Revoke all user sessions", skip(executor))]
+pub async fn revoke_all_sessions<'c, E>(
+	user_id: Uuid,
+	executor: E
+) -> Result<(), anyhow::Error>
+where
+	E: PgExecutor<'c>,
+{
+	sqlx::query!(
+		r#"
+		UPDATE user_sessions
+		SET revoked_at = now()
+		WHERE user_id = $1 AND revoked_at IS NULL
+		"#,
+		user_id
+	)
+	.execute(executor)
+	.await
+	.context("Failed to revoke user sessions")?;
+	Ok(())
+}
+
+// Revoked sessions go once they would have expired anyway.
+// Returns the number of sessions deleted.
+#[tracing::instrument(skip_all)]
+pub async fn purge_user_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+	let deleted = sqlx::query!("DELETE FROM user_sessions WHERE expires_at <= now()")
+		.execute(pool)
+		.await
+		.context("Failed to delete the expired user sessions")?
+		.rows_affected();
+	Ok(deleted)
+}
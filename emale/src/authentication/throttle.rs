@@ -0,0 +1,224 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::{LoginThrottleSettings, PasswordResetThrottleSettings};
+use crate::counters::CounterStore;
+
+// How long a lockout is remembered when computing the next
+// (escalated) lockout duration.
+const LOCKOUT_MEMORY_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug)]
+enum ThrottleScope {
+	Username,
+	ClientIp,
+}
+
+impl ThrottleScope {
+	fn as_str(&self) -> &'static str {
+		match self {
+			ThrottleScope::Username => "username",
+			ThrottleScope::ClientIp => "ip",
+		}
+	}
+}
+
+// Sliding-window counters of failed logins, one per username and one
+// per client IP.
+#[derive(Clone)]
+pub struct LoginThrottle {
+	counters: CounterStore,
+	settings: LoginThrottleSettings,
+}
+
+impl LoginThrottle {
+	pub fn new(counters: CounterStore, settings: LoginThrottleSettings) -> Self {
+		Self { counters, settings }
+	}
+
+	fn key(&self, kind: &str, scope: ThrottleScope, subject: &str) -> String {
+		format!("{}:{}:{}:{}", self.settings.key_prefix, kind, scope.as_str(), subject)
+	}
+
+	fn max_attempts(&self, scope: ThrottleScope) -> u64 {
+		match scope {
+			ThrottleScope::Username => self.settings.max_attempts_per_username,
+			ThrottleScope::ClientIp => self.settings.max_attempts_per_ip,
+		}
+	}
+
+	fn lockout_seconds(&self, n_lockouts: u32) -> u64 {
+		let factor = 2u64.saturating_pow(n_lockouts.saturating_sub(1));
+		self.settings
+			.base_lockout_seconds
+			.saturating_mul(factor)
+			.min(self.settings.max_lockout_seconds)
+	}
+
+	#[tracing::instrument(name = "Check login lockout", skip(self))]
+	pub async fn is_locked_out(
+		&self,
+		username: &str,
+		client_ip: &str
+	) -> Result<bool, anyhow::Error> {
+		self.counters
+			.exists(&[
+				self.key("locked", ThrottleScope::Username, username),
+				self.key("locked", ThrottleScope::ClientIp, client_ip),
+			])
+			.await
+			.context("Failed to check login lockout")
+	}
+
+	#[tracing::instrument(name = "Record failed login", skip(self, pool))]
+	pub async fn record_failure(
+		&self,
+		username: &str,
+		client_ip: &str,
+		pool: &PgPool
+	) -> Result<(), anyhow::Error> {
+		for (scope, subject) in [
+			(ThrottleScope::Username, username),
+			(ThrottleScope::ClientIp, client_ip),
+		] {
+			let n_failures = self.add_failure(scope, subject).await?;
+			if n_failures >= self.max_attempts(scope) {
+				let lockout_seconds = self.lock_out(scope, subject).await?;
+				tracing::warn!(
+					scope = scope.as_str(),
+					username,
+					client_ip,
+					lockout_seconds,
+					"Login locked out after too many failed attempts"
+				);
+				record_lockout(pool, scope, username, client_ip, lockout_seconds).await?;
+			}
+		}
+		Ok(())
+	}
+
+	// Only the counters of the username: the failures of the IP are left
+	// to expire, or logging into any account between guesses would lift
+	// the per-IP limit.
+	#[tracing::instrument(name = "Reset login failures", skip(self))]
+	pub async fn reset(&self, username: &str) -> Result<(), anyhow::Error> {
+		self.counters
+			.delete(&[
+				self.key("failures", ThrottleScope::Username, username),
+				self.key("lockouts", ThrottleScope::Username, username),
+			])
+			.await
+			.context("Failed to reset login failures")?;
+		Ok(())
+	}
+
+	// Failures older than the window are not counted.
+	async fn add_failure(
+		&self,
+		scope: ThrottleScope,
+		subject: &str
+	) -> Result<u64, anyhow::Error> {
+		self.counters
+			.hit(&self.key("failures", scope, subject), self.settings.window_seconds)
+			.await
+			.context("Failed to record login failure")
+	}
+
+	// Each lockout within `LOCKOUT_MEMORY_SECONDS` doubles the duration
+	// of the next one, up to `max_lockout_seconds`.
+	async fn lock_out(
+		&self,
+		scope: ThrottleScope,
+		subject: &str
+	) -> Result<u64, anyhow::Error> {
+		let n_lockouts = self
+			.counters
+			.incr(&self.key("lockouts", scope, subject), LOCKOUT_MEMORY_SECONDS)
+			.await
+			.context("Failed to count lockouts")?;
+		let n_lockouts = u32::try_from(n_lockouts).unwrap_or(u32::MAX);
+		let lockout_seconds = self.lockout_seconds(n_lockouts);
+
+		// the window starts over once the lockout expires
+		self.counters
+			.set(&self.key("locked", scope, subject), n_lockouts.into(), lockout_seconds)
+			.await
+			.context("Failed to lock out login")?;
+		self.counters
+			.delete(&[self.key("failures", scope, subject)])
+			.await
+			.context("Failed to lock out login")?;
+		Ok(lockout_seconds)
+	}
+}
+
+// Sliding-window counters of password reset requests, one per address
+// and one per client IP, so that one client can neither flood an inbox
+// nor send reset emails to many addresses.
+#[derive(Clone)]
+pub struct PasswordResetThrottle {
+	counters: CounterStore,
+	settings: PasswordResetThrottleSettings,
+}
+
+impl PasswordResetThrottle {
+	pub fn new(counters: CounterStore, settings: PasswordResetThrottleSettings) -> Self {
+		Self { counters, settings }
+	}
+
+	// Every request counts, whether or not the address belongs to an
+	// account, so that the limits do not give that away.
+	#[tracing::instrument(name = "Record password reset request", skip(self, email))]
+	pub async fn allow_request(&self, email: &str, client_ip: &str) -> Result<bool, anyhow::Error> {
+		let prefix = &self.settings.key_prefix;
+		let n_for_address = self
+			.counters
+			.hit(
+				&format!("{}:email:{}", prefix, email.to_lowercase()),
+				self.settings.window_seconds
+			)
+			.await
+			.context("Failed to count password reset requests")?;
+		let n_for_ip = self
+			.counters
+			.hit(&format!("{}:ip:{}", prefix, client_ip), self.settings.window_seconds)
+			.await
+			.context("Failed to count password reset requests")?;
+		Ok(n_for_address <= self.settings.max_requests_per_address
+			&& n_for_ip <= self.settings.max_requests_per_ip)
+	}
+}
+
+#[tracing::instrument(name = "Save lockout auth event", skip(pool))]
+async fn record_lockout(
+	pool: &PgPool,
+	scope: ThrottleScope,
+	username: &str,
+	client_ip: &str,
+	lockout_seconds: u64
+) -> Result<(), anyhow::Error> {
+	sqlx::query!(
+		r#"
+		INSERT INTO auth_events (
+			auth_event_id,
+			event_type,
+			scope,
+			username,
+			client_ip,
+			lockout_seconds,
+			created_at
+		)
+		VALUES ($1, 'lockout', $2, $3, $4, $5, now())
+		"#,
+		Uuid::new_v4(),
+		scope.as_str(),
+		username,
+		client_ip,
+		lockout_seconds as i64
+	)
+	.execute(pool)
+	.await
+	.context("Failed to store lockout auth event")?;
+	Ok(())
+}
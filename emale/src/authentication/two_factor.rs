@@ -0,0 +1,481 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AuthError;
+
+// TOTP secrets are encrypted at rest with AES-256-GCM, under a key derived
+// from `application.totp_encryption_key`. Unlike the recovery codes they
+// cannot be hashed, the server has to compute the codes, but a copy of the
+// database alone must not be enough to generate them. Changing the key
+// makes the stored secrets unreadable.
+
+const TOTP_ISSUER: &str = "emale";
+const TOTP_STEP_SECONDS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+const NONCE_LENGTH: usize = 12;
+
+const SECRET_ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+pub struct TwoFactorState {
+	pub secret: Option<Secret<String>>,
+	pub enabled: bool,
+}
+
+// 160 bits, as recommended by RFC 4226 for HMAC-SHA1
+pub fn generate_totp_secret() -> Secret<String> {
+	let mut bytes = [0u8; 20];
+	thread_rng().fill_bytes(&mut bytes);
+	Secret::new(base32::encode(SECRET_ALPHABET, &bytes))
+}
+
+pub fn otpauth_uri(username: &str, secret: &Secret<String>) -> String {
+	format!(
+		"otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_STEP_SECONDS}",
+		issuer = TOTP_ISSUER,
+		username = urlencoding::encode(username),
+		secret = secret.expose_secret(),
+	)
+}
+
+pub fn otpauth_qr_svg(uri: &str) -> Result<String, anyhow::Error> {
+	let code = qrcode::QrCode::new(uri.as_bytes())
+		.context("Failed to encode otpauth URI as QR code")?;
+	Ok(code
+		.render::<qrcode::render::svg::Color>()
+		.min_dimensions(200, 200)
+		.build())
+}
+
+fn decode_secret(secret: &Secret<String>) -> Result<Vec<u8>, anyhow::Error> {
+	base32::decode(SECRET_ALPHABET, secret.expose_secret())
+		.ok_or_else(|| anyhow::anyhow!("TOTP secret is not valid base32"))
+}
+
+fn cipher(key: &Secret<String>) -> Aes256Gcm {
+	Aes256Gcm::new(&Sha256::digest(key.expose_secret().as_bytes()))
+}
+
+// Stored as the nonce followed by the ciphertext. The user id is
+// authenticated along, so a secret copied to another row is rejected.
+fn encrypt_secret(
+	key: &Secret<String>,
+	user_id: Uuid,
+	secret: &Secret<String>
+) -> Result<Vec<u8>, anyhow::Error> {
+	let mut nonce = [0u8; NONCE_LENGTH];
+	thread_rng().fill_bytes(&mut nonce);
+	let ciphertext = cipher(key)
+		.encrypt(
+			Nonce::from_slice(&nonce),
+			Payload {
+				msg: secret.expose_secret().as_bytes(),
+				aad: user_id.as_bytes(),
+			}
+		)
+		.map_err(|_| anyhow::anyhow!("Failed to encrypt TOTP secret"))?;
+	Ok([&nonce[..], &ciphertext].concat())
+}
+
+fn decrypt_secret(
+	key: &Secret<String>,
+	user_id: Uuid,
+	stored: &[u8]
+) -> Result<Secret<String>, anyhow::Error> {
+	if stored.len() < NONCE_LENGTH {
+		anyhow::bail!("Stored TOTP secret is too short");
+	}
+	let (nonce, ciphertext) = stored.split_at(NONCE_LENGTH);
+	let plaintext = cipher(key)
+		.decrypt(
+			Nonce::from_slice(nonce),
+			Payload {
+				msg: ciphertext,
+				aad: user_id.as_bytes(),
+			}
+		)
+		.map_err(|_| anyhow::anyhow!("Failed to decrypt TOTP secret"))?;
+	String::from_utf8(plaintext)
+		.map(Secret::new)
+		.context("Decrypted TOTP secret is not valid UTF-8")
+}
+
+// HOTP as defined in RFC 4226, section 5.3
+fn hotp(key: &[u8], counter: u64) -> u32 {
+	let mut mac = Hmac::<Sha1>::new_from_slice(key)
+		.expect("HMAC can take key of any size");
+	mac.update(&counter.to_be_bytes());
+	let hash = mac.finalize().into_bytes();
+	let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+	let binary = u32::from_be_bytes([
+		hash[offset] & 0x7f,
+		hash[offset + 1],
+		hash[offset + 2],
+		hash[offset + 3],
+	]);
+	binary % 10u32.pow(TOTP_DIGITS)
+}
+
+pub fn totp_code(secret: &Secret<String>, unix_time: u64) -> Result<String, anyhow::Error> {
+	let key = decode_secret(secret)?;
+	Ok(format!("{:06}", hotp(&key, unix_time / TOTP_STEP_SECONDS)))
+}
+
+// Returns the time step matched by `code`, tolerating one step of clock
+// drift in either direction.
+pub fn verify_totp_code(
+	secret: &Secret<String>,
+	code: &str,
+	unix_time: u64
+) -> Result<Option<i64>, anyhow::Error> {
+	let key = decode_secret(secret)?;
+	if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+		return Ok(None);
+	}
+	let current_step = unix_time / TOTP_STEP_SECONDS;
+	for step in [current_step.saturating_sub(1), current_step, current_step + 1] {
+		if format!("{:06}", hotp(&key, step)) == code {
+			return Ok(Some(step as i64));
+		}
+	}
+	Ok(None)
+}
+
+fn generate_recovery_code() -> String {
+	let mut rng = thread_rng();
+	std::iter::repeat_with(|| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(RECOVERY_CODE_LENGTH)
+		.collect()
+}
+
+// Recovery codes are long random strings, a fast hash is enough here.
+fn hash_recovery_code(code: &str) -> String {
+	hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+#[tracing::instrument(name = "Get two factor state", skip(key, pool))]
+pub async fn get_two_factor_state(
+	user_id: Uuid,
+	key: &Secret<String>,
+	pool: &PgPool
+) -> Result<TwoFactorState, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT totp_secret, totp_enabled
+		FROM users
+		WHERE user_id = $1
+		"#,
+		user_id
+	)
+	.fetch_one(pool)
+	.await
+	.context("Failed to perform a query to retrieve two factor state")?;
+	let secret = row
+		.totp_secret
+		.map(|stored| decrypt_secret(key, user_id, &stored))
+		.transpose()?;
+	Ok(TwoFactorState {
+		secret,
+		enabled: row.totp_enabled,
+	})
+}
+
+#[tracing::instrument(name = "Store pending TOTP secret", skip(secret, key, pool))]
+pub async fn store_pending_totp_secret(
+	user_id: Uuid,
+	secret: &Secret<String>,
+	key: &Secret<String>,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	let secret = encrypt_secret(key, user_id, secret)?;
+	sqlx::query!(
+		r#"
+		UPDATE users
+		SET totp_secret = $1, totp_last_step = NULL
+		WHERE user_id = $2 AND totp_enabled = false
+		"#,
+		secret,
+		user_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to store pending TOTP secret")?;
+	Ok(())
+}
+
+// Encrypts the secrets stored in plain text before they were encrypted at
+// rest, see the `encrypt_totp_secrets` migration.
+#[tracing::instrument(name = "Encrypt legacy TOTP secrets", skip(key, pool))]
+pub async fn encrypt_legacy_totp_secrets(
+	key: &Secret<String>,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	let rows = sqlx::query!(
+		r#"
+		SELECT user_id, legacy_totp_secret AS "legacy_totp_secret!"
+		FROM users
+		WHERE legacy_totp_secret IS NOT NULL
+		"#
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve legacy TOTP secrets")?;
+	for row in rows {
+		let secret = encrypt_secret(key, row.user_id, &Secret::new(row.legacy_totp_secret))?;
+		sqlx::query!(
+			r#"
+			UPDATE users
+			SET totp_secret = $1, legacy_totp_secret = NULL
+			WHERE user_id = $2
+			"#,
+			secret,
+			row.user_id
+		)
+		.execute(pool)
+		.await
+		.context("Failed to encrypt legacy TOTP secret")?;
+	}
+	Ok(())
+}
+
+// Enables two factor authentication and returns a fresh set of
+// recovery codes. The plain codes are never stored.
+#[tracing::instrument(name = "Enable two factor", skip(pool))]
+pub async fn enable_two_factor(
+	user_id: Uuid,
+	pool: &PgPool
+) -> Result<Vec<String>, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	sqlx::query!(
+		r#"
+		UPDATE users
+		SET totp_enabled = true
+		WHERE user_id = $1 AND totp_secret IS NOT NULL
+		"#,
+		user_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to enable two factor authentication")?;
+	sqlx::query!(
+		r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+		user_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to delete old recovery codes")?;
+
+	let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+		.map(|_| generate_recovery_code())
+		.collect();
+	for code in &codes {
+		sqlx::query!(
+			r#"
+			INSERT INTO recovery_codes (user_id, code_hash)
+			VALUES ($1, $2)
+			"#,
+			user_id,
+			hash_recovery_code(code)
+		)
+		.execute(&mut transaction)
+		.await
+		.context("Failed to store recovery code")?;
+	}
+	transaction.commit().await?;
+	Ok(codes)
+}
+
+#[tracing::instrument(name = "Disable two factor", skip(pool))]
+pub async fn disable_two_factor(
+	user_id: Uuid,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	sqlx::query!(
+		r#"
+		UPDATE users
+		SET totp_enabled = false, totp_secret = NULL, totp_last_step = NULL
+		WHERE user_id = $1
+		"#,
+		user_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to disable two factor authentication")?;
+	sqlx::query!(
+		r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+		user_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to delete recovery codes")?;
+	transaction.commit().await?;
+	Ok(())
+}
+
+// A TOTP code can only be used once: the step is only recorded if it is
+// newer than the last one used.
+async fn record_totp_step(
+	user_id: Uuid,
+	step: i64,
+	pool: &PgPool
+) -> Result<bool, anyhow::Error> {
+	let n_updated_rows = sqlx::query!(
+		r#"
+		UPDATE users
+		SET totp_last_step = $1
+		WHERE
+			user_id = $2 AND
+			(totp_last_step IS NULL OR totp_last_step < $1)
+		"#,
+		step,
+		user_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to record TOTP step")?
+	.rows_affected();
+	Ok(n_updated_rows > 0)
+}
+
+async fn use_recovery_code(
+	user_id: Uuid,
+	code: &str,
+	pool: &PgPool
+) -> Result<bool, anyhow::Error> {
+	let n_updated_rows = sqlx::query!(
+		r#"
+		UPDATE recovery_codes
+		SET used_at = now()
+		WHERE
+			user_id = $1 AND
+			code_hash = $2 AND
+			used_at IS NULL
+		"#,
+		user_id,
+		hash_recovery_code(code)
+	)
+	.execute(pool)
+	.await
+	.context("Failed to use recovery code")?
+	.rows_affected();
+	Ok(n_updated_rows > 0)
+}
+
+// Accepts either a TOTP code from the authenticator app
+// or one of the unused recovery codes.
+#[tracing::instrument(name = "Validate second factor", skip(code, key, pool))]
+pub async fn validate_second_factor(
+	user_id: Uuid,
+	code: &str,
+	key: &Secret<String>,
+	pool: &PgPool
+) -> Result<(), AuthError> {
+	let code = code.trim();
+	let state = get_two_factor_state(user_id, key, pool).await?;
+	let secret = match (state.enabled, state.secret) {
+		(true, Some(secret)) => secret,
+		_ => {
+			return Err(AuthError::UnexpectedError(anyhow::anyhow!(
+				"Two factor authentication is not enabled for this user"
+			)))
+		}
+	};
+
+	let now = chrono::Utc::now().timestamp() as u64;
+	if let Some(step) = verify_totp_code(&secret, code, now)? {
+		return if record_totp_step(user_id, step, pool).await? {
+			Ok(())
+		} else {
+			Err(AuthError::InvalidCredential(anyhow::anyhow!("TOTP code was already used")))
+		};
+	}
+	if use_recovery_code(user_id, code, pool).await? {
+		return Ok(());
+	}
+	Err(AuthError::InvalidCredential(anyhow::anyhow!("Invalid second factor code")))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{decrypt_secret, encrypt_secret, hotp, otpauth_uri, totp_code, verify_totp_code};
+	use claim::{assert_err, assert_none, assert_some_eq};
+	use secrecy::{ExposeSecret, Secret};
+	use uuid::Uuid;
+
+	// RFC 6238 appendix B uses the ASCII string "12345678901234567890"
+	fn rfc_secret() -> Secret<String> {
+		Secret::new(base32::encode(
+			super::SECRET_ALPHABET,
+			b"12345678901234567890"
+		))
+	}
+
+	#[test]
+	fn hotp_matches_rfc_4226_test_vectors() {
+		let expected = [755224, 287082, 359152, 969429, 338314];
+		for (counter, code) in expected.iter().enumerate() {
+			assert_eq!(hotp(b"12345678901234567890", counter as u64), *code);
+		}
+	}
+
+	#[test]
+	fn totp_matches_rfc_6238_test_vectors() {
+		let secret = rfc_secret();
+		assert_eq!(totp_code(&secret, 59).unwrap(), "287082");
+		assert_eq!(totp_code(&secret, 1111111109).unwrap(), "081804");
+		assert_eq!(totp_code(&secret, 1234567890).unwrap(), "005924");
+	}
+
+	#[test]
+	fn code_from_adjacent_step_is_accepted() {
+		let secret = rfc_secret();
+		let code = totp_code(&secret, 1111111109).unwrap();
+		assert_some_eq!(verify_totp_code(&secret, &code, 1111111109 + 30).unwrap(), 37037036);
+	}
+
+	#[test]
+	fn code_from_distant_step_is_rejected() {
+		let secret = rfc_secret();
+		let code = totp_code(&secret, 1111111109).unwrap();
+		assert_none!(verify_totp_code(&secret, &code, 1111111109 + 90).unwrap());
+	}
+
+	#[test]
+	fn malformed_code_is_rejected() {
+		let secret = rfc_secret();
+		assert_none!(verify_totp_code(&secret, "12345", 59).unwrap());
+		assert_none!(verify_totp_code(&secret, "28708a", 59).unwrap());
+	}
+
+	#[test]
+	fn encrypted_secret_is_decrypted_for_its_user_only() {
+		let key = Secret::new("a-long-random-key".to_string());
+		let user_id = Uuid::new_v4();
+		let stored = encrypt_secret(&key, user_id, &rfc_secret()).unwrap();
+		assert!(!String::from_utf8_lossy(&stored).contains(rfc_secret().expose_secret()));
+
+		let secret = decrypt_secret(&key, user_id, &stored).unwrap();
+		assert_eq!(secret.expose_secret(), rfc_secret().expose_secret());
+		assert_err!(decrypt_secret(&key, Uuid::new_v4(), &stored));
+		assert_err!(decrypt_secret(&Secret::new("another-key".to_string()), user_id, &stored));
+	}
+
+	#[test]
+	fn otpauth_uri_contains_issuer_and_secret() {
+		let uri = otpauth_uri("bruh", &rfc_secret());
+		assert!(uri.starts_with("otpauth://totp/emale:bruh?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+		assert!(uri.contains("issuer=emale"));
+	}
+}
@@ -0,0 +1,128 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::telemetry::spawn_blocking_with_tracing;
+use super::password::compute_password_hash;
+use super::{change_password, revoke_all_sessions, validate_new_password};
+
+// Admin accounts are managed from the command line, these are the
+// functions behind `emale user ...`.
+
+#[tracing::instrument(name = "Get user id", skip(pool))]
+pub async fn get_user_id(
+	username: &str,
+	pool: &PgPool
+) -> Result<Option<Uuid>, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT user_id
+		FROM users
+		WHERE username = $1
+		"#,
+		username
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to perform a query to retrieve the user id")?;
+	Ok(row.map(|r| r.user_id))
+}
+
+#[tracing::instrument(name = "Create user", skip(password, pool))]
+pub async fn create_user(
+	username: &str,
+	email: Option<&str>,
+	password: Secret<String>,
+	// the user is made a member of it
+	publication_id: &str,
+	pool: &PgPool
+) -> Result<Uuid, anyhow::Error> {
+	validate_new_password(&password, &password)?;
+	let email = email
+		.map(|e| SubscriberEmail::parse(e.to_owned()).map(|e| e.to_string()))
+		.transpose()
+		.map_err(|e| anyhow::anyhow!(e))?;
+	if get_user_id(username, pool).await?.is_some() {
+		anyhow::bail!("The username {} is already taken", username);
+	}
+	let password_hash = spawn_blocking_with_tracing(
+		move || compute_password_hash(password)
+	)
+	.await?
+	.context("Failed to hash password")?;
+	let user_id = Uuid::new_v4();
+	let mut transaction = pool
+		.begin()
+		.await
+		.context("Failed to acquire a Postgres connection from the pool")?;
+	sqlx::query!(
+		r#"
+		INSERT INTO users (user_id, username, password_hash, email)
+		VALUES ($1, $2, $3, $4)
+		"#,
+		user_id,
+		username,
+		password_hash.expose_secret(),
+		email
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to insert the new user in the database")?;
+	sqlx::query!(
+		r#"
+		INSERT INTO publication_members (publication_id, user_id)
+		VALUES ($1, $2)
+		"#,
+		publication_id,
+		user_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to add the new user to the publication")?;
+	transaction
+		.commit()
+		.await
+		.context("Failed to commit SQL transaction to store the new user")?;
+	Ok(user_id)
+}
+
+#[tracing::instrument(name = "Reset user password", skip(password, pool))]
+pub async fn reset_user_password(
+	username: &str,
+	password: Secret<String>,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	validate_new_password(&password, &password)?;
+	let user_id = get_user_id(username, pool)
+		.await?
+		.ok_or_else(|| anyhow::anyhow!("There is no user named {}", username))?;
+	change_password(user_id, password, pool).await?;
+	// whoever knew the old password is logged out
+	revoke_all_sessions(user_id, pool).await
+}
+
+// The user can no longer log in or reset their password, and
+// their sessions are revoked.
+#[tracing::instrument(name = "Disable user", skip(pool))]
+pub async fn disable_user(
+	username: &str,
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	let user_id = sqlx::query!(
+		r#"
+		UPDATE users
+		SET disabled_at = now()
+		WHERE username = $1 AND disabled_at IS NULL
+		RETURNING user_id
+		"#,
+		username
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to disable the user")?
+	.map(|r| r.user_id)
+	.ok_or_else(|| anyhow::anyhow!("There is no enabled user named {}", username))?;
+	revoke_all_sessions(user_id, pool).await
+}
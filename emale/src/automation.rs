@@ -0,0 +1,447 @@
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::publications::sender_email_client;
+
+// Automation sequences are series of emails sent after a subscriber
+// confirms, e.g. a welcome mail right away, tips after 3 days and a survey
+// after 14. Subscribers are enrolled in the active sequences of their
+// publication when they confirm; the delay of each step counts from that
+// moment.
+//
+// `schedule_due_steps` runs in the delivery worker: it moves each
+// subscriber to their next step once it falls due and queues the email in
+// `automation_delivery_queue`, drained by `try_execute_step_task`.
+// Subscribers that are no longer confirmed are skipped, erased ones are
+// gone along with their progress. A subscriber who went through every step
+// is done with the sequence, steps added later are only sent to the
+// subscribers still in it.
+
+pub struct SequenceStep {
+	pub step_id: Uuid,
+	pub position: i32,
+	pub delay_days: i32,
+	pub subject: String,
+	// number of subscribers who were sent this step
+	pub reached: i64,
+}
+
+pub struct Sequence {
+	pub sequence_id: Uuid,
+	pub name: String,
+	pub is_active: bool,
+	pub enrolled: i64,
+	pub completed: i64,
+	pub steps: Vec<SequenceStep>,
+}
+
+// Oldest first, with their steps in order, for the admin pages.
+#[tracing::instrument(name = "Get automation sequences", skip(pool))]
+pub async fn get_sequences(
+	pool: &PgPool,
+	publication_id: &str
+) -> Result<Vec<Sequence>, anyhow::Error> {
+	let mut sequences: Vec<Sequence> = sqlx::query!(
+		r#"
+		SELECT
+			sq.sequence_id,
+			sq.name,
+			sq.is_active,
+			COUNT(p.subscriber_id) AS "enrolled!",
+			COUNT(p.completed_at) AS "completed!"
+		FROM automation_sequences sq
+		LEFT JOIN subscriber_sequence_progress p USING (sequence_id)
+		WHERE sq.publication_id = $1
+		GROUP BY sq.sequence_id
+		ORDER BY sq.created_at
+		"#,
+		publication_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the automation sequences")?
+	.into_iter()
+	.map(|r| Sequence {
+		sequence_id: r.sequence_id,
+		name: r.name,
+		is_active: r.is_active,
+		enrolled: r.enrolled,
+		completed: r.completed,
+		steps: Vec::new(),
+	})
+	.collect();
+	let steps = sqlx::query!(
+		r#"
+		SELECT
+			st.sequence_id,
+			st.step_id,
+			st.position,
+			st.delay_days,
+			st.subject,
+			COUNT(p.subscriber_id) AS "reached!"
+		FROM automation_steps st
+		LEFT JOIN subscriber_sequence_progress p ON
+			p.sequence_id = st.sequence_id AND
+			p.reached_position >= st.position
+		WHERE st.sequence_id IN (
+			SELECT sequence_id FROM automation_sequences WHERE publication_id = $1
+		)
+		GROUP BY st.step_id
+		ORDER BY st.position
+		"#,
+		publication_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the automation steps")?;
+	for step in steps {
+		if let Some(sequence) = sequences
+			.iter_mut()
+			.find(|s| s.sequence_id == step.sequence_id)
+		{
+			sequence.steps.push(SequenceStep {
+				step_id: step.step_id,
+				position: step.position,
+				delay_days: step.delay_days,
+				subject: step.subject,
+				reached: step.reached,
+			});
+		}
+	}
+	Ok(sequences)
+}
+
+#[tracing::instrument(name = "Create automation sequence", skip(pool))]
+pub async fn create_sequence(
+	pool: &PgPool,
+	publication_id: &str,
+	name: &str
+) -> Result<Uuid, anyhow::Error> {
+	let sequence_id = Uuid::new_v4();
+	sqlx::query!(
+		r#"
+		INSERT INTO automation_sequences (sequence_id, name, is_active, created_at, publication_id)
+		VALUES ($1, $2, true, now(), $3)
+		"#,
+		sequence_id,
+		name,
+		publication_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to create the automation sequence")?;
+	Ok(sequence_id)
+}
+
+pub struct NewStep<'a> {
+	pub delay_days: i32,
+	pub subject: &'a str,
+	pub text_content: &'a str,
+	pub html_content: &'a str,
+}
+
+// Appends the step to the sequence. Returns false if the publication has
+// no such sequence.
+#[tracing::instrument(name = "Add automation step", skip(pool, step))]
+pub async fn add_step(
+	pool: &PgPool,
+	publication_id: &str,
+	sequence_id: Uuid,
+	step: NewStep<'_>
+) -> Result<bool, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	// serializes concurrent additions to the same sequence
+	let exists = sqlx::query!(
+		r#"
+		SELECT sequence_id FROM automation_sequences
+		WHERE sequence_id = $1 AND publication_id = $2
+		FOR UPDATE
+		"#,
+		sequence_id,
+		publication_id
+	)
+	.fetch_optional(&mut transaction)
+	.await
+	.context("Failed to lock the automation sequence")?
+	.is_some();
+	if !exists {
+		return Ok(false);
+	}
+	sqlx::query!(
+		r#"
+		INSERT INTO automation_steps (
+			step_id,
+			sequence_id,
+			position,
+			delay_days,
+			subject,
+			text_content,
+			html_content
+		)
+		SELECT $1, $2, COALESCE(MAX(position), 0) + 1, $3, $4, $5, $6
+		FROM automation_steps
+		WHERE sequence_id = $2
+		"#,
+		Uuid::new_v4(),
+		sequence_id,
+		step.delay_days,
+		step.subject,
+		step.text_content,
+		step.html_content
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to add the automation step")?;
+	transaction.commit().await?;
+	Ok(true)
+}
+
+// Inactive sequences enroll nobody and send nothing, subscribers already
+// in them pick up where they were when the sequence is reactivated.
+// Returns false if the publication has no such sequence.
+#[tracing::instrument(name = "Set automation sequence activity", skip(pool))]
+pub async fn set_sequence_active(
+	pool: &PgPool,
+	publication_id: &str,
+	sequence_id: Uuid,
+	is_active: bool
+) -> Result<bool, anyhow::Error> {
+	let updated = sqlx::query!(
+		r#"
+		UPDATE automation_sequences SET is_active = $2
+		WHERE sequence_id = $1 AND publication_id = $3
+		"#,
+		sequence_id,
+		is_active,
+		publication_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to update the automation sequence")?
+	.rows_affected();
+	Ok(updated == 1)
+}
+
+// Called when the subscriber confirms. Confirming twice does not restart
+// the sequences.
+#[tracing::instrument(name = "Enroll subscriber in automation sequences", skip(executor))]
+pub async fn enroll_subscriber<'c, E>(executor: E, subscriber_id: Uuid) -> Result<(), sqlx::Error>
+where
+	E: PgExecutor<'c>,
+{
+	sqlx::query!(
+		r#"
+		INSERT INTO subscriber_sequence_progress (subscriber_id, sequence_id, started_at)
+		SELECT $1, sequence_id, now()
+		FROM automation_sequences
+		WHERE
+			is_active AND
+			publication_id = (SELECT publication_id FROM subscriber WHERE id = $1)
+		ON CONFLICT DO NOTHING
+		"#,
+		subscriber_id
+	)
+	.execute(executor)
+	.await?;
+	Ok(())
+}
+
+#[derive(Serialize, Debug)]
+pub struct SequenceProgress {
+	pub sequence: String,
+	pub started_at: String,
+	pub reached_step: Option<i32>,
+	pub completed_at: Option<String>,
+}
+
+// For the subscriber data export.
+#[tracing::instrument(name = "Get subscriber sequence progress", skip(pool))]
+pub async fn get_subscriber_progress(
+	pool: &PgPool,
+	subscriber_id: Uuid
+) -> Result<Vec<SequenceProgress>, anyhow::Error> {
+	let progress = sqlx::query!(
+		r#"
+		SELECT sq.name, p.started_at, p.reached_position, p.completed_at
+		FROM subscriber_sequence_progress p
+		JOIN automation_sequences sq USING (sequence_id)
+		WHERE p.subscriber_id = $1
+		ORDER BY p.started_at, sq.name
+		"#,
+		subscriber_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the sequence progress")?
+	.into_iter()
+	.map(|r| SequenceProgress {
+		sequence: r.name,
+		started_at: r.started_at.to_rfc3339(),
+		reached_step: r.reached_position,
+		completed_at: r.completed_at.map(|at| at.to_rfc3339()),
+	})
+	.collect();
+	Ok(progress)
+}
+
+// Queues the next step of every subscriber whose step fell due, one step
+// per subscriber and sequence at a time, until nothing is due. Several
+// workers can run it at once: progress rows are locked while they are
+// moved forward. Returns the number of steps queued.
+#[tracing::instrument(name = "Schedule due automation steps", skip(pool))]
+pub async fn schedule_due_steps(pool: &PgPool) -> Result<u64, anyhow::Error> {
+	let mut scheduled = 0;
+	loop {
+		let moved = sqlx::query!(
+			r#"
+			WITH due AS (
+				SELECT
+					p.subscriber_id,
+					p.sequence_id,
+					st.step_id,
+					st.position,
+					NOT EXISTS (
+						SELECT 1 FROM automation_steps later
+						WHERE later.sequence_id = p.sequence_id AND later.position > st.position
+					) AS is_last
+				FROM subscriber_sequence_progress p
+				JOIN automation_sequences sq ON sq.sequence_id = p.sequence_id
+				JOIN subscriber s ON s.id = p.subscriber_id
+				CROSS JOIN LATERAL (
+					SELECT step_id, position, delay_days
+					FROM automation_steps
+					WHERE
+						sequence_id = p.sequence_id AND
+						position > COALESCE(p.reached_position, 0)
+					ORDER BY position
+					LIMIT 1
+				) st
+				WHERE
+					p.completed_at IS NULL AND
+					sq.is_active AND
+					s.status = 'confirmed' AND
+					p.started_at + make_interval(days => st.delay_days) <= now()
+				FOR UPDATE OF p SKIP LOCKED
+			),
+			queued AS (
+				INSERT INTO automation_delivery_queue (step_id, subscriber_id, enqueued_at)
+				SELECT step_id, subscriber_id, now() FROM due
+				ON CONFLICT DO NOTHING
+			)
+			UPDATE subscriber_sequence_progress p
+			SET
+				reached_position = due.position,
+				reached_at = now(),
+				completed_at = CASE WHEN due.is_last THEN now() END
+			FROM due
+			WHERE p.subscriber_id = due.subscriber_id AND p.sequence_id = due.sequence_id
+			"#
+		)
+		.execute(pool)
+		.await
+		.context("Failed to schedule the due automation steps")?
+		.rows_affected();
+		if moved == 0 {
+			return Ok(scheduled);
+		}
+		scheduled += moved;
+	}
+}
+
+struct StepEmail {
+	subject: String,
+	text_content: String,
+	html_content: String,
+	email: String,
+	status: String,
+	sender_email: Option<String>,
+}
+
+// Sends one queued step. The subscriber status is checked again, they may
+// have stopped being confirmed since the step was queued.
+#[tracing::instrument(
+	skip_all,
+	fields(step_id=tracing::field::Empty, subscriber_id=tracing::field::Empty),
+	err
+)]
+pub async fn try_execute_step_task(
+	pool: &PgPool,
+	email_client: &EmailClient
+) -> Result<ExecutionOutcome, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	let task = sqlx::query!(
+		r#"
+		SELECT step_id, subscriber_id
+		FROM automation_delivery_queue
+		ORDER BY enqueued_at
+		FOR UPDATE
+		SKIP LOCKED
+		LIMIT 1
+		"#
+	)
+	.fetch_optional(&mut transaction)
+	.await?;
+	let (step_id, subscriber_id) = match task {
+		Some(r) => (r.step_id, r.subscriber_id),
+		None => return Ok(ExecutionOutcome::EmptyQueue),
+	};
+	tracing::Span::current()
+		.record("step_id", &tracing::field::display(step_id))
+		.record("subscriber_id", &tracing::field::display(subscriber_id));
+	let step = sqlx::query_as!(
+		StepEmail,
+		r#"
+		SELECT st.subject, st.text_content, st.html_content, s.email, s.status, p.sender_email
+		FROM automation_steps st, subscriber s
+		JOIN publications p ON p.publication_id = s.publication_id
+		WHERE st.step_id = $1 AND s.id = $2
+		"#,
+		step_id,
+		subscriber_id
+	)
+	.fetch_one(&mut transaction)
+	.await?;
+	if step.status != "confirmed" {
+		tracing::info!("Skipping an automation step, the subscriber is no longer confirmed");
+	} else {
+		match SubscriberEmail::parse(step.email) {
+			Ok(email) => {
+				// sent from the address of the publication of the subscriber
+				let outcome = match sender_email_client(email_client, step.sender_email) {
+					Ok(email_client) => email_client
+						.send_email(&email, &step.subject, &step.html_content, &step.text_content)
+						.await
+						.map_err(anyhow::Error::from),
+					Err(e) => Err(e),
+				};
+				if let Err(e) = outcome {
+					tracing::error!(
+						error.cause_chain = ?e,
+						error.message = %e,
+						"Failed to send an automation step"
+					);
+				}
+			}
+			Err(e) => tracing::error!(
+				error.cause_chain = ?e,
+				error.message = %e,
+				"Skipping an automation step, the subscriber email is invalid"
+			),
+		}
+	}
+	sqlx::query!(
+		r#"DELETE FROM automation_delivery_queue WHERE step_id = $1 AND subscriber_id = $2"#,
+		step_id,
+		subscriber_id
+	)
+	.execute(&mut transaction)
+	.await?;
+	transaction.commit().await?;
+	Ok(ExecutionOutcome::TaskCompleted)
+}
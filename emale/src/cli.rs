@@ -0,0 +1,189 @@
+use std::io::BufRead;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use emale::authentication::{create_user, disable_user, get_user_id, reset_user_password};
+use emale::configuration::Settings;
+use emale::issue_delivery_worker::{queue_stats, requeue_issue};
+use emale::publications::{add_member, create_publication, NewPublication, DEFAULT_PUBLICATION_ID};
+use emale::startup::{get_connection_pool, MIGRATOR};
+
+#[derive(Parser)]
+#[command(name = "emale", about = "Newsletter delivery service")]
+pub struct Cli {
+	// Without a subcommand both the API and the delivery worker are run
+	#[command(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+	/// Serve the API
+	Serve,
+	/// Run the newsletter delivery worker
+	Worker,
+	/// Apply the pending database migrations
+	Migrate,
+	/// Manage admin users
+	User {
+		#[command(subcommand)]
+		command: UserCommand,
+	},
+	/// Inspect and refill the delivery queue
+	Queue {
+		#[command(subcommand)]
+		command: QueueCommand,
+	},
+	/// Manage the publications hosted by the deployment
+	Publication {
+		#[command(subcommand)]
+		command: PublicationCommand,
+	},
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+	/// Create a user, the password is read from stdin
+	Create {
+		username: String,
+		#[arg(long)]
+		email: Option<String>,
+		/// The publication the user is made a member of
+		#[arg(long, default_value = DEFAULT_PUBLICATION_ID)]
+		publication: String,
+	},
+	/// Set a new password, read from stdin, and revoke every session
+	ResetPassword {
+		username: String,
+	},
+	/// Prevent a user from logging in and revoke every session
+	Disable {
+		username: String,
+	},
+}
+
+#[derive(Subcommand)]
+pub enum QueueCommand {
+	/// Number of pending deliveries per issue
+	Stats,
+	/// Queue an issue again for every confirmed subscriber, or only one
+	Requeue {
+		newsletter_issue_id: Uuid,
+		#[arg(long)]
+		email: Option<String>,
+	},
+}
+
+#[derive(Subcommand)]
+pub enum PublicationCommand {
+	/// Create a publication, served to requests for its host
+	Create {
+		publication_id: String,
+		name: String,
+		#[arg(long)]
+		host: Option<String>,
+		/// Defaults to the application base URL
+		#[arg(long)]
+		base_url: Option<String>,
+		/// Defaults to the configured sender address
+		#[arg(long)]
+		sender_email: Option<String>,
+	},
+	/// Let a user manage the publication
+	AddMember {
+		publication_id: String,
+		username: String,
+	},
+}
+
+// Runs the subcommands that do their job and exit.
+pub async fn run_command(command: Command, config: Settings) -> Result<(), anyhow::Error> {
+	let pool = get_connection_pool(&config.database);
+	match command {
+		Command::Serve | Command::Worker => unreachable!("long-running commands are run by main"),
+		Command::Migrate => {
+			MIGRATOR
+				.run(&pool)
+				.await
+				.context("Failed to apply the migrations")?;
+			println!("Database is up to date");
+		}
+		Command::User { command } => match command {
+			UserCommand::Create { username, email, publication } => {
+				let password = read_password()?;
+				let user_id =
+					create_user(&username, email.as_deref(), password, &publication, &pool).await?;
+				println!("Created user {} ({})", username, user_id);
+			}
+			UserCommand::ResetPassword { username } => {
+				let password = read_password()?;
+				reset_user_password(&username, password, &pool).await?;
+				println!("Password of {} has been reset", username);
+			}
+			UserCommand::Disable { username } => {
+				disable_user(&username, &pool).await?;
+				println!("User {} has been disabled", username);
+			}
+		},
+		Command::Queue { command } => match command {
+			QueueCommand::Stats => {
+				let stats = queue_stats(&pool).await?;
+				if stats.is_empty() {
+					println!("The delivery queue is empty");
+				}
+				for issue in stats {
+					println!(
+						"{}\t{}\t{}",
+						issue.newsletter_issue_id, issue.pending_deliveries, issue.title
+					);
+				}
+			}
+			QueueCommand::Requeue { newsletter_issue_id, email } => {
+				let queued = requeue_issue(&pool, newsletter_issue_id, email.as_deref()).await?;
+				println!("Queued {} deliveries", queued);
+			}
+		},
+		Command::Publication { command } => match command {
+			PublicationCommand::Create { publication_id, name, host, base_url, sender_email } => {
+				create_publication(
+					&pool,
+					NewPublication {
+						publication_id: &publication_id,
+						name: &name,
+						host: host.as_deref(),
+						base_url: base_url.as_deref(),
+						sender_email: sender_email.as_deref(),
+					},
+				)
+				.await?;
+				println!("Created publication {}", publication_id);
+			}
+			PublicationCommand::AddMember { publication_id, username } => {
+				let user_id = get_user_id(&username, &pool)
+					.await?
+					.ok_or_else(|| anyhow::anyhow!("There is no user named {}", username))?;
+				if add_member(&pool, &publication_id, user_id).await? {
+					println!("{} is now a member of {}", username, publication_id);
+				} else {
+					println!("{} already is a member of {}", username, publication_id);
+				}
+			}
+		},
+	}
+	Ok(())
+}
+
+// Passwords are never taken as arguments, they would end up in the
+// shell history and in the process list.
+fn read_password() -> Result<Secret<String>, anyhow::Error> {
+	eprintln!("Password:");
+	let mut password = String::new();
+	std::io::stdin()
+		.lock()
+		.read_line(&mut password)
+		.context("Failed to read the password from stdin")?;
+	Ok(Secret::new(password.trim_end_matches(&['\r', '\n'][..]).to_owned()))
+}
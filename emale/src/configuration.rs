@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use secrecy::{Secret, ExposeSecret};
@@ -10,12 +12,249 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: AppSettings,
     pub email_client: EmailClientSettings,
-    pub redis_uri: Secret<String>
+    // Sessions, login throttle and subscribe counters are kept in Postgres
+    // when unset, `session_store` has to be `postgres` then
+    pub redis_uri: Option<Secret<String>>,
+    #[serde(default)]
+    pub session_store: SessionStoreKind,
+    #[serde(default)]
+    pub login_throttle: LoginThrottleSettings,
+    #[serde(default)]
+    pub password_reset_throttle: PasswordResetThrottleSettings,
+    #[serde(default)]
+    pub subscribe_protection: SubscribeProtectionSettings,
+    #[serde(default)]
+    pub email_validation: EmailValidationSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    #[serde(default)]
+    pub health: HealthSettings,
+    #[serde(default)]
+    pub shutdown: ShutdownSettings,
+}
+
+// Once shutdown has begun the API keeps serving, while reporting itself as
+// not ready, for `readiness_grace_seconds` so that load balancers stop
+// routing to it. In-flight requests and the current delivery task are then
+// given `deadline_seconds` to complete before they are dropped.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    pub readiness_grace_seconds: u64,
+    pub deadline_seconds: u64,
+}
+
+impl ShutdownSettings {
+    pub fn readiness_grace(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.readiness_grace_seconds)
+    }
+
+    pub fn deadline(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.deadline_seconds)
+    }
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            readiness_grace_seconds: 5,
+            deadline_seconds: 30,
+        }
+    }
+}
+
+// Where session state is kept, `postgres` uses the application database.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    #[default]
+    Redis,
+    Postgres,
+}
+
+// Bounds of the `/health/ready` checks: the worker is considered down
+// when no heartbeat has been written for `worker_heartbeat_max_age_seconds`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct HealthSettings {
+    pub worker_heartbeat_max_age_seconds: u64,
+    pub check_timeout_milliseconds: u64,
+}
+
+impl HealthSettings {
+    pub fn worker_heartbeat_max_age(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.worker_heartbeat_max_age_seconds)
+    }
+
+    pub fn check_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.check_timeout_milliseconds)
+    }
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        Self {
+            worker_heartbeat_max_age_seconds: 60,
+            check_timeout_milliseconds: 2000,
+        }
+    }
+}
+
+// Spans are only exported when an OTLP collector endpoint is set,
+// e.g. `http://localhost:4317`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "emale".into(),
+        }
+    }
+}
+
+// `/metrics` is only served if at least one of these is set. With a
+// `bind_address` it gets its own listener (e.g. on a private interface)
+// instead of being mounted on the public application; with a
+// `bearer_token` scrapers must send `Authorization: Bearer <token>`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MetricsSettings {
+    pub bind_address: Option<String>,
+    pub bearer_token: Option<Secret<String>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoginThrottleSettings {
+    pub key_prefix: String,
+    pub max_attempts_per_username: u64,
+    pub max_attempts_per_ip: u64,
+    pub window_seconds: u64,
+    pub base_lockout_seconds: u64,
+    pub max_lockout_seconds: u64,
+}
+
+impl Default for LoginThrottleSettings {
+    fn default() -> Self {
+        Self {
+            key_prefix: "login_throttle".into(),
+            max_attempts_per_username: 5,
+            max_attempts_per_ip: 20,
+            window_seconds: 15 * 60,
+            base_lockout_seconds: 60,
+            max_lockout_seconds: 60 * 60,
+        }
+    }
+}
+
+// Limits on `POST /password-reset`: requests per address and per client
+// IP within `window_seconds`. Requests beyond them send no email.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PasswordResetThrottleSettings {
+    pub key_prefix: String,
+    pub max_requests_per_address: u64,
+    pub max_requests_per_ip: u64,
+    pub window_seconds: u64,
+}
+
+impl Default for PasswordResetThrottleSettings {
+    fn default() -> Self {
+        Self {
+            key_prefix: "password_reset".into(),
+            max_requests_per_address: 3,
+            max_requests_per_ip: 10,
+            window_seconds: 60 * 60,
+        }
+    }
+}
+
+// Limits on `POST /subscribe`: attempts per client IP and confirmation
+// emails per recipient domain within `window_seconds`, and the time a
+// human needs at least to fill the form.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SubscribeProtectionSettings {
+    pub key_prefix: String,
+    pub max_attempts_per_ip: u64,
+    pub max_emails_per_domain: u64,
+    pub window_seconds: u64,
+    pub min_fill_seconds: u64,
+    pub max_form_age_seconds: u64,
+    pub challenge: ChallengeSettings,
+}
+
+impl Default for SubscribeProtectionSettings {
+    fn default() -> Self {
+        Self {
+            key_prefix: "subscribe".into(),
+            max_attempts_per_ip: 5,
+            max_emails_per_domain: 100,
+            window_seconds: 60 * 60,
+            min_fill_seconds: 3,
+            max_form_age_seconds: 24 * 60 * 60,
+            challenge: ChallengeSettings::default(),
+        }
+    }
+}
+
+// Checks on the address given to `POST /subscribe`, beyond its syntax.
+// DNS lookups that fail or time out let the address through.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct EmailValidationSettings {
+    pub reject_disposable: bool,
+    pub suggest_typos: bool,
+    pub check_dns: bool,
+    pub dns_timeout_milliseconds: u64,
+}
+
+impl EmailValidationSettings {
+    pub fn dns_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.dns_timeout_milliseconds)
+    }
+}
+
+impl Default for EmailValidationSettings {
+    fn default() -> Self {
+        Self {
+            reject_disposable: false,
+            suggest_typos: false,
+            check_dns: false,
+            dns_timeout_milliseconds: 2000,
+        }
+    }
+}
+
+// `site_verify` works with any provider exposing the common `siteverify`
+// API (reCAPTCHA, hCaptcha, Turnstile); `local` is meant for tests.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum ChallengeSettings {
+    #[default]
+    None,
+    SiteVerify {
+        verify_url: String,
+        secret: Secret<String>,
+        site_key: String,
+        script_url: String,
+    },
+    Local {
+        expected_response: String,
+    },
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct EmailClientSettings {
     pub base_url: String,
+    // publications without a sender address of their own send from this one
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64
@@ -48,6 +287,13 @@ pub struct AppSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // Encrypts the TOTP secrets at rest, see `authentication::two_factor`
+    pub totp_encryption_key: Secret<String>,
+    // Only send the session cookie over HTTPS
+    pub secure_cookies: bool,
+    // The reverse proxies in front of the application, see `client_ip`
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
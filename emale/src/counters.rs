@@ -0,0 +1,237 @@
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::shutdown::ShutdownState;
+
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+// Expiring counters behind the login throttle and the subscribe
+// protection. Kept in Redis when `redis_uri` is configured, in the
+// `counters` and `counter_hits` tables otherwise; expired rows are
+// never counted, they are deleted by `purge_expired_counters`.
+#[derive(Clone)]
+pub enum CounterStore {
+	Redis(ConnectionManager),
+	Postgres(PgPool),
+}
+
+impl CounterStore {
+	pub async fn connect(
+		redis_uri: Option<&Secret<String>>,
+		pool: &PgPool
+	) -> Result<Self, anyhow::Error> {
+		match redis_uri {
+			Some(redis_uri) => {
+				let client = redis::Client::open(redis_uri.expose_secret().as_str())?;
+				let redis = ConnectionManager::new(client)
+					.await
+					.context("Failed to connect to redis")?;
+				Ok(CounterStore::Redis(redis))
+			}
+			None => Ok(CounterStore::Postgres(pool.clone())),
+		}
+	}
+
+	// Adds a hit to `key` and returns the number of hits within the last
+	// `window_seconds`: a sliding window rather than fixed buckets.
+	pub async fn hit(&self, key: &str, window_seconds: u64) -> Result<u64, anyhow::Error> {
+		match self {
+			CounterStore::Redis(redis) => {
+				let now = chrono::Utc::now().timestamp_millis();
+				let window_start = now - (window_seconds as i64) * 1000;
+				let (n_hits,): (u64,) = redis::pipe()
+					.atomic()
+					.zrembyscore(key, "-inf", window_start)
+					.ignore()
+					.zadd(key, Uuid::new_v4().to_string(), now)
+					.ignore()
+					.zcard(key)
+					.expire(key, window_seconds as usize)
+					.ignore()
+					.query_async(&mut redis.clone())
+					.await
+					.context("Failed to count the hits")?;
+				Ok(n_hits)
+			}
+			CounterStore::Postgres(pool) => {
+				// the row inserted by the statement is not visible to its own count
+				let n_hits = sqlx::query_scalar!(
+					r#"
+					WITH hit AS (
+						INSERT INTO counter_hits (key, expires_at)
+						VALUES ($1, now() + make_interval(secs => $2))
+					)
+					SELECT COUNT(*) + 1 AS "n_hits!"
+					FROM counter_hits
+					WHERE key = $1 AND expires_at > now()
+					"#,
+					key,
+					window_seconds as f64
+				)
+				.fetch_one(pool)
+				.await
+				.context("Failed to count the hits")?;
+				Ok(n_hits as u64)
+			}
+		}
+	}
+
+	// Increments `key`, which expires `ttl_seconds` after its last increment.
+	pub async fn incr(&self, key: &str, ttl_seconds: u64) -> Result<u64, anyhow::Error> {
+		match self {
+			CounterStore::Redis(redis) => {
+				let (value,): (u64,) = redis::pipe()
+					.atomic()
+					.incr(key, 1)
+					.expire(key, ttl_seconds as usize)
+					.ignore()
+					.query_async(&mut redis.clone())
+					.await
+					.context("Failed to increment the counter")?;
+				Ok(value)
+			}
+			CounterStore::Postgres(pool) => {
+				let value = sqlx::query_scalar!(
+					r#"
+					INSERT INTO counters (key, value, expires_at)
+					VALUES ($1, 1, now() + make_interval(secs => $2))
+					ON CONFLICT (key) DO UPDATE
+					SET
+						value = CASE
+							WHEN counters.expires_at > now() THEN counters.value + 1
+							ELSE 1
+						END,
+						expires_at = EXCLUDED.expires_at
+					RETURNING value
+					"#,
+					key,
+					ttl_seconds as f64
+				)
+				.fetch_one(pool)
+				.await
+				.context("Failed to increment the counter")?;
+				Ok(value as u64)
+			}
+		}
+	}
+
+	pub async fn set(&self, key: &str, value: u64, ttl_seconds: u64) -> Result<(), anyhow::Error> {
+		match self {
+			CounterStore::Redis(redis) => {
+				redis
+					.clone()
+					.set_ex::<_, _, ()>(key, value, ttl_seconds as usize)
+					.await
+					.context("Failed to set the counter")?;
+			}
+			CounterStore::Postgres(pool) => {
+				sqlx::query!(
+					r#"
+					INSERT INTO counters (key, value, expires_at)
+					VALUES ($1, $2, now() + make_interval(secs => $3))
+					ON CONFLICT (key) DO UPDATE
+					SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at
+					"#,
+					key,
+					value as i64,
+					ttl_seconds as f64
+				)
+				.execute(pool)
+				.await
+				.context("Failed to set the counter")?;
+			}
+		}
+		Ok(())
+	}
+
+	// Whether any of the `keys` is set and not expired.
+	pub async fn exists(&self, keys: &[String]) -> Result<bool, anyhow::Error> {
+		match self {
+			CounterStore::Redis(redis) => {
+				let n_existing: u32 = redis
+					.clone()
+					.exists(keys)
+					.await
+					.context("Failed to look the counters up")?;
+				Ok(n_existing > 0)
+			}
+			CounterStore::Postgres(pool) => {
+				let exists = sqlx::query_scalar!(
+					r#"
+					SELECT EXISTS (
+						SELECT 1 FROM counters
+						WHERE key = ANY($1) AND expires_at > now()
+					) AS "exists!"
+					"#,
+					keys
+				)
+				.fetch_one(pool)
+				.await
+				.context("Failed to look the counters up")?;
+				Ok(exists)
+			}
+		}
+	}
+
+	// Both the counters and the hits of `keys`.
+	pub async fn delete(&self, keys: &[String]) -> Result<(), anyhow::Error> {
+		match self {
+			CounterStore::Redis(redis) => {
+				redis
+					.clone()
+					.del::<_, ()>(keys)
+					.await
+					.context("Failed to delete the counters")?;
+			}
+			CounterStore::Postgres(pool) => {
+				let mut transaction = pool.begin().await?;
+				sqlx::query!("DELETE FROM counters WHERE key = ANY($1)", keys)
+					.execute(&mut transaction)
+					.await
+					.context("Failed to delete the counters")?;
+				sqlx::query!("DELETE FROM counter_hits WHERE key = ANY($1)", keys)
+					.execute(&mut transaction)
+					.await
+					.context("Failed to delete the counters")?;
+				transaction.commit().await?;
+			}
+		}
+		Ok(())
+	}
+}
+
+// Returns the number of counters and hits deleted.
+#[tracing::instrument(skip_all)]
+pub async fn purge_expired_counters(pool: &PgPool) -> Result<u64, anyhow::Error> {
+	let counters = sqlx::query!("DELETE FROM counters WHERE expires_at <= now()")
+		.execute(pool)
+		.await
+		.context("Failed to delete the expired counters")?
+		.rows_affected();
+	let hits = sqlx::query!("DELETE FROM counter_hits WHERE expires_at <= now()")
+		.execute(pool)
+		.await
+		.context("Failed to delete the expired hits")?
+		.rows_affected();
+	Ok(counters + hits)
+}
+
+// Runs `purge_expired_counters` every ten minutes until shutdown.
+pub async fn purge_expired_counters_periodically(pool: PgPool, shutdown: ShutdownState) {
+	loop {
+		tokio::select! {
+			_ = tokio::time::sleep(PURGE_INTERVAL) => {}
+			_ = shutdown.wait() => return,
+		}
+		if let Err(e) = purge_expired_counters(&pool).await {
+			tracing::warn!(
+				error.cause_chain = ?e,
+				"Failed to delete the expired counters"
+			);
+		}
+	}
+}
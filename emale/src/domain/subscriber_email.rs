@@ -11,6 +11,11 @@ impl SubscriberEmail {
       Err(format!("{} is not valid email", s))
     }
   }
+
+  // Everything after the last `@`, a valid email always has one.
+  pub fn domain(&self) -> &str {
+    self.0.rsplit('@').next().unwrap_or_default()
+  }
 }
 
 impl std::fmt::Display for SubscriberEmail {
@@ -61,6 +66,12 @@ mod tests {
     assert_err!(SubscriberEmail::parse(email));
   }
 
+  #[test]
+  fn domain_is_the_part_after_the_at_sign() {
+    let email = SubscriberEmail::parse("ursula@example.com".to_string()).unwrap();
+    assert_eq!(email.domain(), "example.com");
+  }
+
   #[quickcheck_macros::quickcheck]
   fn valid_emails_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
     // dbg!(valid_email.0);
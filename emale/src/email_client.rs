@@ -1,4 +1,6 @@
 use crate::domain::SubscriberEmail;
+use crate::metrics::record_email_request;
+use crate::telemetry::current_traceparent;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Serialize};
@@ -40,6 +42,14 @@ impl EmailClient {
         }
     }
 
+    // Same provider and credentials, another sender address.
+    pub fn with_sender(&self, sender: SubscriberEmail) -> Self {
+        Self {
+            sender,
+            ..self.clone()
+        }
+    }
+
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
@@ -55,17 +65,24 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
-        self
+        let mut request = self
             .http_client
             .post(&url)
             .header(
                 "api-key",
                 self.authorization_token.expose_secret(),
-            )
+            );
+        if let Some(traceparent) = current_traceparent() {
+            request = request.header("traceparent", traceparent);
+        }
+        let started_at = std::time::Instant::now();
+        let outcome = request
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .and_then(|response| response.error_for_status());
+        record_email_request(started_at, outcome.is_ok());
+        outcome?;
 
         Ok(())
     }
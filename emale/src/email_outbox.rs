@@ -0,0 +1,181 @@
+use chrono::{Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::field::display;
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::publications::sender_email_client;
+use crate::telemetry::{current_traceparent, link_to_traceparent};
+use crate::utils::backoff_delay;
+
+// Transactional emails, e.g. the subscription confirmation, are written
+// to `email_outbox` in the transaction of the change they are about, so
+// that a slow or failing email provider neither fails the request nor
+// loses the email. The delivery worker sends them, retrying with an
+// exponential backoff; sent emails are deleted, the ones given up on are
+// kept with their last error.
+
+const MAX_ATTEMPTS: i32 = 10;
+const FIRST_RETRY_DELAY_SECONDS: i64 = 30;
+const MAX_RETRY_DELAY_SECONDS: i64 = 60 * 60;
+
+pub struct OutgoingEmail<'a> {
+	// sent from the address of the publication
+	pub publication_id: &'a str,
+	pub recipient: &'a SubscriberEmail,
+	pub subject: &'a str,
+	pub html_content: &'a str,
+	pub text_content: &'a str,
+}
+
+#[tracing::instrument(name = "Queue transactional email", skip(transaction, email))]
+pub async fn enqueue_email(
+	transaction: &mut Transaction<'_, Postgres>,
+	email: OutgoingEmail<'_>
+) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+		INSERT INTO email_outbox (
+			email_id,
+			publication_id,
+			recipient,
+			subject,
+			html_content,
+			text_content,
+			traceparent,
+			next_attempt_at,
+			created_at
+		)
+		VALUES ($1, $2, $3, $4, $5, $6, $7, now(), now())
+		"#,
+		Uuid::new_v4(),
+		email.publication_id,
+		email.recipient.as_ref(),
+		email.subject,
+		email.html_content,
+		email.text_content,
+		current_traceparent()
+	)
+	.execute(transaction)
+	.await?;
+	Ok(())
+}
+
+// `None` once the email should be given up on.
+fn retry_delay(attempts: i32) -> Option<Duration> {
+	if attempts >= MAX_ATTEMPTS {
+		return None;
+	}
+	Some(backoff_delay(
+		attempts,
+		Duration::seconds(FIRST_RETRY_DELAY_SECONDS),
+		Duration::seconds(MAX_RETRY_DELAY_SECONDS)
+	))
+}
+
+#[tracing::instrument(
+	skip_all,
+	fields(email_id=tracing::field::Empty),
+	err
+)]
+pub async fn try_send_outbox_email(
+	pool: &PgPool,
+	email_client: &EmailClient
+) -> Result<ExecutionOutcome, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	let email = sqlx::query!(
+		r#"
+		SELECT
+			o.email_id,
+			o.recipient,
+			o.subject,
+			o.html_content,
+			o.text_content,
+			o.traceparent,
+			o.attempts,
+			p.sender_email
+		FROM email_outbox o
+		JOIN publications p USING (publication_id)
+		WHERE o.next_attempt_at <= now()
+		ORDER BY o.next_attempt_at
+		FOR UPDATE OF o
+		SKIP LOCKED
+		LIMIT 1
+		"#
+	)
+	.fetch_optional(&mut transaction)
+	.await?;
+	let email = match email {
+		Some(email) => email,
+		None => return Ok(ExecutionOutcome::EmptyQueue),
+	};
+	// sent in its own trace, linked to the request that queued it
+	if let Some(traceparent) = &email.traceparent {
+		link_to_traceparent(traceparent);
+	}
+	Span::current().record("email_id", &display(email.email_id));
+
+	// the error, and whether retrying can help
+	let failure = match SubscriberEmail::parse(email.recipient) {
+		Ok(recipient) => match sender_email_client(email_client, email.sender_email) {
+			Ok(email_client) => email_client
+				.send_email(&recipient, &email.subject, &email.html_content, &email.text_content)
+				.await
+				.err()
+				.map(|e| (e.to_string(), true)),
+			Err(e) => Some((format!("{:#}", e), false)),
+		},
+		Err(e) => Some((e, false)),
+	};
+	match failure {
+		None => {
+			sqlx::query!(
+				r#"DELETE FROM email_outbox WHERE email_id = $1"#,
+				email.email_id
+			)
+			.execute(&mut transaction)
+			.await?;
+		}
+		Some((error, retryable)) => {
+			let attempts = email.attempts + 1;
+			tracing::warn!(error.message = %error, attempts, "Failed to send a transactional email");
+			let next_attempt_at = if retryable {
+				retry_delay(attempts).map(|delay| Utc::now() + delay)
+			} else {
+				None
+			};
+			sqlx::query!(
+				r#"
+				UPDATE email_outbox
+				SET attempts = $2, next_attempt_at = $3, last_error = $4
+				WHERE email_id = $1
+				"#,
+				email.email_id,
+				attempts,
+				next_attempt_at,
+				error
+			)
+			.execute(&mut transaction)
+			.await?;
+		}
+	}
+	transaction.commit().await?;
+	Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{retry_delay, MAX_ATTEMPTS};
+	use chrono::Duration;
+
+	#[test]
+	fn retries_back_off_up_to_an_hour() {
+		assert_eq!(retry_delay(1), Some(Duration::seconds(30)));
+		assert_eq!(retry_delay(2), Some(Duration::minutes(1)));
+		assert_eq!(retry_delay(MAX_ATTEMPTS - 1), Some(Duration::hours(1)));
+		assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+	}
+}
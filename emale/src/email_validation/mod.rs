@@ -0,0 +1,272 @@
+mod resolver;
+mod typos;
+
+pub use resolver::{DomainResolver, SystemResolver};
+pub use typos::suggest_domain;
+
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+
+use crate::configuration::EmailValidationSettings;
+use crate::domain::SubscriberEmail;
+
+static DISPOSABLE_DOMAINS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    include_str!("disposable_domains.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+// `mail.yopmail.com` is as disposable as `yopmail.com`.
+pub fn is_disposable(domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if DISPOSABLE_DOMAINS.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+// Displayed as is to the person subscribing.
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum EmailRejection {
+    #[error("Disposable email addresses are not accepted.")]
+    Disposable,
+    #[error("{domain} does not accept email.")]
+    NoMailServer { domain: String },
+}
+
+// Checks run on top of the syntax validation of `SubscriberEmail`,
+// each one enabled by `email_validation` in the configuration.
+pub struct EmailValidator {
+    settings: EmailValidationSettings,
+    resolver: Option<Box<dyn DomainResolver>>,
+}
+
+impl EmailValidator {
+    pub fn new(settings: EmailValidationSettings, resolver: Box<dyn DomainResolver>) -> Self {
+        Self {
+            settings,
+            resolver: Some(resolver),
+        }
+    }
+
+    // The system resolver is only set up when DNS checks are enabled.
+    pub fn from_settings(settings: EmailValidationSettings) -> Result<Self, anyhow::Error> {
+        if settings.check_dns {
+            Ok(Self::new(settings, Box::new(SystemResolver::from_system_conf()?)))
+        } else {
+            Ok(Self {
+                settings,
+                resolver: None,
+            })
+        }
+    }
+
+    #[tracing::instrument(name = "Validate email address", skip(self))]
+    pub async fn validate(&self, email: &SubscriberEmail) -> Result<(), EmailRejection> {
+        let domain = email.domain().to_lowercase();
+        if self.settings.reject_disposable && is_disposable(&domain) {
+            return Err(EmailRejection::Disposable);
+        }
+        if let (true, Some(resolver)) = (self.settings.check_dns, &self.resolver) {
+            let lookup = tokio::time::timeout(
+                self.settings.dns_timeout(),
+                accepts_mail(resolver.as_ref(), &domain),
+            )
+            .await;
+            // an unreachable resolver must not prevent people from subscribing
+            match lookup {
+                Ok(Ok(true)) => {}
+                Ok(Ok(false)) => return Err(EmailRejection::NoMailServer { domain }),
+                Ok(Err(e)) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Failed to look up the mail servers of {}", domain
+                ),
+                Err(_) => tracing::warn!("Timed out looking up the mail servers of {}", domain),
+            }
+        }
+        Ok(())
+    }
+
+    // The address on the popular domain that `email` is likely a typo of.
+    // Only a suggestion: the domain may well exist, the person decides.
+    pub fn suggest(&self, email: &SubscriberEmail) -> Option<String> {
+        if !self.settings.suggest_typos {
+            return None;
+        }
+        let suggested_domain = suggest_domain(&email.domain().to_lowercase())?;
+        let local_part = email
+            .as_ref()
+            .rsplit_once('@')
+            .map(|(local_part, _)| local_part)
+            .unwrap_or_default();
+        Some(format!("{}@{}", local_part, suggested_domain))
+    }
+}
+
+// Mail goes to the MX hosts of the domain or, without any, to the domain
+// itself (RFC 5321). A null MX (RFC 7505) means no mail is accepted.
+async fn accepts_mail(resolver: &dyn DomainResolver, domain: &str) -> Result<bool, anyhow::Error> {
+    let mx_hosts = resolver.mx_hosts(domain).await?;
+    if !mx_hosts.is_empty() {
+        return Ok(mx_hosts.iter().any(|host| host != "." && !host.is_empty()));
+    }
+    Ok(!resolver.addresses(domain).await?.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_disposable, DomainResolver, EmailRejection, EmailValidator};
+    use crate::configuration::EmailValidationSettings;
+    use crate::domain::SubscriberEmail;
+    use claim::assert_ok;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    #[derive(Default)]
+    struct FakeResolver {
+        mx_hosts: HashMap<String, Vec<String>>,
+        addresses: HashMap<String, Vec<IpAddr>>,
+        failing: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl DomainResolver for FakeResolver {
+        async fn mx_hosts(&self, domain: &str) -> Result<Vec<String>, anyhow::Error> {
+            if self.failing {
+                anyhow::bail!("SERVFAIL");
+            }
+            Ok(self.mx_hosts.get(domain).cloned().unwrap_or_default())
+        }
+
+        async fn addresses(&self, domain: &str) -> Result<Vec<IpAddr>, anyhow::Error> {
+            Ok(self.addresses.get(domain).cloned().unwrap_or_default())
+        }
+    }
+
+    fn settings() -> EmailValidationSettings {
+        EmailValidationSettings {
+            reject_disposable: true,
+            suggest_typos: true,
+            check_dns: true,
+            dns_timeout_milliseconds: 1000,
+        }
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn subdomains_of_disposable_domains_are_disposable() {
+        assert!(is_disposable("yopmail.com"));
+        assert!(is_disposable("mail.yopmail.com"));
+        assert!(!is_disposable("example.com"));
+    }
+
+    #[tokio::test]
+    async fn disposable_addresses_are_rejected() {
+        let validator = EmailValidator::new(settings(), Box::new(FakeResolver::default()));
+        assert_eq!(
+            validator.validate(&email("ursula@mailinator.com")).await,
+            Err(EmailRejection::Disposable)
+        );
+    }
+
+    #[test]
+    fn typos_come_with_the_corrected_address() {
+        let validator = EmailValidator::new(settings(), Box::new(FakeResolver::default()));
+        assert_eq!(
+            validator.suggest(&email("ursula@gmial.com")),
+            Some("ursula@gmail.com".into())
+        );
+        assert_eq!(validator.suggest(&email("ursula@gmail.com")), None);
+    }
+
+    #[tokio::test]
+    async fn likely_typos_are_not_rejected() {
+        let resolver = FakeResolver {
+            mx_hosts: HashMap::from([("email.com".into(), vec!["mx.email.com.".into()])]),
+            ..FakeResolver::default()
+        };
+        let validator = EmailValidator::new(settings(), Box::new(resolver));
+        assert_ok!(validator.validate(&email("ursula@email.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_with_an_mx_record_are_accepted() {
+        let resolver = FakeResolver {
+            mx_hosts: HashMap::from([("example.com".into(), vec!["mx.example.com.".into()])]),
+            ..FakeResolver::default()
+        };
+        let validator = EmailValidator::new(settings(), Box::new(resolver));
+        assert_ok!(validator.validate(&email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_with_only_an_address_are_accepted() {
+        let resolver = FakeResolver {
+            addresses: HashMap::from([("example.com".into(), vec!["192.0.2.1".parse().unwrap()])]),
+            ..FakeResolver::default()
+        };
+        let validator = EmailValidator::new(settings(), Box::new(resolver));
+        assert_ok!(validator.validate(&email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_without_any_record_are_rejected() {
+        let validator = EmailValidator::new(settings(), Box::new(FakeResolver::default()));
+        assert_eq!(
+            validator.validate(&email("ursula@example.com")).await,
+            Err(EmailRejection::NoMailServer {
+                domain: "example.com".into()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn domains_with_a_null_mx_are_rejected() {
+        let resolver = FakeResolver {
+            mx_hosts: HashMap::from([("example.com".into(), vec![".".into()])]),
+            addresses: HashMap::from([("example.com".into(), vec!["192.0.2.1".parse().unwrap()])]),
+            ..FakeResolver::default()
+        };
+        let validator = EmailValidator::new(settings(), Box::new(resolver));
+        assert_eq!(
+            validator.validate(&email("ursula@example.com")).await,
+            Err(EmailRejection::NoMailServer {
+                domain: "example.com".into()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn resolver_failures_do_not_reject_the_address() {
+        let resolver = FakeResolver {
+            failing: true,
+            ..FakeResolver::default()
+        };
+        let validator = EmailValidator::new(settings(), Box::new(resolver));
+        assert_ok!(validator.validate(&email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn disabled_checks_are_skipped() {
+        let settings = EmailValidationSettings {
+            reject_disposable: false,
+            suggest_typos: false,
+            check_dns: false,
+            ..settings()
+        };
+        let validator = EmailValidator::new(settings, Box::new(FakeResolver::default()));
+        assert_ok!(validator.validate(&email("ursula@mailinator.com")).await);
+        assert_ok!(validator.validate(&email("ursula@gmial.com")).await);
+    }
+}
@@ -0,0 +1,54 @@
+use std::net::IpAddr;
+
+use anyhow::Context;
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::TokioAsyncResolver;
+
+// The DNS lookups needed to tell whether a domain accepts email.
+// Domains without any record resolve to an empty list, not an error.
+#[async_trait::async_trait]
+pub trait DomainResolver: Send + Sync {
+    async fn mx_hosts(&self, domain: &str) -> Result<Vec<String>, anyhow::Error>;
+    async fn addresses(&self, domain: &str) -> Result<Vec<IpAddr>, anyhow::Error>;
+}
+
+// Resolves through the name servers of the host (`/etc/resolv.conf`).
+pub struct SystemResolver(TokioAsyncResolver);
+
+impl SystemResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .context("Failed to read the system DNS configuration")?;
+        Ok(Self(resolver))
+    }
+}
+
+// Queried as a fully qualified name, the search domains of
+// the host must not be appended.
+fn fqdn(domain: &str) -> String {
+    format!("{}.", domain.trim_end_matches('.'))
+}
+
+fn empty_if_no_records<T>(e: ResolveError) -> Result<Vec<T>, anyhow::Error> {
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound { .. } => Ok(Vec::new()),
+        _ => Err(e.into()),
+    }
+}
+
+#[async_trait::async_trait]
+impl DomainResolver for SystemResolver {
+    async fn mx_hosts(&self, domain: &str) -> Result<Vec<String>, anyhow::Error> {
+        match self.0.mx_lookup(fqdn(domain)).await {
+            Ok(lookup) => Ok(lookup.iter().map(|mx| mx.exchange().to_utf8()).collect()),
+            Err(e) => empty_if_no_records(e),
+        }
+    }
+
+    async fn addresses(&self, domain: &str) -> Result<Vec<IpAddr>, anyhow::Error> {
+        match self.0.lookup_ip(fqdn(domain)).await {
+            Ok(lookup) => Ok(lookup.iter().collect()),
+            Err(e) => empty_if_no_records(e),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Domains that get misspelled often enough to be worth a suggestion.
+const POPULAR_DOMAINS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "googlemail.com",
+    "hotmail.com",
+    "hotmail.co.uk",
+    "hotmail.fr",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "yahoo.com",
+    "yahoo.co.uk",
+    "yahoo.fr",
+    "ymail.com",
+];
+
+// A popular domain one edit away from `domain`. Popular domains are
+// never corrected, some of them are one edit away from each other.
+pub fn suggest_domain(domain: &str) -> Option<&'static str> {
+    if POPULAR_DOMAINS.contains(&domain) {
+        return None;
+    }
+    POPULAR_DOMAINS
+        .iter()
+        .copied()
+        .find(|popular| edit_distance(domain, popular) == 1)
+}
+
+// Optimal string alignment distance: insertions, deletions, substitutions
+// and transpositions of adjacent characters all cost one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suggest_domain;
+    use claim::{assert_none, assert_some_eq};
+
+    #[test]
+    fn transposed_letters_are_corrected() {
+        assert_some_eq!(suggest_domain("gmial.com"), "gmail.com");
+        assert_some_eq!(suggest_domain("hotmial.com"), "hotmail.com");
+    }
+
+    #[test]
+    fn mistyped_top_level_domains_are_corrected() {
+        assert_some_eq!(suggest_domain("gmail.con"), "gmail.com");
+        assert_some_eq!(suggest_domain("yahoo.co"), "yahoo.com");
+    }
+
+    #[test]
+    fn popular_domains_are_left_alone() {
+        assert_none!(suggest_domain("mail.com"));
+        assert_none!(suggest_domain("ymail.com"));
+    }
+
+    #[test]
+    fn unrelated_domains_are_left_alone() {
+        assert_none!(suggest_domain("example.com"));
+        assert_none!(suggest_domain("gmail.company.com"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::configuration::HealthSettings;
+use crate::shutdown::ShutdownState;
+use crate::startup::MIGRATOR;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub status: CheckStatus,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    pub fn is_up(&self) -> bool {
+        matches!(self.status, CheckStatus::Up)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReadinessChecks {
+    pub database: CheckResult,
+    // only checked when Redis is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redis: Option<CheckResult>,
+    pub migrations: CheckResult,
+    pub worker: CheckResult,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub shutting_down: bool,
+    pub checks: ReadinessChecks,
+}
+
+// Everything `/health/ready` needs besides the database pool.
+pub struct ReadinessProbe {
+    // The session store does not expose its connection, the probe opens
+    // its own against the same server.
+    redis: Option<redis::Client>,
+    settings: HealthSettings,
+}
+
+impl ReadinessProbe {
+    pub fn new(redis_uri: Option<&str>, settings: HealthSettings) -> Result<Self, anyhow::Error> {
+        let redis = redis_uri.map(redis::Client::open).transpose()?;
+        Ok(Self { redis, settings })
+    }
+
+    // The checks run concurrently, each one bounded by the configured timeout.
+    #[tracing::instrument(name = "Check readiness", skip_all)]
+    pub async fn check(&self, pool: &PgPool, shutdown: &ShutdownState) -> ReadinessReport {
+        let (database, redis, migrations, worker) = tokio::join!(
+            self.run_check(check_database(pool)),
+            async {
+                match &self.redis {
+                    Some(redis) => Some(self.run_check(check_redis(redis)).await),
+                    None => None,
+                }
+            },
+            self.run_check(check_migrations(pool)),
+            self.run_check(check_worker_heartbeat(
+                pool,
+                self.settings.worker_heartbeat_max_age(),
+            )),
+        );
+        let checks = ReadinessChecks {
+            database,
+            redis,
+            migrations,
+            worker,
+        };
+        let shutting_down = shutdown.is_shutting_down();
+        let ready = !shutting_down
+            && checks.database.is_up()
+            && checks.redis.as_ref().map_or(true, CheckResult::is_up)
+            && checks.migrations.is_up()
+            && checks.worker.is_up();
+        ReadinessReport {
+            ready,
+            shutting_down,
+            checks,
+        }
+    }
+
+    async fn run_check<F>(&self, check: F) -> CheckResult
+    where
+        F: Future<Output = Result<Option<serde_json::Value>, anyhow::Error>>,
+    {
+        let started_at = Instant::now();
+        let outcome = tokio::time::timeout(self.settings.check_timeout(), check).await;
+        let duration_ms = started_at.elapsed().as_millis() as u64;
+        let (status, details, error) = match outcome {
+            Ok(Ok(details)) => (CheckStatus::Up, details, None),
+            Ok(Err(e)) => (CheckStatus::Down, None, Some(format!("{:#}", e))),
+            Err(_) => (CheckStatus::Down, None, Some("Timed out".into())),
+        };
+        CheckResult {
+            status,
+            duration_ms,
+            details,
+            error,
+        }
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    sqlx::query("SELECT 1")
+        .execute(pool)
+        .await
+        .context("Failed to reach the database")?;
+    Ok(None)
+}
+
+async fn check_redis(client: &redis::Client) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    let mut connection = client
+        .get_async_connection()
+        .await
+        .context("Failed to connect to redis")?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut connection)
+        .await
+        .context("Failed to ping redis")?;
+    Ok(None)
+}
+
+// Compares the migrations embedded in the binary with the ones
+// recorded by sqlx in the database.
+async fn check_migrations(pool: &PgPool) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    let applied: HashSet<i64> = sqlx::query_scalar::<_, i64>(
+        "SELECT version FROM _sqlx_migrations WHERE success = true",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to read the applied migrations")?
+    .into_iter()
+    .collect();
+    let pending: Vec<i64> = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| m.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+    if !pending.is_empty() {
+        anyhow::bail!("Pending migrations: {:?}", pending);
+    }
+    Ok(None)
+}
+
+async fn check_worker_heartbeat(
+    pool: &PgPool,
+    max_age: Duration,
+) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    // the age is computed with the database clock, the one
+    // used by the workers to write their heartbeat
+    let r = sqlx::query!(
+        r#"
+        SELECT MAX(last_seen_at) AS last_seen_at, now() AS "now!"
+        FROM worker_heartbeats
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to read the worker heartbeats")?;
+    let last_seen_at = r
+        .last_seen_at
+        .ok_or_else(|| anyhow::anyhow!("No worker has reported a heartbeat"))?;
+    let age_seconds = (r.now - last_seen_at).num_seconds();
+    if age_seconds > max_age.as_secs() as i64 {
+        anyhow::bail!("Last worker heartbeat was {}s ago", age_seconds);
+    }
+    Ok(Some(serde_json::json!({ "last_heartbeat_seconds_ago": age_seconds })))
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use actix_web::{FromRequest, HttpRequest};
+use once_cell::sync::Lazy;
+
+// Catalogs are `key = value` lines, `\n` in a value is a line break and
+// `{name}` a placeholder filled by `Locale::format`. A key missing from a
+// catalog falls back to the English one.
+static CATALOGS: Lazy<HashMap<Locale, HashMap<&'static str, String>>> = Lazy::new(|| {
+    HashMap::from([
+        (Locale::En, parse_catalog(include_str!("en.txt"))),
+        (Locale::Fr, parse_catalog(include_str!("fr.txt"))),
+    ])
+});
+
+fn parse_catalog(catalog: &'static str) -> HashMap<&'static str, String> {
+    catalog
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim().replace("\\n", "\n")))
+        .collect()
+}
+
+// Languages subscribers can pick, stored as `subscriber.locale`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Fr];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+        }
+    }
+
+    // Shown in the language pickers, in the language itself.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::Fr => "Français",
+        }
+    }
+
+    // Only the primary subtag matters: `fr-CA` is `fr`.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?;
+        Self::ALL
+            .into_iter()
+            .find(|locale| locale.as_str().eq_ignore_ascii_case(primary))
+    }
+
+    // The supported language with the highest weight in an
+    // `Accept-Language` header.
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+        for range in header.split(',') {
+            let mut parts = range.split(';');
+            let tag = parts.next().unwrap_or_default();
+            let weight = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            if let Some(locale) = Self::parse(tag) {
+                if weight > 0.0 && best.map_or(true, |(_, w)| weight > w) {
+                    best = Some((locale, weight));
+                }
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+
+    // Unknown keys are displayed as is.
+    pub fn t<'a>(&self, key: &'a str) -> &'a str {
+        CATALOGS[self]
+            .get(key)
+            .or_else(|| CATALOGS[&Locale::En].get(key))
+            .map(String::as_str)
+            .unwrap_or_else(|| {
+                tracing::warn!("Missing translation for {}", key);
+                key
+            })
+    }
+
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> String {
+        args.iter()
+            .fold(self.t(key).to_owned(), |message, (name, value)| {
+                message.replace(&format!("{{{}}}", name), value)
+            })
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// The locale asked for by the browser, English if none is supported.
+impl FromRequest for Locale {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let locale = req
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(Locale::from_accept_language)
+            .unwrap_or_default();
+        ready(Ok(locale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Locale, CATALOGS};
+    use claim::{assert_none, assert_some_eq};
+
+    #[test]
+    fn tags_are_matched_on_their_primary_subtag() {
+        assert_some_eq!(Locale::parse("fr-CA"), Locale::Fr);
+        assert_some_eq!(Locale::parse("EN"), Locale::En);
+        assert_none!(Locale::parse("de"));
+    }
+
+    #[test]
+    fn the_preferred_supported_language_wins() {
+        assert_some_eq!(Locale::from_accept_language("de, fr;q=0.8, en;q=0.5"), Locale::Fr);
+        assert_some_eq!(Locale::from_accept_language("en-GB, fr;q=0.9"), Locale::En);
+        assert_none!(Locale::from_accept_language("de, fr;q=0"));
+    }
+
+    #[test]
+    fn every_translated_key_exists_in_english() {
+        for key in CATALOGS[&Locale::Fr].keys() {
+            assert!(CATALOGS[&Locale::En].contains_key(key), "{} is not in en.txt", key);
+        }
+    }
+
+    #[test]
+    fn placeholders_are_filled() {
+        let body = Locale::Fr.format("email.confirm_subscription.text", &[("link", "https://example.com")]);
+        assert!(body.contains("https://example.com"));
+        assert!(!body.contains("{link}"));
+    }
+}
@@ -1,3 +1,9 @@
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::utils::{client_ip, user_agent};
+
 #[derive(Debug)]
 pub struct IdempotencyKey(String);
 
@@ -31,3 +37,29 @@ impl AsRef<str> for IdempotencyKey {
 		&self.0
 	}
 }
+
+
+// Whom a key belongs to: the logged in user or, on anonymous routes,
+// the client, told apart by its address and user agent.
+#[derive(Debug)]
+pub struct IdempotencyScope(String);
+
+impl IdempotencyScope {
+	pub fn user(user_id: Uuid) -> Self {
+		Self(format!("user:{}", user_id))
+	}
+
+	pub fn client(request: &HttpRequest) -> Self {
+		let mut hasher = Sha256::new();
+		hasher.update(client_ip(request));
+		hasher.update([0]);
+		hasher.update(user_agent(request).unwrap_or_default());
+		Self(format!("client:{}", hex::encode(hasher.finalize())))
+	}
+}
+
+impl AsRef<str> for IdempotencyScope {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
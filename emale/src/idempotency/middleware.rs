@@ -0,0 +1,125 @@
+use actix_http::body::{BoxBody, MessageBody};
+use actix_http::HttpMessage;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::{web, FromRequest, HttpResponse};
+use actix_web_lab::middleware::Next;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use super::{save_response, try_proccesing, IdempotencyKey, IdempotencyScope, NextAction};
+use crate::authentication::UserId;
+use crate::utils::{error_400, error_500};
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+#[derive(serde::Deserialize)]
+struct IdempotencyFormData {
+	idempotency_key: Option<String>,
+}
+
+// What the key was used for, so that it cannot be replayed
+// for another request.
+fn request_hash(req: &ServiceRequest, body: &[u8]) -> Vec<u8> {
+	let mut hasher = Sha256::new();
+	hasher.update(req.method().as_str());
+	hasher.update([0]);
+	hasher.update(req.path());
+	hasher.update([0]);
+	hasher.update(body);
+	hasher.finalize().to_vec()
+}
+
+fn reject_reused_key() -> actix_web::Error {
+	let e = anyhow::anyhow!("The idempotency key was first used for another request");
+	let response = HttpResponse::UnprocessableEntity()
+		.body("This idempotency key has already been used for another request");
+	InternalError::from_response(e, response).into()
+}
+
+// A request carrying a key, as an `Idempotency-Key` header or an
+// `idempotency_key` form field, is handled once and its response
+// replayed on retries. Keys belong to the logged in user, to the
+// client on anonymous routes. Server errors are not saved, so that
+// the request can be retried.
+pub async fn enforce_idempotency(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	idempotency(req, next, false).await
+}
+
+// Same as `enforce_idempotency`, for routes where a retry must never
+// be handled twice: unsafe requests without a key are rejected.
+pub async fn require_idempotency(
+	req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+	idempotency(req, next, true).await
+}
+
+async fn idempotency(
+	mut req: ServiceRequest,
+	next: Next<impl MessageBody + 'static>,
+	key_required: bool
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+	if req.method().is_safe() {
+		return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+	}
+
+	let body = {
+		let (http_request, payload) = req.parts_mut();
+		web::Bytes::from_request(http_request, payload).await
+	}?;
+	let header_key = req
+		.headers()
+		.get(IDEMPOTENCY_KEY_HEADER)
+		.and_then(|h| h.to_str().ok())
+		.map(|h| h.to_owned());
+	let key = header_key.or_else(|| {
+		serde_urlencoded::from_bytes::<IdempotencyFormData>(&body)
+			.ok()
+			.and_then(|form| form.idempotency_key)
+	});
+	let request_hash = request_hash(&req, &body);
+	// the body has been consumed, hand it back for the handler
+	let (_, mut payload) = actix_http::h1::Payload::create(true);
+	payload.unread_data(body);
+	req.set_payload(payload.into());
+
+	let key: IdempotencyKey = match key {
+		Some(key) => key.try_into().map_err(error_400)?,
+		None if key_required => return Err(error_400("Missing idempotency key")),
+		None => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+	};
+	let user_id = req.extensions().get::<UserId>().copied();
+	let scope = match user_id {
+		Some(user_id) => IdempotencyScope::user(*user_id),
+		None => IdempotencyScope::client(req.request()),
+	};
+	let pool = req
+		.app_data::<web::Data<PgPool>>()
+		.cloned()
+		.ok_or_else(|| error_500("Missing database pool"))?;
+
+	let transaction = match try_proccesing(&pool, &key, &scope, &request_hash)
+		.await
+		.map_err(error_500)?
+	{
+		NextAction::StartProcessing(t) => t,
+		NextAction::ReturnSavedResponse(saved_response) => {
+			return Ok(req.into_response(saved_response));
+		}
+		NextAction::RejectReusedKey => return Err(reject_reused_key()),
+	};
+	let response = next.call(req).await?;
+	if response.status().is_server_error() {
+		// dropping the transaction releases the key
+		return Ok(response.map_into_boxed_body());
+	}
+	let (request, response) = response.into_parts();
+	let response = save_response(transaction, &key, &scope, response.map_into_boxed_body())
+		.await
+		.map_err(error_500)?;
+	Ok(ServiceResponse::new(request, response))
+}
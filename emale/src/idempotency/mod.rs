@@ -1,5 +1,7 @@
 mod key;
+mod middleware;
 mod persistence;
 
 pub use persistence::*;
-pub use key::IdempotencyKey;
\ No newline at end of file
+pub use key::{IdempotencyKey, IdempotencyScope};
+pub use middleware::{enforce_idempotency, require_idempotency};
\ No newline at end of file
@@ -3,9 +3,7 @@ use actix_http::body::to_bytes;
 use actix_web::HttpResponse;
 use sqlx::{PgPool, Transaction, Postgres};
 use sqlx::postgres::PgHasArrayType;
-use uuid::Uuid;
-
-use super::IdempotencyKey;
+use super::{IdempotencyKey, IdempotencyScope};
 
 #[derive(Debug, sqlx::Type)]
 #[sqlx(type_name="header_pair")]
@@ -23,7 +21,7 @@ impl PgHasArrayType for HeaderPair {
 pub async fn get_saved_response(
 	pool: &PgPool,
 	key: &IdempotencyKey,
-	user_id: Uuid
+	scope: &IdempotencyScope
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
 	let saved_response = sqlx::query!(
 		r#"
@@ -33,10 +31,10 @@ pub async fn get_saved_response(
 			response_body as "response_body!"
 		FROM idempotency
 		WHERE
-			user_id = $1 AND
+			scope = $1 AND
 			idempotency_key = $2
 		"#,
-		user_id,
+		scope.as_ref(),
 		key.as_ref()
 	)
 	.fetch_optional(pool)
@@ -58,7 +56,7 @@ pub async fn get_saved_response(
 pub async fn save_response(
 	mut transaction: Transaction<'static, Postgres>,
 	idempotency_key: &IdempotencyKey,
-	user_id: Uuid,
+	scope: &IdempotencyScope,
 	http_response: HttpResponse
 ) -> Result<HttpResponse, anyhow::Error> {
 	let (response_head, body) = http_response.into_parts();
@@ -82,10 +80,10 @@ pub async fn save_response(
 				response_headers = $4,
 				response_body = $5
 			WHERE
-				user_id = $1 AND
+				scope = $1 AND
 				idempotency_key = $2
 		"#,
-		user_id,
+		scope.as_ref(),
 		idempotency_key.as_ref(),
 		status_code,
 		headers,
@@ -100,27 +98,32 @@ pub async fn save_response(
 
 pub enum NextAction {
 	ReturnSavedResponse(HttpResponse),
-	StartProcessing(Transaction<'static, Postgres>)
+	StartProcessing(Transaction<'static, Postgres>),
+	// the key was first used with another request
+	RejectReusedKey
 }
 
 pub async fn try_proccesing(
 	pool: &PgPool,
 	idempotency_key: &IdempotencyKey,
-	user_id: Uuid
+	scope: &IdempotencyScope,
+	request_hash: &[u8]
 ) -> Result<NextAction, anyhow::Error> {
 	let mut transaction = pool.begin().await?;
 	let n_inserted_rows = sqlx::query!(
 		r#"
 		INSERT INTO idempotency (
-			user_id,
+			scope,
 			idempotency_key,
+			request_hash,
 			created_at
 		)
-		VALUES ($1, $2, now())
+		VALUES ($1, $2, $3, now())
 		ON CONFLICT DO NOTHING
 		"#,
-		user_id,
-		idempotency_key.as_ref()
+		scope.as_ref(),
+		idempotency_key.as_ref(),
+		request_hash
 	)
 	.execute(&mut transaction)
 	.await?
@@ -128,7 +131,24 @@ pub async fn try_proccesing(
 	if n_inserted_rows > 0 {
 		Ok(NextAction::StartProcessing(transaction))
 	} else {
-		let saved_response = get_saved_response(pool, idempotency_key, user_id)
+		let saved_hash = sqlx::query!(
+			r#"
+			SELECT request_hash
+			FROM idempotency
+			WHERE
+				scope = $1 AND
+				idempotency_key = $2
+			"#,
+			scope.as_ref(),
+			idempotency_key.as_ref()
+		)
+		.fetch_one(pool)
+		.await?
+		.request_hash;
+		if matches!(saved_hash, Some(hash) if hash != request_hash) {
+			return Ok(NextAction::RejectReusedKey);
+		}
+		let saved_response = get_saved_response(pool, idempotency_key, scope)
 			.await?
 			.ok_or_else(||
 				anyhow::anyhow!("We expected a saved response, can't find it")
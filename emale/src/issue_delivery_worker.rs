@@ -1,12 +1,224 @@
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
 use sqlx::{PgPool, Postgres, Transaction};
 use tracing::field::display;
-use tracing::Span;
+use tracing::{Instrument, Span};
 use uuid::Uuid;
 
+use crate::automation::{schedule_due_steps, try_execute_step_task};
 use crate::configuration::Settings;
 use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
+use crate::email_outbox::try_send_outbox_email;
+use crate::publications::sender_email_client;
+use crate::shutdown::{in_flight_drained, shielded, with_deadline, ShutdownState};
 use crate::startup::get_connection_pool;
+use crate::telemetry::link_to_traceparent;
+use crate::webhooks::{try_deliver_webhook, webhook_client};
+
+static DELIVERY_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "issue_delivery_queue_depth",
+        "Number of newsletter deliveries waiting in the queue"
+    )
+    .expect("Failed to register issue_delivery_queue_depth")
+});
+
+static DELIVERIES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "issue_deliveries_total",
+        "Number of newsletter deliveries attempted, per issue and outcome",
+        &["newsletter_issue_id", "outcome"]
+    )
+    .expect("Failed to register issue_deliveries_total")
+});
+
+pub(crate) fn record_delivery(issue_id: Uuid, outcome: &str) {
+    DELIVERIES_TOTAL
+        .with_label_values(&[&issue_id.to_string(), outcome])
+        .inc();
+}
+
+// Sampled by the `/metrics` endpoint rather than by the worker loop,
+// so that the gauge is fresh whenever it is scraped.
+#[tracing::instrument(skip_all)]
+pub async fn refresh_queue_depth(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let depth = sqlx::query!(r#"SELECT COUNT(*) AS "depth!" FROM issue_delivery_queue"#)
+        .fetch_one(pool)
+        .await
+        .context("Failed to count queued deliveries")?
+        .depth;
+    DELIVERY_QUEUE_DEPTH.set(depth);
+    Ok(())
+}
+
+pub struct IssueQueueStats {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub pending_deliveries: i64,
+}
+
+// Pending deliveries per issue, behind `emale queue stats`.
+#[tracing::instrument(skip_all)]
+pub async fn queue_stats(pool: &PgPool) -> Result<Vec<IssueQueueStats>, anyhow::Error> {
+    let stats = sqlx::query_as!(
+        IssueQueueStats,
+        r#"
+			SELECT
+				q.newsletter_issue_id,
+				i.title,
+				COUNT(*) AS "pending_deliveries!"
+			FROM issue_delivery_queue q
+			JOIN newsletter_issues i USING (newsletter_issue_id)
+			GROUP BY q.newsletter_issue_id, i.title
+			ORDER BY q.newsletter_issue_id
+		"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to compute the queue stats")?;
+    Ok(stats)
+}
+
+// Queues the issue again for every confirmed subscriber, or only for
+// `subscriber_email`, e.g. after a delivery failed. Deliveries that are
+// still pending are not duplicated, cancelled issues are left alone.
+// Returns the number of new deliveries.
+#[tracing::instrument(skip(pool))]
+pub async fn requeue_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_email: Option<&str>,
+) -> Result<u64, anyhow::Error> {
+    let queued = sqlx::query!(
+        r#"
+			INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, publication_id)
+			SELECT i.newsletter_issue_id, s.email, i.publication_id
+			FROM newsletter_issues i, subscriber s
+			WHERE
+				i.newsletter_issue_id = $1 AND
+				i.delivery_status <> 'cancelled' AND
+				s.publication_id = i.publication_id AND
+				s.status = 'confirmed' AND
+				($2::TEXT IS NULL OR s.email = $2) AND
+				NOT EXISTS (
+					SELECT 1 FROM subscriber_list_opt_outs o
+					WHERE o.subscriber_id = s.id AND o.list_id = i.list_id
+				)
+			ON CONFLICT DO NOTHING
+		"#,
+        issue_id,
+        subscriber_email
+    )
+    .execute(pool)
+    .await
+    .context("Failed to requeue the issue")?
+    .rows_affected();
+    Ok(queued)
+}
+
+async fn set_delivery_status(
+    pool: &PgPool,
+    publication_id: &str,
+    issue_id: Uuid,
+    from: &str,
+    to: &str,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+			UPDATE newsletter_issues
+			SET delivery_status = $3
+			WHERE
+				newsletter_issue_id = $1 AND
+				delivery_status = $2 AND
+				publication_id = $4
+		"#,
+        issue_id,
+        from,
+        to,
+        publication_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the delivery status")?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+// The pending deliveries stay in the queue until the issue is resumed.
+// Returns false unless the issue of the publication was being sent.
+#[tracing::instrument(skip(pool))]
+pub async fn pause_issue_delivery(
+    pool: &PgPool,
+    publication_id: &str,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    set_delivery_status(pool, publication_id, issue_id, "sending", "paused").await
+}
+
+// Returns false unless the issue of the publication was paused.
+#[tracing::instrument(skip(pool))]
+pub async fn resume_issue_delivery(
+    pool: &PgPool,
+    publication_id: &str,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    set_delivery_status(pool, publication_id, issue_id, "paused", "sending").await
+}
+
+pub struct CancelledDelivery {
+    pub sent_deliveries: i32,
+    pub cancelled_deliveries: i32,
+}
+
+// Purges the pending deliveries of the issue, waiting for the ones being
+// sent. `None` if the publication has no such issue or it was already
+// cancelled.
+#[tracing::instrument(skip(pool))]
+pub async fn cancel_issue_delivery(
+    pool: &PgPool,
+    publication_id: &str,
+    issue_id: Uuid,
+) -> Result<Option<CancelledDelivery>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // the queue first: workers lock their delivery, then the issue
+    let purged = sqlx::query!(
+        r#"
+			DELETE FROM issue_delivery_queue
+			WHERE newsletter_issue_id = $1 AND publication_id = $2
+		"#,
+        issue_id,
+        publication_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to purge the pending deliveries")?
+    .rows_affected();
+    let cancelled = sqlx::query_as!(
+        CancelledDelivery,
+        r#"
+			UPDATE newsletter_issues
+			SET
+				delivery_status = 'cancelled',
+				cancelled_deliveries = $2,
+				cancelled_at = now()
+			WHERE
+				newsletter_issue_id = $1 AND
+				delivery_status <> 'cancelled' AND
+				publication_id = $3
+			RETURNING sent_deliveries, cancelled_deliveries AS "cancelled_deliveries!"
+		"#,
+        issue_id,
+        purged as i32,
+        publication_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to cancel the issue")?;
+    transaction.commit().await?;
+    Ok(cancelled)
+}
 
 struct NewsletterIssue {
     title: String,
@@ -14,17 +226,33 @@ struct NewsletterIssue {
     html_content: String,
 }
 
+// The translation matching the locale of the subscriber, the issue
+// itself if there is none.
 #[tracing::instrument(skip_all)]
-async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<NewsletterIssue, anyhow::Error> {
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
-			SELECT title, text_content, html_content
-			FROM newsletter_issues
+			SELECT
+				COALESCE(v.title, i.title) AS "title!",
+				COALESCE(v.text_content, i.text_content) AS "text_content!",
+				COALESCE(v.html_content, i.html_content) AS "html_content!"
+			FROM newsletter_issues i
+			LEFT JOIN newsletter_issue_variants v ON
+				v.newsletter_issue_id = i.newsletter_issue_id AND
+				v.locale = (
+					SELECT locale FROM subscriber
+					WHERE email = $2 AND publication_id = i.publication_id
+				)
 			WHERE
-				newsletter_issue_id = $1
+				i.newsletter_issue_id = $1
 		"#,
-        issue_id
+        issue_id,
+        subscriber_email
     )
     .fetch_one(pool)
     .await?;
@@ -47,35 +275,69 @@ pub enum ExecutionOutcome {
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    shutdown: &ShutdownState,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(pool).await?;
     if task.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
+    let (transaction, task) = task.unwrap();
+    // the task runs in its own trace, linked to the request that published the issue
+    if let Some(traceparent) = &task.traceparent {
+        link_to_traceparent(traceparent);
+    }
     Span::current()
-        .record("newesletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
+        .record("newesletter_issue_id", &display(task.issue_id))
+        .record("subscriber_email", &display(&task.subscriber_email));
+    // once the email is handed to the provider the delivery has to be
+    // committed, even if the worker is dropped past the shutdown deadline:
+    // rolled back, it would be sent again by the next worker
+    let delivery = deliver(pool.clone(), email_client.clone(), transaction, task);
+    shielded(delivery.instrument(Span::current()), shutdown).await??;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn deliver(
+    pool: PgPool,
+    email_client: EmailClient,
+    mut transaction: PgTransaction,
+    task: Task,
+) -> Result<(), anyhow::Error> {
+    let Task { issue_id, subscriber_email: email, sender_email, .. } = task;
     match SubscriberEmail::parse(email.clone()) {
         Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
-                .await
-            {
-                tracing::error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issueto a confirmed subscriber"
-                );
+            let issue = get_issue(&pool, issue_id, email.as_ref()).await?;
+            // sent from the address of the publication of the issue
+            let outcome = match sender_email_client(&email_client, sender_email) {
+                Ok(email_client) => email_client
+                    .send_email(
+                        &email,
+                        &issue.title,
+                        &issue.html_content,
+                        &issue.text_content,
+                    )
+                    .await
+                    .map_err(anyhow::Error::from),
+                Err(e) => Err(e),
+            };
+            match outcome {
+                Ok(()) => {
+                    record_delivery(issue_id, "sent");
+                    count_sent_delivery(&mut transaction, issue_id).await?;
+                }
+                Err(e) => {
+                    record_delivery(issue_id, "failed");
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issueto a confirmed subscriber"
+                    );
+                }
             }
         }
         Err(e) => {
+            record_delivery(issue_id, "skipped");
             tracing::error!(
                 error.cause_chain = ?e,
                 error.message = %e,
@@ -83,23 +345,35 @@ pub async fn try_execute_task(
             )
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
-
-    Ok(ExecutionOutcome::TaskCompleted)
+    delete_task(transaction, issue_id, &email).await
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct Task {
+    issue_id: Uuid,
+    subscriber_email: String,
+    traceparent: Option<String>,
+    // `None` to send from the configured address
+    sender_email: Option<String>,
+}
+
+// Only the queue rows are locked, the deliveries of paused and cancelled
+// issues are skipped. A delivery picked up right before the issue is
+// paused is still sent.
 #[tracing::instrument(skip_all)]
 async fn dequeue_task(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
     let r = sqlx::query!(
         r#"
-			SELECT newsletter_issue_id, subscriber_email
-			FROM issue_delivery_queue
-			FOR UPDATE
+			SELECT q.newsletter_issue_id, q.subscriber_email, q.traceparent, p.sender_email
+			FROM issue_delivery_queue q
+			JOIN newsletter_issues i USING (newsletter_issue_id)
+			JOIN publications p ON p.publication_id = i.publication_id
+			WHERE i.delivery_status = 'sending'
+			FOR UPDATE OF q
 			SKIP LOCKED
 			LIMIT 1
 		"#,
@@ -109,14 +383,36 @@ async fn dequeue_task(
     if let Some(r) = r {
         Ok(Some((
             transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
+            Task {
+                issue_id: r.newsletter_issue_id,
+                subscriber_email: r.subscriber_email,
+                traceparent: r.traceparent,
+                sender_email: r.sender_email,
+            },
         )))
     } else {
         Ok(None)
     }
 }
 
+#[tracing::instrument(skip_all)]
+async fn count_sent_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+			UPDATE newsletter_issues
+			SET sent_deliveries = sent_deliveries + 1
+			WHERE newsletter_issue_id = $1
+		"#,
+        issue_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
 #[tracing::instrument(skip_all)]
 async fn delete_task(
     mut transaction: PgTransaction,
@@ -139,22 +435,199 @@ async fn delete_task(
     Ok(())
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+// Upserted by `worker_loop` at most every `HEARTBEAT_INTERVAL`,
+// `/health/ready` checks that one of the workers is still alive.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Automation steps are due by the day, checking every minute is plenty.
+const SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[tracing::instrument(skip(pool))]
+pub async fn record_worker_heartbeat(pool: &PgPool, worker_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+			INSERT INTO worker_heartbeats (worker_id, last_seen_at)
+			VALUES ($1, now())
+			ON CONFLICT (worker_id) DO UPDATE
+			SET last_seen_at = EXCLUDED.last_seen_at
+		"#,
+        worker_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the worker heartbeat")?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TaskKind {
+    OutboxEmail,
+    IssueDelivery,
+    AutomationStep,
+    Webhook,
+}
+
+impl TaskKind {
+    const ALL: [TaskKind; 4] = [
+        TaskKind::OutboxEmail,
+        TaskKind::IssueDelivery,
+        TaskKind::AutomationStep,
+        TaskKind::Webhook,
+    ];
+
+    // Every kind, starting from the one whose turn it is.
+    fn in_turn(turn: usize) -> impl Iterator<Item = TaskKind> {
+        (0..Self::ALL.len()).map(move |i| Self::ALL[(turn + i) % Self::ALL.len()])
+    }
+}
+
+async fn try_execute_kind(
+    kind: TaskKind,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    webhook_client: &reqwest::Client,
+    shutdown: &ShutdownState,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    match kind {
+        TaskKind::OutboxEmail => try_send_outbox_email(pool, email_client).await,
+        TaskKind::IssueDelivery => try_execute_task(pool, email_client, shutdown).await,
+        TaskKind::AutomationStep => try_execute_step_task(pool, email_client).await,
+        TaskKind::Webhook => try_deliver_webhook(pool, webhook_client).await,
+    }
+}
+
+// The task kinds take turns, so that a large newsletter send holds back
+// neither the transactional emails nor the webhooks. `turn` moves past
+// the kind that ran. A kind that fails does not stop the others, its
+// error is returned once none of them had a task to run.
+async fn try_execute_next_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    webhook_client: &reqwest::Client,
+    shutdown: &ShutdownState,
+    turn: &mut usize,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut error = None;
+    for (i, kind) in TaskKind::in_turn(*turn).enumerate() {
+        match try_execute_kind(kind, pool, email_client, webhook_client, shutdown).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {
+                *turn = (*turn + i + 1) % TaskKind::ALL.len();
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+            Ok(ExecutionOutcome::EmptyQueue) => {}
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    task_kind = ?kind,
+                    "Failed to execute a task"
+                );
+                error = Some(e);
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(ExecutionOutcome::EmptyQueue),
+    }
+}
+
+// Stops picking up tasks once shutdown has begun. The current task is not
+// interrupted: it commits, or, if the future is dropped because the shutdown
+// deadline elapsed, its transaction is rolled back and the task stays in
+// the queue for the next worker. Issue deliveries are the exception, see
+// `try_execute_task`.
+pub async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    shutdown: ShutdownState,
+) -> Result<(), anyhow::Error> {
+    let worker_id = Uuid::new_v4();
+    let mut last_heartbeat: Option<std::time::Instant> = None;
+    let mut last_schedule: Option<std::time::Instant> = None;
+    let webhook_client = webhook_client();
+    let mut turn = 0;
+    while !shutdown.is_shutting_down() {
+        if last_heartbeat.map_or(true, |at| at.elapsed() >= HEARTBEAT_INTERVAL) {
+            // a failed heartbeat is not a reason to stop delivering
+            match record_worker_heartbeat(&pool, worker_id).await {
+                Ok(()) => last_heartbeat = Some(std::time::Instant::now()),
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Failed to record the worker heartbeat"
+                ),
             }
-			Ok(ExecutionOutcome::TaskCompleted) => {}
-            Err(_) => {
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        if last_schedule.map_or(true, |at| at.elapsed() >= SCHEDULE_INTERVAL) {
+            // the due steps are picked up again on the next run
+            match schedule_due_steps(&pool).await {
+                Ok(_) => last_schedule = Some(std::time::Instant::now()),
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Failed to schedule the automation steps"
+                ),
             }
         }
+        let next_task =
+            try_execute_next_task(&pool, &email_client, &webhook_client, &shutdown, &mut turn);
+        let idle_for = match next_task.await {
+            Ok(ExecutionOutcome::EmptyQueue) => std::time::Duration::from_secs(10),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            Err(_) => std::time::Duration::from_secs(1),
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(idle_for) => {}
+            _ = shutdown.wait() => {}
+        }
     }
+    tracing::info!("Delivery worker stopped");
+    Ok(())
 }
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: ShutdownState,
+) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
     let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    let deadline = configuration.shutdown.deadline();
+    let worker = worker_loop(connection_pool, email_client, shutdown.clone());
+    match with_deadline(worker, &shutdown, deadline).await {
+        Some(outcome) => outcome,
+        None => {
+            tracing::warn!("The current task did not complete before the shutdown deadline");
+            // an email already handed to the provider is still recorded
+            in_flight_drained(&shutdown).await;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TaskKind;
+
+    #[test]
+    fn every_kind_gets_a_turn() {
+        for turn in 0..TaskKind::ALL.len() {
+            let kinds: Vec<_> = TaskKind::in_turn(turn).collect();
+            assert_eq!(kinds[0], TaskKind::ALL[turn]);
+            for kind in TaskKind::ALL {
+                assert!(kinds.contains(&kind));
+            }
+        }
+    }
+
+    #[test]
+    fn turns_wrap_around() {
+        let kinds: Vec<_> = TaskKind::in_turn(3).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TaskKind::Webhook,
+                TaskKind::OutboxEmail,
+                TaskKind::IssueDelivery,
+                TaskKind::AutomationStep,
+            ]
+        );
+    }
 }
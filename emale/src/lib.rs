@@ -8,4 +8,22 @@ pub mod authentication;
 pub mod session_state;
 pub mod utils;
 pub mod idempotency;
-pub mod issue_delivery_worker;
\ No newline at end of file
+pub mod issue_delivery_worker;
+pub mod audit;
+pub mod templates;
+pub mod metrics;
+pub mod health;
+pub mod shutdown;
+pub mod session_store;
+pub mod subscribe_protection;
+pub mod email_validation;
+pub mod subscriber_data;
+pub mod mailing_lists;
+pub mod preferences;
+pub mod i18n;
+pub mod archive;
+pub mod automation;
+pub mod webhooks;
+pub mod email_outbox;
+pub mod publications;
+pub mod counters;
\ No newline at end of file
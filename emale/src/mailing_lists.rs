@@ -0,0 +1,23 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+// Issues are published to one list. Subscribers receive every list
+// they have not opted out of from their preferences.
+pub const DEFAULT_LIST_ID: &str = "newsletter";
+
+pub struct MailingList {
+	pub list_id: String,
+	pub name: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_mailing_lists(pool: &PgPool) -> Result<Vec<MailingList>, anyhow::Error> {
+	let lists = sqlx::query_as!(
+		MailingList,
+		r#"SELECT list_id, name FROM mailing_lists ORDER BY name"#
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the mailing lists")?;
+	Ok(lists)
+}
@@ -1,33 +1,92 @@
-use tokio::task::JoinError;
+mod cli;
+
+use clap::Parser;
+use tokio::task::{JoinError, JoinHandle};
 use emale::configuration::get_config;
 use emale::issue_delivery_worker::run_worker_until_stopped;
+use emale::shutdown::{wait_for_signal, ShutdownState};
 use emale::startup::Application;
-use emale::telemetry::{get_tracing_subscriber, init_tracing_subscriber};
+use cli::{run_command, Cli, Command};
+use emale::telemetry::{
+    get_otlp_tracer, get_tracing_subscriber, init_tracing_subscriber, shutdown_tracer,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let config = get_config().expect("Failed to get configuration");
+
+    let (run_api, run_worker) = match cli.command {
+        None => (true, true),
+        Some(Command::Serve) => (true, false),
+        Some(Command::Worker) => (false, true),
+        Some(command) => {
+            // one-shot commands only log warnings, on stderr
+            let subscriber = get_tracing_subscriber(
+                config.telemetry.service_name.clone(),
+                "warn".into(),
+                std::io::stderr,
+                None,
+            );
+            init_tracing_subscriber(subscriber);
+            return run_command(command, config).await;
+        }
+    };
+
     // Logger
-    let subscriber = get_tracing_subscriber("emale".into(), "info".into(), std::io::stdout);
+    let tracer = get_otlp_tracer(&config.telemetry)?;
+    let subscriber = get_tracing_subscriber(
+        config.telemetry.service_name.clone(),
+        "info".into(),
+        std::io::stdout,
+        tracer,
+    );
     init_tracing_subscriber(subscriber);
 
     // App
-    let config = get_config().expect("Failed to get configuration");
-    let app = tokio::spawn(Application::build(config.clone())
-		.await?
-		.run_until_stopped());
+    let (shutdown, app) = if run_api {
+        let application = Application::build(config.clone()).await?;
+        let shutdown = application.shutdown_state();
+        (shutdown, Some(tokio::spawn(application.run_until_stopped())))
+    } else {
+        (ShutdownState::default(), None)
+    };
 	// background worker
-	let worker = tokio::spawn(run_worker_until_stopped(config));
-
-	// build the Future for each of the two long-running tasks - Rust Futures are lazy
-	// so nothing happens until they are actually awaited.
-	tokio::select! {
-		o = app => report_exit("API", o),
-		o = worker => report_exit("Background worker", o)
-	};
-	
+	let worker = run_worker
+		.then(|| tokio::spawn(run_worker_until_stopped(config, shutdown.clone())));
+
+	let signal = shutdown.clone();
+	tokio::spawn(async move {
+		wait_for_signal().await;
+		signal.begin();
+	});
+
+	// whichever task stops first, the other one is asked to stop as well
+	// and both are given the chance to finish their current work
+	let (app_outcome, worker_outcome) = tokio::join!(
+		stop_together(app, &shutdown),
+		stop_together(worker, &shutdown)
+	);
+	if let Some(outcome) = app_outcome {
+		report_exit("API", outcome);
+	}
+	if let Some(outcome) = worker_outcome {
+		report_exit("Background worker", outcome);
+	}
+
+	shutdown_tracer();
     Ok(())
 }
 
+async fn stop_together<T>(
+	task: Option<JoinHandle<T>>,
+	shutdown: &ShutdownState
+) -> Option<Result<T, JoinError>> {
+	let outcome = task?.await;
+	shutdown.begin();
+	Some(outcome)
+}
+
 fn report_exit(
 	task_name: &str,
 	outcome: Result<Result<(), impl std::fmt::Debug + std::fmt::Display>, JoinError>
@@ -0,0 +1,173 @@
+use std::time::Instant;
+
+use actix_http::body::MessageBody;
+use actix_http::header::AUTHORIZATION;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_lab::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::issue_delivery_worker::refresh_queue_depth;
+use crate::utils::error_500;
+
+// Every metric is registered in the default prometheus registry,
+// next to the code that updates it.
+
+static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests handled, per route and status code",
+        &["method", "route", "status"]
+    )
+    .expect("Failed to register http_requests_total")
+});
+
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time spent handling HTTP requests, per route",
+        &["method", "route"]
+    )
+    .expect("Failed to register http_request_duration_seconds")
+});
+
+static LOGIN_ATTEMPTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "login_attempts_total",
+        "Number of login attempts, per outcome",
+        &["outcome"]
+    )
+    .expect("Failed to register login_attempts_total")
+});
+
+static EMAIL_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "email_provider_request_duration_seconds",
+        "Latency of the requests sent to the email provider",
+        &["outcome"]
+    )
+    .expect("Failed to register email_provider_request_duration_seconds")
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_connections",
+        "Number of connections currently open in the database pool"
+    )
+    .expect("Failed to register db_pool_connections")
+});
+
+static DB_POOL_IDLE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_idle_connections",
+        "Number of idle connections in the database pool"
+    )
+    .expect("Failed to register db_pool_idle_connections")
+});
+
+#[derive(Clone, Copy, Debug)]
+pub enum LoginOutcome {
+    Success,
+    Failure,
+    LockedOut,
+    Error,
+}
+
+impl LoginOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LoginOutcome::Success => "success",
+            LoginOutcome::Failure => "failure",
+            LoginOutcome::LockedOut => "locked_out",
+            LoginOutcome::Error => "error",
+        }
+    }
+}
+
+pub fn record_login_attempt(outcome: LoginOutcome) {
+    LOGIN_ATTEMPTS_TOTAL
+        .with_label_values(&[outcome.as_str()])
+        .inc();
+}
+
+pub fn record_email_request(started_at: Instant, succeeded: bool) {
+    let outcome = if succeeded { "success" } else { "failure" };
+    EMAIL_REQUEST_DURATION
+        .with_label_values(&[outcome])
+        .observe(started_at.elapsed().as_secs_f64());
+}
+
+// Requests are labelled with the route pattern (e.g. `/admin/sessions/{session_id}/revoke`)
+// rather than the path, to keep the number of series bounded.
+pub async fn track_http_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started_at = Instant::now();
+
+    let outcome = next.call(req).await;
+    let status = match &outcome {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(started_at.elapsed().as_secs_f64());
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    outcome
+}
+
+// Wrapper type, retrieved by type from the application data.
+pub struct MetricsBearerToken(pub Option<Secret<String>>);
+
+fn is_authorized(request: &HttpRequest, token: &MetricsBearerToken) -> bool {
+    let token = match &token.0 {
+        Some(token) => token,
+        None => return true,
+    };
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|h| h == token.expose_secret())
+        .unwrap_or(false)
+}
+
+pub async fn metrics(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    token: web::Data<MetricsBearerToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !is_authorized(&request, &token) {
+        return Ok(HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .finish());
+    }
+
+    // gauges are sampled when scraped
+    DB_POOL_CONNECTIONS.set(pool.size() as i64);
+    DB_POOL_IDLE_CONNECTIONS.set(pool.num_idle() as i64);
+    refresh_queue_depth(&pool).await.map_err(error_500)?;
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut body)
+        .map_err(error_500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body))
+}
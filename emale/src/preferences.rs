@@ -0,0 +1,331 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::i18n::Locale;
+
+// The preferences page of a subscriber is reached through a link signed
+// with the HMAC secret, it can be handed out in every email and does not
+// expire. Email changes need a second, short-lived link sent to the new
+// address before `subscriber.email` is switched.
+
+const EMAIL_CHANGE_TOKEN_LENGTH: usize = 32;
+const EMAIL_CHANGE_TOKEN_TTL_HOURS: i64 = 24;
+
+fn mac(secret: &Secret<String>, subscriber_id: Uuid) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+		.expect("HMAC can take a key of any size");
+	mac.update(format!("preferences:{}", subscriber_id).as_bytes());
+	mac
+}
+
+pub fn preferences_signature(secret: &Secret<String>, subscriber_id: Uuid) -> String {
+	hex::encode(mac(secret, subscriber_id).finalize().into_bytes())
+}
+
+pub fn is_preferences_signature_valid(
+	secret: &Secret<String>,
+	subscriber_id: Uuid,
+	signature: &str
+) -> bool {
+	match hex::decode(signature) {
+		Ok(signature) => mac(secret, subscriber_id).verify_slice(&signature).is_ok(),
+		Err(_) => false,
+	}
+}
+
+pub fn preferences_path(secret: &Secret<String>, subscriber_id: Uuid) -> String {
+	format!(
+		"/preferences?subscriber_id={}&signature={}",
+		subscriber_id,
+		preferences_signature(secret, subscriber_id)
+	)
+}
+
+pub fn preferences_link(base_url: &str, secret: &Secret<String>, subscriber_id: Uuid) -> String {
+	format!("{}{}", base_url, preferences_path(secret, subscriber_id))
+}
+
+pub struct ListPreference {
+	pub list_id: String,
+	pub name: String,
+	pub receives: bool,
+}
+
+pub struct SubscriberPreferences {
+	pub name: String,
+	pub email: String,
+	pub locale: Locale,
+	pub lists: Vec<ListPreference>,
+	// waiting for the new address to be confirmed
+	pub pending_email: Option<String>,
+}
+
+#[tracing::instrument(name = "Get subscriber preferences", skip(pool))]
+pub async fn get_preferences(
+	subscriber_id: Uuid,
+	pool: &PgPool
+) -> Result<Option<SubscriberPreferences>, anyhow::Error> {
+	let subscriber = match sqlx::query!(
+		r#"SELECT name, email, locale FROM subscriber WHERE id = $1"#,
+		subscriber_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to retrieve the subscriber")?
+	{
+		Some(subscriber) => subscriber,
+		None => return Ok(None),
+	};
+	let lists = sqlx::query!(
+		r#"
+		SELECT
+			l.list_id,
+			l.name,
+			o.subscriber_id IS NULL AS "receives!"
+		FROM mailing_lists l
+		LEFT JOIN subscriber_list_opt_outs o
+			ON o.list_id = l.list_id AND o.subscriber_id = $1
+		ORDER BY l.name
+		"#,
+		subscriber_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the list preferences")?
+	.into_iter()
+	.map(|r| ListPreference {
+		list_id: r.list_id,
+		name: r.name,
+		receives: r.receives,
+	})
+	.collect();
+	let pending_email = sqlx::query!(
+		r#"
+		SELECT new_email
+		FROM subscriber_email_changes
+		WHERE subscriber_id = $1 AND expires_at > now()
+		"#,
+		subscriber_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to retrieve the pending email change")?
+	.map(|r| r.new_email);
+	Ok(Some(SubscriberPreferences {
+		name: subscriber.name,
+		email: subscriber.email,
+		locale: Locale::parse(&subscriber.locale).unwrap_or_default(),
+		lists,
+		pending_email,
+	}))
+}
+
+// Replaces the name, the locale and the opt-outs of the subscriber:
+// every list missing from `received_lists` is opted out of.
+#[tracing::instrument(name = "Update subscriber preferences", skip(pool))]
+pub async fn update_preferences(
+	subscriber_id: Uuid,
+	name: &SubscriberName,
+	locale: Locale,
+	received_lists: &[String],
+	pool: &PgPool
+) -> Result<(), anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	sqlx::query!(
+		r#"UPDATE subscriber SET name = $2, locale = $3 WHERE id = $1"#,
+		subscriber_id,
+		name.as_ref(),
+		locale.as_str()
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to update the subscriber name and locale")?;
+	sqlx::query!(
+		r#"DELETE FROM subscriber_list_opt_outs WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to clear the list opt-outs")?;
+	sqlx::query!(
+		r#"
+		INSERT INTO subscriber_list_opt_outs (subscriber_id, list_id)
+		SELECT $1, list_id
+		FROM mailing_lists
+		WHERE list_id <> ALL($2)
+		"#,
+		subscriber_id,
+		received_lists
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to store the list opt-outs")?;
+	transaction.commit().await?;
+	Ok(())
+}
+
+fn generate_email_change_token() -> String {
+	let mut rng = thread_rng();
+	std::iter::repeat_with(|| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(EMAIL_CHANGE_TOKEN_LENGTH)
+		.collect()
+}
+
+fn hash_email_change_token(token: &str) -> String {
+	hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Replaces any change still waiting for confirmation. Returns the token
+// of the confirmation link.
+#[tracing::instrument(name = "Request email change", skip(pool))]
+pub async fn request_email_change(
+	subscriber_id: Uuid,
+	new_email: &SubscriberEmail,
+	pool: &PgPool
+) -> Result<String, anyhow::Error> {
+	let token = generate_email_change_token();
+	let mut transaction = pool.begin().await?;
+	sqlx::query!(
+		r#"DELETE FROM subscriber_email_changes WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to discard the previous email change")?;
+	sqlx::query!(
+		r#"
+		INSERT INTO subscriber_email_changes
+			(token_hash, subscriber_id, new_email, created_at, expires_at)
+		VALUES ($1, $2, $3, now(), now() + make_interval(hours => $4))
+		"#,
+		hash_email_change_token(&token),
+		subscriber_id,
+		new_email.as_ref(),
+		EMAIL_CHANGE_TOKEN_TTL_HOURS as i32
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to store the email change")?;
+	transaction.commit().await?;
+	Ok(token)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EmailChangeOutcome {
+	Changed { subscriber_id: Uuid },
+	AddressTaken { subscriber_id: Uuid },
+	InvalidToken,
+}
+
+// Switches `subscriber.email`, and the pending deliveries along with it,
+// to the confirmed address. A token can only be used once.
+#[tracing::instrument(name = "Confirm email change", skip(token, pool))]
+pub async fn confirm_email_change(
+	token: &str,
+	pool: &PgPool
+) -> Result<EmailChangeOutcome, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	let change = match sqlx::query!(
+		r#"
+		DELETE FROM subscriber_email_changes
+		WHERE token_hash = $1 AND expires_at > now()
+		RETURNING subscriber_id, new_email
+		"#,
+		hash_email_change_token(token)
+	)
+	.fetch_optional(&mut transaction)
+	.await
+	.context("Failed to consume the email change token")?
+	{
+		Some(change) => change,
+		None => return Ok(EmailChangeOutcome::InvalidToken),
+	};
+	// addresses are unique within a publication
+	let taken = sqlx::query!(
+		r#"
+		SELECT id FROM subscriber
+		WHERE lower(email) = lower($1)
+			AND id <> $2
+			AND publication_id = (SELECT publication_id FROM subscriber WHERE id = $2)
+		"#,
+		change.new_email,
+		change.subscriber_id
+	)
+	.fetch_optional(&mut transaction)
+	.await
+	.context("Failed to check whether the address is taken")?
+	.is_some();
+	if taken {
+		transaction.commit().await?;
+		return Ok(EmailChangeOutcome::AddressTaken {
+			subscriber_id: change.subscriber_id,
+		});
+	}
+	let subscriber = sqlx::query!(
+		r#"SELECT email, publication_id FROM subscriber WHERE id = $1 FOR UPDATE"#,
+		change.subscriber_id
+	)
+	.fetch_one(&mut transaction)
+	.await
+	.context("Failed to lock the subscriber")?;
+	sqlx::query!(
+		r#"UPDATE subscriber SET email = $2 WHERE id = $1"#,
+		change.subscriber_id,
+		change.new_email
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to switch the subscriber email")?;
+	sqlx::query!(
+		r#"
+		UPDATE issue_delivery_queue
+		SET subscriber_email = $2
+		WHERE subscriber_email = $1 AND publication_id = $3
+		"#,
+		subscriber.email,
+		change.new_email,
+		subscriber.publication_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to move the pending deliveries to the new address")?;
+	transaction.commit().await?;
+	Ok(EmailChangeOutcome::Changed {
+		subscriber_id: change.subscriber_id,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{is_preferences_signature_valid, preferences_signature};
+	use secrecy::Secret;
+	use uuid::Uuid;
+
+	fn secret() -> Secret<String> {
+		Secret::new("preferences-secret".into())
+	}
+
+	#[test]
+	fn signatures_are_tied_to_the_subscriber() {
+		let subscriber_id = Uuid::new_v4();
+		let signature = preferences_signature(&secret(), subscriber_id);
+		assert!(is_preferences_signature_valid(&secret(), subscriber_id, &signature));
+		assert!(!is_preferences_signature_valid(&secret(), Uuid::new_v4(), &signature));
+	}
+
+	#[test]
+	fn signatures_depend_on_the_secret() {
+		let subscriber_id = Uuid::new_v4();
+		let signature = preferences_signature(&Secret::new("other".into()), subscriber_id);
+		assert!(!is_preferences_signature_valid(&secret(), subscriber_id, &signature));
+		assert!(!is_preferences_signature_valid(&secret(), subscriber_id, "not-hex"));
+	}
+}
@@ -0,0 +1,211 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_http::HttpMessage;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{error_500, request_host};
+
+// Several independent newsletters are hosted in one deployment. Each
+// publication has its own subscribers, issues, sequences, webhooks, audit
+// log and admin members; requests are routed to the publication of their
+// host, the default one when no publication claims it. Mailing lists and
+// the admin accounts themselves are shared.
+pub const DEFAULT_PUBLICATION_ID: &str = "default";
+
+#[derive(Clone, Debug)]
+pub struct Publication {
+	pub publication_id: String,
+	pub name: String,
+	// the application base URL unless the publication has its own
+	pub base_url: String,
+	sender_email: Option<String>,
+}
+
+impl Publication {
+	// Emails of the publication are sent from its own address, from the
+	// configured one otherwise.
+	pub fn email_client(&self, email_client: &EmailClient) -> Result<EmailClient, anyhow::Error> {
+		sender_email_client(email_client, self.sender_email.clone())
+	}
+}
+
+pub fn sender_email_client(
+	email_client: &EmailClient,
+	sender_email: Option<String>
+) -> Result<EmailClient, anyhow::Error> {
+	match sender_email {
+		Some(sender_email) => {
+			let sender = SubscriberEmail::parse(sender_email).map_err(|e| anyhow::anyhow!(e))?;
+			Ok(email_client.with_sender(sender))
+		}
+		None => Ok(email_client.clone()),
+	}
+}
+
+// The `Host` header without its port, lowercased.
+fn host_name(host: &str) -> String {
+	let name = match host.rsplit_once(':') {
+		// an IPv6 address without a port has colons too
+		Some((name, port)) if !name.is_empty() && !port.contains(']') => name,
+		_ => host,
+	};
+	name.to_lowercase()
+}
+
+#[tracing::instrument(name = "Get publication for host", skip(pool, default_base_url))]
+pub async fn get_publication_for_host(
+	pool: &PgPool,
+	host: &str,
+	default_base_url: &str
+) -> Result<Publication, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT publication_id, name, base_url, sender_email
+		FROM publications
+		WHERE host = $1 OR publication_id = $2
+		ORDER BY host = $1 DESC NULLS LAST
+		LIMIT 1
+		"#,
+		host,
+		DEFAULT_PUBLICATION_ID
+	)
+	.fetch_one(pool)
+	.await
+	.context("Failed to retrieve the publication")?;
+	Ok(Publication {
+		publication_id: row.publication_id,
+		name: row.name,
+		base_url: row.base_url.unwrap_or_else(|| default_base_url.to_owned()),
+		sender_email: row.sender_email,
+	})
+}
+
+// The publication of the request host, looked up once per request.
+impl FromRequest for Publication {
+	type Error = actix_web::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+	fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+		let req = req.clone();
+		Box::pin(async move {
+			if let Some(publication) = req.extensions().get::<Publication>() {
+				return Ok(publication.clone());
+			}
+			let pool = req
+				.app_data::<web::Data<PgPool>>()
+				.ok_or_else(|| error_500("Missing database pool"))?;
+			let base_url = req
+				.app_data::<web::Data<ApplicationBaseUrl>>()
+				.ok_or_else(|| error_500("Missing application base URL"))?;
+			let host = host_name(&request_host(&req));
+			let publication = get_publication_for_host(pool, &host, &base_url.0)
+				.await
+				.map_err(error_500)?;
+			req.extensions_mut().insert(publication.clone());
+			Ok(publication)
+		})
+	}
+}
+
+#[tracing::instrument(name = "Check publication membership", skip(pool))]
+pub async fn is_member(
+	pool: &PgPool,
+	publication_id: &str,
+	user_id: Uuid
+) -> Result<bool, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT 1 AS "member!"
+		FROM publication_members
+		WHERE publication_id = $1 AND user_id = $2
+		"#,
+		publication_id,
+		user_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to check the publication membership")?;
+	Ok(row.is_some())
+}
+
+pub struct NewPublication<'a> {
+	pub publication_id: &'a str,
+	pub name: &'a str,
+	pub host: Option<&'a str>,
+	pub base_url: Option<&'a str>,
+	pub sender_email: Option<&'a str>,
+}
+
+#[tracing::instrument(name = "Create publication", skip(pool, publication))]
+pub async fn create_publication(
+	pool: &PgPool,
+	publication: NewPublication<'_>
+) -> Result<(), anyhow::Error> {
+	let sender_email = publication
+		.sender_email
+		.map(|e| SubscriberEmail::parse(e.to_owned()).map(|e| e.to_string()))
+		.transpose()
+		.map_err(|e| anyhow::anyhow!(e))?;
+	let base_url = publication
+		.base_url
+		.map(|url| url.trim_end_matches('/').to_owned());
+	sqlx::query!(
+		r#"
+		INSERT INTO publications (publication_id, name, host, base_url, sender_email, created_at)
+		VALUES ($1, $2, $3, $4, $5, now())
+		"#,
+		publication.publication_id,
+		publication.name,
+		publication.host.map(host_name),
+		base_url,
+		sender_email
+	)
+	.execute(pool)
+	.await
+	.context("Failed to create the publication")?;
+	Ok(())
+}
+
+// Returns false if the user already was a member.
+#[tracing::instrument(name = "Add publication member", skip(pool))]
+pub async fn add_member(
+	pool: &PgPool,
+	publication_id: &str,
+	user_id: Uuid
+) -> Result<bool, anyhow::Error> {
+	let added = sqlx::query!(
+		r#"
+		INSERT INTO publication_members (publication_id, user_id)
+		VALUES ($1, $2)
+		ON CONFLICT DO NOTHING
+		"#,
+		publication_id,
+		user_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to add the publication member")?
+	.rows_affected();
+	Ok(added == 1)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::host_name;
+
+	#[test]
+	fn the_port_is_not_part_of_the_host() {
+		assert_eq!(host_name("News.Example.com:8000"), "news.example.com");
+		assert_eq!(host_name("news.example.com"), "news.example.com");
+		assert_eq!(host_name("[::1]:8000"), "[::1]");
+		assert_eq!(host_name("[::1]"), "[::1]");
+	}
+}
@@ -0,0 +1,70 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::audit::{get_audit_entries, AuditAction, AuditEntry};
+use crate::authentication::get_or_create_csrf_token;
+use crate::publications::Publication;
+use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
+use crate::utils::{error_400, error_500};
+
+const AUDIT_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Deserialize)]
+pub struct AuditFilter {
+	action: Option<String>,
+	actor: Option<String>,
+}
+
+// Empty form fields mean "no filter"
+fn non_empty(value: Option<String>) -> Option<String> {
+	value.filter(|v| !v.trim().is_empty())
+}
+
+#[derive(Template)]
+#[template(path = "admin/audit.html")]
+struct AuditLogTemplate<'a> {
+	layout: Layout,
+	// every action, with whether it is the one being filtered on
+	actions: Vec<(&'static str, bool)>,
+	actor: &'a str,
+	entries: Vec<AuditEntry>,
+}
+
+pub async fn audit_log(
+	filter: web::Query<AuditFilter>,
+	session: TypedSession,
+	pool: web::Data<PgPool>,
+	publication: Publication,
+	flash_messages: IncomingFlashMessages
+) -> Result<HttpResponse, actix_web::Error> {
+	let AuditFilter { action, actor } = filter.into_inner();
+	let action: Option<AuditAction> = non_empty(action)
+		.map(AuditAction::try_from)
+		.transpose()
+		.map_err(error_400)?;
+	let actor = non_empty(actor);
+
+	let entries = get_audit_entries(
+		&pool,
+		&publication.publication_id,
+		action,
+		actor.as_deref(),
+		AUDIT_PAGE_SIZE
+	)
+		.await
+		.map_err(error_500)?;
+	let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+
+	render(&AuditLogTemplate {
+		layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+		actions: AuditAction::ALL
+			.iter()
+			.map(|a| (a.as_str(), Some(*a) == action))
+			.collect(),
+		actor: actor.as_deref().unwrap_or(""),
+		entries,
+	})
+}
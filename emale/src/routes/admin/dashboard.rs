@@ -1,9 +1,13 @@
-use actix_web::{HttpResponse, web, http::header::ContentType};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::authentication::get_or_create_csrf_token;
 use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
 use crate::utils::{error_500, see_other};
 
 #[tracing::instrument(
@@ -28,9 +32,17 @@ pub async fn get_username(
 	Ok(row.username)
 }
 
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate {
+	layout: Layout,
+	username: String
+}
+
 pub async fn admin_dashboard(
 	session: TypedSession,
-	pool: web::Data<PgPool>
+	pool: web::Data<PgPool>,
+	flash_messages: IncomingFlashMessages
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) = session
 		.get_user_id()
@@ -41,29 +53,11 @@ pub async fn admin_dashboard(
 		return Ok(see_other("/login"))
 		
 	};
-	Ok(HttpResponse::Ok()
-		.content_type(ContentType::html())
-		.body(format!(
-			r#"<!DOCTYPE html>
-					<html lang="en">
-					<head>
-					<meta http-equiv="content-type" content="text/html; charset=utf-8">
-					<title>Admin dashboard</title>
-					</head>
-					<body>
-						<p>Welcome {username}!</p>
-						<ol>
-							<li><a href="/admin/password/">Change Password</a></li>
-              				<li>
-              				  <form name="logoutForm" action="/admin/logout" method="post">
-									<input type="submit" value="logout">
-              				  </form>
-              				</li>
-						</ol>
-					</body>
-				</html>"#
-		))
-	)
+	let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+	render(&DashboardTemplate {
+		layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+		username
+	})
 }
 
 // preserving error 500 root cause for logging
@@ -0,0 +1,32 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::archive::{get_issue_summaries, IssueSummary};
+use crate::authentication::get_or_create_csrf_token;
+use crate::publications::Publication;
+use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "admin/issues.html")]
+struct IssuesTemplate {
+    layout: Layout,
+    issues: Vec<IssueSummary>,
+}
+
+pub async fn list_issues(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    let issues = get_issue_summaries(&pool, &publication.publication_id).await.map_err(error_500)?;
+    render(&IssuesTemplate {
+        layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+        issues,
+    })
+}
@@ -0,0 +1,128 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::archive::set_issue_private;
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::authentication::UserId;
+use crate::issue_delivery_worker::{
+    cancel_issue_delivery, pause_issue_delivery, resume_issue_delivery,
+};
+use crate::publications::Publication;
+use crate::utils::{client_ip, error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct VisibilityForm {
+    private: bool,
+}
+
+pub async fn change_issue_visibility(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    form: web::Form<VisibilityForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let issue_id = path.into_inner();
+    if !set_issue_private(&pool, &publication.publication_id, issue_id, form.private)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::error("The issue does not exist").send();
+        return Ok(see_other("/admin/issues"));
+    }
+    let visibility = if form.private { "private" } else { "public" };
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::IssueVisibilityChange,
+        Some(&format!("{} ({})", issue_id, visibility)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info(format!("The issue is now {}", visibility)).send();
+    Ok(see_other("/admin/issues"))
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+impl DeliveryAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryAction::Pause => "pause",
+            DeliveryAction::Resume => "resume",
+            DeliveryAction::Cancel => "cancel",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeliveryForm {
+    action: DeliveryAction,
+}
+
+pub async fn change_issue_delivery(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    form: web::Form<DeliveryForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let issue_id = path.into_inner();
+    let publication_id = &publication.publication_id;
+    let action = form.action;
+    let outcome = match action {
+        DeliveryAction::Pause => pause_issue_delivery(&pool, publication_id, issue_id)
+            .await
+            .map_err(error_500)?
+            .then(|| "The delivery is paused".to_owned())
+            .ok_or("Only issues being sent can be paused"),
+        DeliveryAction::Resume => resume_issue_delivery(&pool, publication_id, issue_id)
+            .await
+            .map_err(error_500)?
+            .then(|| "The delivery has resumed".to_owned())
+            .ok_or("Only paused issues can be resumed"),
+        DeliveryAction::Cancel => cancel_issue_delivery(&pool, publication_id, issue_id)
+            .await
+            .map_err(error_500)?
+            .map(|cancelled| {
+                format!(
+                    "The delivery is cancelled: {} emails had been sent, {} will not be",
+                    cancelled.sent_deliveries, cancelled.cancelled_deliveries
+                )
+            })
+            .ok_or("The issue does not exist or is already cancelled"),
+    };
+    let message = match outcome {
+        Ok(message) => message,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/issues"));
+        }
+    };
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::IssueDeliveryChange,
+        Some(&format!("{} ({})", issue_id, action.as_str())),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info(message).send();
+    Ok(see_other("/admin/issues"))
+}
@@ -1,15 +1,37 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::authentication::revoke_session;
+use crate::publications::Publication;
 use crate::session_state::TypedSession;
-use crate::utils::{see_other, error_500};
+use crate::utils::{client_ip, see_other, error_500};
 
-pub async fn logout(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-	if session.get_user_id().map_err(error_500)?.is_none() {
-		Ok(see_other("/login"))
-	} else {
+pub async fn logout(
+	request: HttpRequest,
+	session: TypedSession,
+	pool: web::Data<PgPool>,
+	publication: Publication
+) -> Result<HttpResponse, actix_web::Error> {
+	if let Some(user_id) = session.get_user_id().map_err(error_500)? {
+		record_audit_entry(
+			&**pool,
+			user_id,
+			&publication.publication_id,
+			AuditAction::Logout,
+			None,
+			Some(&client_ip(&request))
+		)
+		.await
+		.map_err(error_500)?;
+		if let Some(session_id) = session.get_session_id().map_err(error_500)? {
+			revoke_session(session_id, &pool).await.map_err(error_500)?;
+		}
 		session.log_out();
 		FlashMessage::info("Logged out successfully").send();
 		Ok(see_other("/login"))
+	} else {
+		Ok(see_other("/login"))
 	}
-}
\ No newline at end of file
+}
@@ -2,8 +2,20 @@ mod dashboard;
 mod password;
 mod logout;
 mod newsletter;
+mod two_factor;
+mod sessions;
+mod audit;
+mod issues;
+mod sequences;
+mod webhooks;
 
 pub use dashboard::*;
 pub use password::*;
 pub use logout::*;
 pub use newsletter::*;
+pub use two_factor::*;
+pub use sessions::*;
+pub use audit::*;
+pub use issues::*;
+pub use sequences::*;
+pub use webhooks::*;
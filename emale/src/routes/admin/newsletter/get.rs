@@ -1,60 +1,42 @@
-use actix_web::{HttpResponse, http::header::ContentType};
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::get_or_create_csrf_token;
+use crate::i18n::Locale;
+use crate::mailing_lists::{get_mailing_lists, MailingList, DEFAULT_LIST_ID};
+use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "admin/newsletter.html")]
+struct PublishNewsletterTemplate {
+    layout: Layout,
+    idempotency_key: Uuid,
+    lists: Vec<MailingList>,
+    default_list_id: &'static str,
+    // locales the issue can be translated to
+    translations: Vec<Locale>,
+}
 
 pub async fn publish_newsletter_form(
+    session: TypedSession,
     flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-    let idempotency_key = uuid::Uuid::new_v4();
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-			<!DOCTYPE html>
-			<html lang="en">
-			<head>
-				<meta http-equiv="content-type" content="text/html; charset=utf-8">
-				<title>Publish Newsletter Issue</title>
-			</head>
-			<body>
-				{msg_html}
-				<form action="/admin/newsletters" method="post">
-					<label>Title:<br>
-						<input
-							type="text"
-							placeholder="Enter the issue title"
-							name="title"
-						>
-					</label>
-					<br>
-					<label>Plain text content:<br>
-						<textarea
-							placeholder="Enter the content in plain text"
-							name="text_content"
-							rows="20"
-							cols="50"
-						></textarea>
-					</label>
-					<br>
-					<label>HTML content:<br>
-						<textarea
-							placeholder="Enter the content in HTML format"
-							name="html_content"
-							rows="20"
-							cols="50"
-						></textarea>
-					</label>
-					<br>
-          <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
-					<button type="submit">Publish</button>
-				</form>
-				<p><a href="/admin/dashboard">&lt;- Back</a></p>
-			</body>
-			</html>
-			"#
-        )))
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    let lists = get_mailing_lists(&pool).await.map_err(error_500)?;
+    render(&PublishNewsletterTemplate {
+        layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+        idempotency_key: Uuid::new_v4(),
+        lists,
+        default_list_id: DEFAULT_LIST_ID,
+        translations: Locale::ALL
+            .into_iter()
+            .filter(|locale| *locale != Locale::default())
+            .collect(),
+    })
 }
@@ -1,13 +1,16 @@
+use crate::audit::{record_audit_entry, AuditAction};
 use crate::authentication::UserId;
 use crate::domain::SubscriberEmail;
-use crate::idempotency::{
-    save_response, try_proccesing, IdempotencyKey, NextAction,
-};
-use crate::utils::{error_400, error_500, see_other};
-use actix_web::{web, HttpResponse};
+use crate::i18n::Locale;
+use crate::mailing_lists::{get_mailing_lists, DEFAULT_LIST_ID};
+use crate::publications::Publication;
+use crate::telemetry::current_traceparent;
+use crate::utils::{client_ip, error_400, error_500, see_other};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
 use sqlx::{PgPool, Transaction, Postgres};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -15,19 +18,67 @@ pub struct FormData {
     title: String,
     text_content: String,
     html_content: String,
-    idempotency_key: String,
+    // the default list when missing
+    list_id: Option<String>,
+    // checkbox, kept out of the public archive when checked
+    private: Option<String>,
+    // `title.fr`, `text_content.fr`, `html_content.fr`... for the
+    // translations of the issue
+    #[serde(flatten)]
+    variant_fields: HashMap<String, String>,
+}
+
+struct IssueVariant {
+    locale: Locale,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+// The issue itself is in the default locale. Translations left blank
+// are skipped, partially filled ones are refused.
+fn parse_variants(mut fields: HashMap<String, String>) -> Result<Vec<IssueVariant>, String> {
+    let mut variants = Vec::new();
+    for locale in Locale::ALL.into_iter().filter(|l| *l != Locale::default()) {
+        let mut field = |name: &str| {
+            fields
+                .remove(&format!("{}.{}", name, locale))
+                .map(|value| value.trim().to_owned())
+                .unwrap_or_default()
+        };
+        let (title, text_content, html_content) =
+            (field("title"), field("text_content"), field("html_content"));
+        if title.is_empty() && text_content.is_empty() && html_content.is_empty() {
+            continue;
+        }
+        if title.is_empty() || text_content.is_empty() || html_content.is_empty() {
+            return Err(format!(
+                "The {} translation needs a title, a plain text and an HTML content",
+                locale
+            ));
+        }
+        variants.push(IssueVariant {
+            locale,
+            title,
+            text_content,
+            html_content,
+        });
+    }
+    Ok(variants)
 }
 
 #[tracing::instrument(
 	name = "Publish a newsletter",
-	skip(user_id, pool, form),
+	skip(request, user_id, pool, publication, form),
 	fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 
 pub async fn publish_newsletter(
+    request: HttpRequest,
     form: web::Form<FormData>,
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    publication: Publication,
 ) -> Result<HttpResponse, actix_web::Error> {
     // destructure form to avoid upsetting borrow checker
     let user_id = user_id.into_inner();
@@ -35,43 +86,74 @@ pub async fn publish_newsletter(
         title,
         text_content,
         html_content,
-        idempotency_key,
+        list_id,
+        private,
+        variant_fields,
     } = form.0;
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(error_400)?;
-    let mut transaction = match try_proccesing(&pool, &idempotency_key, *user_id)
+    let variants = parse_variants(variant_fields).map_err(error_400)?;
+    let list_id = list_id.unwrap_or_else(|| DEFAULT_LIST_ID.to_owned());
+    if !get_mailing_lists(&pool)
         .await
         .map_err(error_500)?
+        .iter()
+        .any(|list| list.list_id == list_id)
     {
-        NextAction::StartProcessing(t) => t,
-        NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
-            return Ok(saved_response);
-        }
-    };
-	let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
+        return Err(error_400(format!("{} is not a mailing list", list_id)));
+    }
+    let mut transaction = pool.begin().await.map_err(error_500)?;
+	let issue_id = insert_newsletter_issue(
+		&mut transaction,
+		&publication.publication_id,
+		&title,
+		&text_content,
+		&html_content,
+		&list_id,
+		private.is_some()
+	)
 		.await
 		.context("Failed to store newsletter isuue details")
 		.map_err(error_500)?;
 
+	insert_issue_variants(&mut transaction, issue_id, &variants)
+		.await
+		.context("Failed to store the translations of the issue")
+		.map_err(error_500)?;
+
 	enqueue_delivery_tasks(&mut transaction, issue_id)
 		.await
 		.context("Failed to enqueue delivery tasks")
 		.map_err(error_500)?;
-	
-    let response = see_other("/admin/newsletters");
-    let response = save_response(transaction, &idempotency_key, *user_id, response)
+
+	record_audit_entry(
+		&mut transaction,
+		*user_id,
+		&publication.publication_id,
+		AuditAction::NewsletterPublish,
+		Some(&format!("{} ({})", issue_id, title)),
+		Some(&client_ip(&request))
+	)
+		.await
+		.context("Failed to record audit entry")
+		.map_err(error_500)?;
+
+    transaction
+        .commit()
         .await
+        .context("Failed to commit the newsletter issue")
         .map_err(error_500)?;
-
-    Ok(response)
+    success_message().send();
+    Ok(see_other("/admin/newsletters"))
 }
 
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
 	transaction: &mut Transaction<'_, Postgres>,
+	publication_id: &str,
 	title: &str,
 	text_content: &str,
 	html_content: &str,
+	list_id: &str,
+	is_private: bool,
 ) -> Result <Uuid, sqlx::Error> {
 	let newsletter_issue_id = Uuid::new_v4();
 	sqlx::query!(
@@ -81,20 +163,56 @@ async fn insert_newsletter_issue(
 				title,
 				text_content,
 				html_content,
-				published_at
+				published_at,
+				list_id,
+				is_private,
+				publication_id
 			)
-			VALUES ($1, $2, $3, $4, now())
+			VALUES ($1, $2, $3, $4, now(), $5, $6, $7)
 		"#,
 		newsletter_issue_id,
 		title,
 		text_content,
-		html_content
+		html_content,
+		list_id,
+		is_private,
+		publication_id
 	)
 	.execute(transaction)
 	.await?;
 	Ok(newsletter_issue_id)
 }
 
+#[tracing::instrument(skip_all)]
+async fn insert_issue_variants(
+	transaction: &mut Transaction<'_, Postgres>,
+	newsletter_issue_id: Uuid,
+	variants: &[IssueVariant],
+) -> Result<(), sqlx::Error> {
+	for variant in variants {
+		sqlx::query!(
+			r#"
+				INSERT INTO newsletter_issue_variants (
+					newsletter_issue_id,
+					locale,
+					title,
+					text_content,
+					html_content
+				)
+				VALUES ($1, $2, $3, $4, $5)
+			"#,
+			newsletter_issue_id,
+			variant.locale.as_str(),
+			variant.title,
+			variant.text_content,
+			variant.html_content
+		)
+		.execute(&mut *transaction)
+		.await?;
+	}
+	Ok(())
+}
+
 #[tracing::instrument(skip_all)]
 async fn enqueue_delivery_tasks (
 	transaction: &mut Transaction<'_, Postgres>,
@@ -104,13 +222,23 @@ async fn enqueue_delivery_tasks (
 		r#"
 			INSERT INTO issue_delivery_queue (
 				newsletter_issue_id,
-				subscriber_email
+				subscriber_email,
+				traceparent,
+				publication_id
 			)
-			SELECT $1, email
-			FROM subscriber
-			WHERE status = 'confirmed'
+			SELECT $1, s.email, $2, i.publication_id
+			FROM subscriber s
+			JOIN newsletter_issues i ON i.newsletter_issue_id = $1
+			WHERE
+				s.publication_id = i.publication_id AND
+				s.status = 'confirmed' AND
+				NOT EXISTS (
+					SELECT 1 FROM subscriber_list_opt_outs o
+					WHERE o.subscriber_id = s.id AND o.list_id = i.list_id
+				)
 		"#,
-		newsletter_issue_id
+		newsletter_issue_id,
+		current_traceparent()
 	)
 	.execute(transaction)
 	.await?;
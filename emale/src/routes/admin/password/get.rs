@@ -1,10 +1,20 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
+use uuid::Uuid;
 
+use crate::authentication::get_or_create_csrf_token;
 use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
 use crate::utils::{error_500, see_other};
 
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct ChangePasswordTemplate {
+    layout: Layout,
+    idempotency_key: Uuid,
+}
+
 pub async fn change_password_form(
     session: TypedSession,
     flash_message: IncomingFlashMessages,
@@ -13,52 +23,9 @@ pub async fn change_password_form(
         return Ok(see_other("/login"));
     }
 
-    let mut msg_html = String::new();
-
-    for m in flash_message.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-				<html lang="en">
-				<head>
-				<meta http-equiv="content-type" content="text/html; charset=utf-8">
-				<title>Change Password</title>
-				</head>
-				<body>
-					{msg_html}
-					<form action="/admin/password" method="post">
-					<label>Current password
-					<input
-						type="password"
-						placeholder="Enter current password"
-						name="current_password"
-					>
-					</label>
-					<br>
-					<label>New password
-					<input
-						type="password"
-						placeholder="Enter new password"
-						name="new_password"
-					>
-					</label>
-					<br>
-					<label>Confirm new password
-					<input
-						type="password"
-						placeholder="Type the new password again"
-						name="new_password_check"
-					>
-					</label>
-					<br>
-					<button type="submit">Change password</button>
-					</form>
-					<p><a href="/admin/dashboard">&lt;- Back</a></p>
-				</body>
-				</html>"#
-        )))
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    render(&ChangePasswordTemplate {
+        layout: Layout::new(&flash_message).with_csrf_token(csrf_token),
+        idempotency_key: Uuid::new_v4(),
+    })
 }
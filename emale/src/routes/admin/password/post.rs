@@ -1,12 +1,18 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
-use secrecy::{ExposeSecret, Secret};
+use secrecy::Secret;
 use sqlx::PgPool;
 
-use crate::authentication::{validate_credentials, AuthError, Credentials, UserId};
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::authentication::{
+    revoke_other_sessions, validate_credentials, validate_new_password, AuthError, Credentials,
+    UserId,
+};
 use crate::authentication;
+use crate::publications::Publication;
 use crate::routes::get_username;
-use crate::utils::{error_500, see_other};
+use crate::session_state::TypedSession;
+use crate::utils::{client_ip, error_500, see_other};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -16,24 +22,16 @@ pub struct FormData {
 }
 
 pub async fn change_password(
+    request: HttpRequest,
     form: web::Form<FormData>,
     user_id: web::ReqData<UserId>,
+    session: TypedSession,
     pool: web::Data<PgPool>,
+    publication: Publication,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
-    if form.new_password.expose_secret().len() < 12 || form.new_password.expose_secret().len() > 128
-    {
-        FlashMessage::error(
-            "Password must be longer than 12 characters and less than 128 characters",
-        )
-        .send();
-        return Ok(see_other("/admin/password"));
-    }
-    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
-        FlashMessage::error(
-            "You entered two different new passwords - the fieid values must match",
-        )
-        .send();
+    if let Err(e) = validate_new_password(&form.new_password, &form.new_password_check) {
+        FlashMessage::error(e.to_string()).send();
         return Ok(see_other("/admin/password"));
     }
     let username = get_username(*user_id, &pool).await.map_err(error_500)?;
@@ -50,9 +48,25 @@ pub async fn change_password(
             AuthError::UnexpectedError(_) => Err(error_500(e).into()),
         };
     }
-    authentication::change_password(*user_id, form.0.new_password, &pool)
+    authentication::change_password(*user_id, form.0.new_password, &**pool)
 		.await
 		.map_err(error_500)?;
+    // anybody else holding a session with the old password is logged out
+    if let Some(session_id) = session.get_session_id().map_err(error_500)? {
+        revoke_other_sessions(*user_id, session_id, &pool)
+            .await
+            .map_err(error_500)?;
+    }
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::PasswordChange,
+        Some(&user_id.to_string()),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
 	FlashMessage::error("Password successfully updated").send();
 	Ok(see_other("/admin/password"))
 }
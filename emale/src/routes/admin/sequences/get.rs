@@ -0,0 +1,32 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::authentication::get_or_create_csrf_token;
+use crate::automation::{get_sequences, Sequence};
+use crate::publications::Publication;
+use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "admin/sequences.html")]
+struct SequencesTemplate {
+    layout: Layout,
+    sequences: Vec<Sequence>,
+}
+
+pub async fn list_sequences(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    let sequences = get_sequences(&pool, &publication.publication_id).await.map_err(error_500)?;
+    render(&SequencesTemplate {
+        layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+        sequences,
+    })
+}
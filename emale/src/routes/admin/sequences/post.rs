@@ -0,0 +1,135 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::authentication::UserId;
+use crate::automation::{add_step, create_sequence, set_sequence_active, NewStep};
+use crate::publications::Publication;
+use crate::utils::{client_ip, error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct SequenceForm {
+    name: String,
+}
+
+pub async fn create_automation_sequence(
+    request: HttpRequest,
+    form: web::Form<SequenceForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The sequence needs a name").send();
+        return Ok(see_other("/admin/sequences"));
+    }
+    let sequence_id = create_sequence(&pool, &publication.publication_id, name)
+        .await
+        .map_err(error_500)?;
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::SequenceEdit,
+        Some(&format!("{} (created)", sequence_id)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info("The sequence has been created").send();
+    Ok(see_other("/admin/sequences"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct StepForm {
+    delay_days: i32,
+    subject: String,
+    text_content: String,
+    html_content: String,
+}
+
+pub async fn add_automation_step(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    form: web::Form<StepForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let sequence_id = path.into_inner();
+    if form.delay_days < 0 {
+        FlashMessage::error("The delay cannot be negative").send();
+        return Ok(see_other("/admin/sequences"));
+    }
+    if form.subject.trim().is_empty() {
+        FlashMessage::error("The step needs a subject").send();
+        return Ok(see_other("/admin/sequences"));
+    }
+    let step = NewStep {
+        delay_days: form.delay_days,
+        subject: &form.subject,
+        text_content: &form.text_content,
+        html_content: &form.html_content,
+    };
+    if !add_step(&pool, &publication.publication_id, sequence_id, step)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::error("The sequence does not exist").send();
+        return Ok(see_other("/admin/sequences"));
+    }
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::SequenceEdit,
+        Some(&format!("{} (step added)", sequence_id)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info("The step has been added").send();
+    Ok(see_other("/admin/sequences"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ActivityForm {
+    active: bool,
+}
+
+pub async fn change_sequence_activity(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    form: web::Form<ActivityForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let sequence_id = path.into_inner();
+    if !set_sequence_active(&pool, &publication.publication_id, sequence_id, form.active)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::error("The sequence does not exist").send();
+        return Ok(see_other("/admin/sequences"));
+    }
+    let activity = if form.active { "active" } else { "paused" };
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::SequenceEdit,
+        Some(&format!("{} ({})", sequence_id, activity)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info(format!("The sequence is now {}", activity)).send();
+    Ok(see_other("/admin/sequences"))
+}
@@ -0,0 +1,39 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{get_or_create_csrf_token, list_active_sessions, UserId, UserSession};
+use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsTemplate {
+    layout: Layout,
+    sessions: Vec<UserSession>,
+    current_session_id: Option<Uuid>,
+}
+
+pub async fn list_sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let current_session_id = session.get_session_id().map_err(error_500)?;
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+
+    let sessions = list_active_sessions(*user_id, &pool)
+        .await
+        .map_err(error_500)?;
+
+    render(&SessionsTemplate {
+        layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+        sessions,
+        current_session_id,
+    })
+}
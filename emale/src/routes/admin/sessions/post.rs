@@ -0,0 +1,42 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{revoke_other_sessions, revoke_user_session, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::{error_500, see_other};
+
+pub async fn revoke_session_by_id(
+    path: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if revoke_user_session(*user_id, path.into_inner(), &pool)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::info("The session has been revoked").send();
+    } else {
+        FlashMessage::error("The session does not exist or has already ended").send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+pub async fn revoke_all_other_sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let current_session_id = session
+        .get_session_id()
+        .map_err(error_500)?
+        .ok_or_else(|| error_500("Logged in session without a session id"))?;
+    revoke_other_sessions(*user_id, current_session_id, &pool)
+        .await
+        .map_err(error_500)?;
+    FlashMessage::info("All other sessions have been logged out").send();
+    Ok(see_other("/admin/sessions"))
+}
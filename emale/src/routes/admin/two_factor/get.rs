@@ -0,0 +1,69 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::authentication::two_factor::{
+    generate_totp_secret, otpauth_qr_svg, otpauth_uri, store_pending_totp_secret,
+};
+use crate::authentication::{get_or_create_csrf_token, get_two_factor_state, UserId};
+use crate::routes::get_username;
+use crate::session_state::TypedSession;
+use crate::startup::TotpEncryptionKey;
+use crate::templates::{render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "admin/two_factor_enabled.html")]
+struct TwoFactorEnabledTemplate {
+    layout: Layout,
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor_setup.html")]
+struct TwoFactorSetupTemplate {
+    layout: Layout,
+    qr_code: String,
+    uri: String,
+}
+
+pub async fn two_factor_form(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    totp_key: web::Data<TotpEncryptionKey>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    let layout = Layout::new(&flash_messages).with_csrf_token(csrf_token);
+
+    let state = get_two_factor_state(*user_id, &totp_key.0, &pool)
+        .await
+        .map_err(error_500)?;
+    if state.enabled {
+        return render(&TwoFactorEnabledTemplate { layout });
+    }
+
+    // Keep showing the same pending secret until it is confirmed, otherwise
+    // a failed confirmation would invalidate what the user has just scanned.
+    let secret = match state.secret {
+        Some(secret) => secret,
+        None => {
+            let secret = generate_totp_secret();
+            store_pending_totp_secret(*user_id, &secret, &totp_key.0, &pool)
+                .await
+                .map_err(error_500)?;
+            secret
+        }
+    };
+    let username = get_username(*user_id, &pool).await.map_err(error_500)?;
+    let uri = otpauth_uri(&username, &secret);
+    let qr_code = otpauth_qr_svg(&uri).map_err(error_500)?;
+
+    render(&TwoFactorSetupTemplate {
+        layout,
+        qr_code,
+        uri,
+    })
+}
@@ -0,0 +1,90 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::authentication::two_factor::{disable_two_factor, enable_two_factor, verify_totp_code};
+use crate::authentication::{
+    get_or_create_csrf_token, get_two_factor_state, validate_second_factor, AuthError, UserId,
+};
+use crate::session_state::TypedSession;
+use crate::startup::TotpEncryptionKey;
+use crate::templates::{render, Layout};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorCodeFormData {
+    code: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/recovery_codes.html")]
+struct RecoveryCodesTemplate {
+    layout: Layout,
+    recovery_codes: Vec<String>,
+}
+
+pub async fn confirm_two_factor(
+    form: web::Form<TwoFactorCodeFormData>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    totp_key: web::Data<TotpEncryptionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let state = get_two_factor_state(*user_id, &totp_key.0, &pool)
+        .await
+        .map_err(error_500)?;
+    if state.enabled {
+        FlashMessage::info("Two-factor authentication is already enabled").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+    let secret = match state.secret {
+        Some(secret) => secret,
+        None => return Ok(see_other("/admin/two-factor")),
+    };
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    if verify_totp_code(&secret, form.code.trim(), now)
+        .map_err(error_500)?
+        .is_none()
+    {
+        FlashMessage::error("The authentication code is invalid").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+
+    let recovery_codes = enable_two_factor(*user_id, &pool)
+        .await
+        .map_err(error_500)?;
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+
+    // Recovery codes are shown only once, so render them directly
+    // instead of redirecting.
+    render(&RecoveryCodesTemplate {
+        layout: Layout::default().with_csrf_token(csrf_token),
+        recovery_codes,
+    })
+}
+
+pub async fn turn_off_two_factor(
+    form: web::Form<TwoFactorCodeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    totp_key: web::Data<TotpEncryptionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if let Err(e) = validate_second_factor(*user_id, &form.code, &totp_key.0, &pool).await {
+        return match e {
+            AuthError::InvalidCredential(_) => {
+                FlashMessage::error("The authentication code is invalid").send();
+                Ok(see_other("/admin/two-factor"))
+            }
+            AuthError::UnexpectedError(_) => Err(error_500(e)),
+        };
+    }
+    disable_two_factor(*user_id, &pool)
+        .await
+        .map_err(error_500)?;
+    FlashMessage::info("Two-factor authentication has been disabled").send();
+    Ok(see_other("/admin/two-factor"))
+}
@@ -0,0 +1,73 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::get_or_create_csrf_token;
+use crate::publications::Publication;
+use crate::session_state::TypedSession;
+use crate::templates::{render, Layout};
+use crate::utils::{error_500, see_other};
+use crate::webhooks::{get_deliveries, get_endpoints, WebhookDelivery, WebhookEndpoint};
+
+const DELIVERY_LOG_LENGTH: i64 = 100;
+
+#[derive(Template)]
+#[template(path = "admin/webhooks.html")]
+struct WebhooksTemplate {
+    layout: Layout,
+    endpoints: Vec<WebhookEndpoint>,
+}
+
+pub async fn list_webhooks(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    let endpoints = get_endpoints(&pool, &publication.publication_id).await.map_err(error_500)?;
+    render(&WebhooksTemplate {
+        layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+        endpoints,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/webhook_deliveries.html")]
+struct WebhookDeliveriesTemplate {
+    layout: Layout,
+    url: String,
+    deliveries: Vec<WebhookDelivery>,
+}
+
+pub async fn webhook_deliveries(
+    session: TypedSession,
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = get_or_create_csrf_token(&session).map_err(error_500)?;
+    let log = get_deliveries(
+        &pool,
+        &publication.publication_id,
+        path.into_inner(),
+        DELIVERY_LOG_LENGTH,
+    )
+    .await
+    .map_err(error_500)?;
+    let (url, deliveries) = match log {
+        Some(log) => log,
+        None => {
+            FlashMessage::error("The endpoint does not exist").send();
+            return Ok(see_other("/admin/webhooks"));
+        }
+    };
+    render(&WebhookDeliveriesTemplate {
+        layout: Layout::new(&flash_messages).with_csrf_token(csrf_token),
+        url,
+        deliveries,
+    })
+}
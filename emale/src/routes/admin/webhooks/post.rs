@@ -0,0 +1,117 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::authentication::UserId;
+use crate::publications::Publication;
+use crate::utils::{client_ip, error_500, see_other};
+use crate::webhooks::{create_endpoint, resend_delivery, set_endpoint_active};
+
+#[derive(serde::Deserialize)]
+pub struct EndpointForm {
+    url: String,
+}
+
+pub async fn create_webhook_endpoint(
+    request: HttpRequest,
+    form: web::Form<EndpointForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let url = match reqwest::Url::parse(form.url.trim()) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => url,
+        _ => {
+            FlashMessage::error("The endpoint must be an http(s) URL").send();
+            return Ok(see_other("/admin/webhooks"));
+        }
+    };
+    let endpoint_id = create_endpoint(&pool, &publication.publication_id, &url)
+        .await
+        .map_err(error_500)?;
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::WebhookEdit,
+        Some(&format!("{} (created, {})", endpoint_id, url)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info("The endpoint has been added").send();
+    Ok(see_other("/admin/webhooks"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ActivityForm {
+    active: bool,
+}
+
+pub async fn change_webhook_activity(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    form: web::Form<ActivityForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let endpoint_id = path.into_inner();
+    if !set_endpoint_active(&pool, &publication.publication_id, endpoint_id, form.active)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::error("The endpoint does not exist").send();
+        return Ok(see_other("/admin/webhooks"));
+    }
+    let activity = if form.active { "active" } else { "paused" };
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::WebhookEdit,
+        Some(&format!("{} ({})", endpoint_id, activity)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info(format!("The endpoint is now {}", activity)).send();
+    Ok(see_other("/admin/webhooks"))
+}
+
+pub async fn resend_webhook_delivery(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    publication: Publication,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let delivery_id = path.into_inner();
+    let endpoint_id = match resend_delivery(&pool, &publication.publication_id, delivery_id)
+        .await
+        .map_err(error_500)?
+    {
+        Some(endpoint_id) => endpoint_id,
+        None => {
+            FlashMessage::error("The delivery does not exist").send();
+            return Ok(see_other("/admin/webhooks"));
+        }
+    };
+    record_audit_entry(
+        &**pool,
+        *user_id,
+        &publication.publication_id,
+        AuditAction::WebhookEdit,
+        Some(&format!("{} (resent)", delivery_id)),
+        Some(&client_ip(&request)),
+    )
+    .await
+    .map_err(error_500)?;
+    FlashMessage::info("The event will be sent again").send();
+    Ok(see_other(&format!("/admin/webhooks/{}", endpoint_id)))
+}
@@ -0,0 +1,31 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::archive::{atom_feed, get_feed_issues, rss_feed};
+use crate::publications::Publication;
+use crate::utils::error_500;
+
+// Feeds are titled after the publication.
+pub async fn feed_rss(
+	pool: web::Data<PgPool>,
+	publication: Publication
+) -> Result<HttpResponse, actix_web::Error> {
+	let issues = get_feed_issues(&pool, &publication.publication_id)
+		.await
+		.map_err(error_500)?;
+	Ok(HttpResponse::Ok()
+		.content_type("application/rss+xml; charset=utf-8")
+		.body(rss_feed(&publication.base_url, &publication.name, &issues)))
+}
+
+pub async fn feed_atom(
+	pool: web::Data<PgPool>,
+	publication: Publication
+) -> Result<HttpResponse, actix_web::Error> {
+	let issues = get_feed_issues(&pool, &publication.publication_id)
+		.await
+		.map_err(error_500)?;
+	Ok(HttpResponse::Ok()
+		.content_type("application/atom+xml; charset=utf-8")
+		.body(atom_feed(&publication.base_url, &publication.name, &issues)))
+}
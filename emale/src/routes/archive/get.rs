@@ -0,0 +1,62 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::archive::{get_archived_issue, get_archived_issues, ArchivedIssue};
+use crate::i18n::Locale;
+use crate::publications::Publication;
+use crate::templates::{render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "archive.html")]
+struct ArchiveTemplate {
+	layout: Layout,
+	issues: Vec<ArchivedIssue>
+}
+
+#[derive(Template)]
+#[template(path = "archive_issue.html")]
+struct ArchivedIssueTemplate {
+	layout: Layout,
+	issue: ArchivedIssue
+}
+
+pub async fn archive_index(
+	pool: web::Data<PgPool>,
+	publication: Publication,
+	flash_messages: IncomingFlashMessages,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	let issues = get_archived_issues(&pool, &publication.publication_id, None)
+		.await
+		.map_err(error_500)?;
+	render(&ArchiveTemplate {
+		layout: Layout::new(&flash_messages).with_locale(locale),
+		issues
+	})
+}
+
+// Private and unknown issues, and the issues of other publications, are
+// all not found.
+pub async fn archived_issue(
+	path: web::Path<Uuid>,
+	pool: web::Data<PgPool>,
+	publication: Publication,
+	flash_messages: IncomingFlashMessages,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	let issue = match get_archived_issue(&pool, &publication.publication_id, path.into_inner(), locale)
+		.await
+		.map_err(error_500)?
+	{
+		Some(issue) => issue,
+		None => return Ok(HttpResponse::NotFound().body(locale.t("archive.not_found").to_owned())),
+	};
+	render(&ArchivedIssueTemplate {
+		layout: Layout::new(&flash_messages).with_locale(locale),
+		issue
+	})
+}
@@ -0,0 +1,5 @@
+mod get;
+mod feeds;
+
+pub use get::*;
+pub use feeds::*;
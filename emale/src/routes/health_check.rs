@@ -1,5 +1,30 @@
-use actix_web::{HttpRequest, Responder, HttpResponse};
+use actix_web::{web, HttpRequest, Responder, HttpResponse};
+use sqlx::PgPool;
 
+use crate::health::ReadinessProbe;
+use crate::shutdown::ShutdownState;
+
+// Kept for the deployments that still probe the old path.
 pub async fn health_check(_: HttpRequest) -> impl Responder {
 	HttpResponse::Ok()
-}
\ No newline at end of file
+}
+
+// The process is up and serving requests, whatever the state of its dependencies.
+pub async fn health_live() -> impl Responder {
+	HttpResponse::Ok()
+}
+
+// 503 as long as one of the dependencies is down or the
+// instance is shutting down, with a breakdown per check.
+pub async fn health_ready(
+	pool: web::Data<PgPool>,
+	probe: web::Data<ReadinessProbe>,
+	shutdown: web::Data<ShutdownState>
+) -> HttpResponse {
+	let report = probe.check(&pool, &shutdown).await;
+	if report.ready {
+		HttpResponse::Ok().json(&report)
+	} else {
+		HttpResponse::ServiceUnavailable().json(&report)
+	}
+}
@@ -1,8 +1,58 @@
-use actix_web::{HttpResponse};
-use actix_web::http::header::ContentType;
-
-pub async fn home() -> HttpResponse {
-  HttpResponse::Ok()
-    .content_type(ContentType::html())
-    .body(include_str!("home.html"))
-}
\ No newline at end of file
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use uuid::Uuid;
+
+use crate::i18n::Locale;
+use crate::subscribe_protection::{ChallengeWidget, SubscribeProtection};
+use crate::templates::{try_render, Layout};
+use crate::utils::error_500;
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomeTemplate {
+  layout: Layout,
+  form_token: String,
+  idempotency_key: Uuid,
+  challenge: Option<ChallengeWidget>,
+  locales: [Locale; 2],
+  pending: Option<PendingSubscription>,
+}
+
+// A subscription held back until the address, which looks like a typo,
+// is submitted again as is.
+pub struct PendingSubscription {
+  pub name: String,
+  pub email: String,
+  pub warning: String,
+}
+
+pub async fn home(
+  flash_messages: IncomingFlashMessages,
+  protection: web::Data<SubscribeProtection>,
+  locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+  render_home(
+    Layout::new(&flash_messages).with_locale(locale),
+    &protection,
+    None
+  )
+  .map_err(error_500)
+}
+
+// With a fresh form token and idempotency key, the form can be submitted
+// again.
+pub fn render_home(
+  layout: Layout,
+  protection: &SubscribeProtection,
+  pending: Option<PendingSubscription>
+) -> Result<HttpResponse, askama::Error> {
+  try_render(&HomeTemplate {
+    layout,
+    form_token: protection.issue_form_token(),
+    idempotency_key: Uuid::new_v4(),
+    challenge: protection.challenge_widget(),
+    locales: Locale::ALL,
+    pending,
+  })
+}
@@ -1,12 +1,18 @@
 use actix_http::header::LOCATION;
-use actix_web::{HttpResponse, web};
+use actix_web::{HttpRequest, HttpResponse, web};
 use actix_web::error::InternalError;
 use actix_web_flash_messages::FlashMessage;
 use secrecy::{Secret};
 use sqlx::PgPool;
-use crate::authentication::{Credentials, validate_credentials, AuthError};
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::metrics::{record_login_attempt, LoginOutcome};
+use crate::publications::Publication;
+use crate::authentication::{Credentials, validate_credentials, AuthError, get_two_factor_state, register_session, LoginThrottle};
 use crate::routes::error_chain_fmt;
 use crate::session_state::TypedSession;
+use crate::startup::TotpEncryptionKey;
+use crate::utils::{client_ip, user_agent};
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -15,37 +21,74 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-	skip(form, pool, session),
+	skip(request, form, pool, session, throttle, totp_key, publication),
 	fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn login(
+	request: HttpRequest,
 	form: web::Form<FormData>, 
 	pool: web::Data<PgPool>,
-    session: TypedSession
+    session: TypedSession,
+    throttle: web::Data<LoginThrottle>,
+    totp_key: web::Data<TotpEncryptionKey>,
+    publication: Publication
 ) -> Result<HttpResponse, InternalError<LoginError>> {
+	let client_ip = client_ip(&request);
 	let credentials = Credentials {
 		username: form.0.username,
 		password: form.0.password
 	};
+	let username = credentials.username.clone();
 
 	tracing::Span::current()
-		.record("username", &tracing::field::display(&credentials.username));
+		.record("username", &tracing::field::display(&username));
+
+    if throttle
+        .is_locked_out(&username, &client_ip)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        return Err(login_redirect(LoginError::TooManyAttempts));
+    }
 
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
             tracing::Span::current()
                 .record("user_id", &tracing::field::display(&user_id));
+            let two_factor = get_two_factor_state(user_id, &totp_key.0, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session.renew();
-            session
-                .insert_user_id(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            // with 2FA enabled the counters are only reset once the
+            // second factor succeeds, so they also cover code guessing
+            if two_factor.enabled {
+                session
+                    .insert_pending_two_factor(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/two-factor"))
+                    .finish());
+            }
+            throttle
+                .reset(&username)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            start_user_session(&request, &session, user_id, &publication.publication_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
                 .finish())
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredential(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredential(_) => {
+                    throttle
+                        .record_failure(&username, &client_ip, &pool)
+                        .await
+                        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into())
             };
 
@@ -54,7 +97,41 @@ pub async fn login(
     }
 }
 
+// Registers the session server side, so that it can be revoked,
+// before marking it as logged in. The login is audited in the log of the
+// publication it happened on.
+pub(super) async fn start_user_session(
+    request: &HttpRequest,
+    session: &TypedSession,
+    user_id: Uuid,
+    publication_id: &str,
+    pool: &PgPool
+) -> Result<(), anyhow::Error> {
+    let client_ip = client_ip(request);
+    let session_id = register_session(
+        user_id,
+        &client_ip,
+        user_agent(request).as_deref(),
+        pool
+    )
+    .await?;
+    session.insert_session_id(session_id)?;
+    session.insert_user_id(user_id)?;
+    record_audit_entry(
+        pool,
+        user_id,
+        publication_id,
+        AuditAction::Login,
+        None,
+        Some(&client_ip)
+    )
+    .await?;
+    record_login_attempt(LoginOutcome::Success);
+    Ok(())
+}
+
 fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    record_login_attempt(e.outcome());
     FlashMessage::error(e.to_string()).send();
     let response = HttpResponse::SeeOther()
         .insert_header((
@@ -69,10 +146,24 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 pub enum LoginError {
 	#[error("Authentication failed")]
 	AuthError(#[source] anyhow::Error),
+	// Deliberately vague: it must not reveal whether the username
+	// or the client address has been locked out.
+	#[error("Too many failed login attempts, please try again later")]
+	TooManyAttempts,
 	#[error("Something went wrong")]
 	UnexpectedError(#[from] anyhow::Error)
 }
 
+impl LoginError {
+	pub(super) fn outcome(&self) -> LoginOutcome {
+		match self {
+			LoginError::AuthError(_) => LoginOutcome::Failure,
+			LoginError::TooManyAttempts => LoginOutcome::LockedOut,
+			LoginError::UnexpectedError(_) => LoginOutcome::Error,
+		}
+	}
+}
+
 impl std::fmt::Debug for LoginError {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		error_chain_fmt(self, f)
@@ -0,0 +1,115 @@
+use actix_http::header::LOCATION;
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web::error::InternalError;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use crate::authentication::{validate_second_factor, AuthError, LoginThrottle};
+use crate::metrics::record_login_attempt;
+use crate::publications::Publication;
+use crate::routes::get_username;
+use crate::session_state::TypedSession;
+use crate::startup::TotpEncryptionKey;
+use crate::templates::{render, Layout};
+use crate::utils::{client_ip, error_500, see_other};
+use super::{start_user_session, LoginError};
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorFormData {
+	code: String
+}
+
+#[derive(Template)]
+#[template(path = "login_two_factor.html")]
+struct LoginTwoFactorTemplate {
+	layout: Layout
+}
+
+pub async fn login_two_factor_form(
+	session: TypedSession,
+	flash_messages: IncomingFlashMessages
+) -> Result<HttpResponse, actix_web::Error> {
+	if session.get_pending_two_factor().map_err(error_500)?.is_none() {
+		return Ok(see_other("/login"));
+	}
+
+	render(&LoginTwoFactorTemplate {
+		layout: Layout::new(&flash_messages)
+	})
+}
+
+#[tracing::instrument(
+	skip(request, form, pool, session, throttle, totp_key, publication),
+	fields(user_id=tracing::field::Empty)
+)]
+pub async fn login_two_factor(
+	request: HttpRequest,
+	form: web::Form<TwoFactorFormData>,
+	pool: web::Data<PgPool>,
+	session: TypedSession,
+	throttle: web::Data<LoginThrottle>,
+	totp_key: web::Data<TotpEncryptionKey>,
+	publication: Publication
+) -> Result<HttpResponse, InternalError<LoginError>> {
+	let user_id = match session
+		.get_pending_two_factor()
+		.map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e.into())))?
+	{
+		Some(user_id) => user_id,
+		None => return Ok(see_other("/login")),
+	};
+	tracing::Span::current()
+		.record("user_id", &tracing::field::display(&user_id));
+
+	// second factor failures count against the same account as
+	// password failures
+	let client_ip = client_ip(&request);
+	let username = get_username(user_id, &pool)
+		.await
+		.map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+	if throttle
+		.is_locked_out(&username, &client_ip)
+		.await
+		.map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?
+	{
+		return Err(two_factor_redirect(LoginError::TooManyAttempts));
+	}
+
+	match validate_second_factor(user_id, &form.0.code, &totp_key.0, &pool).await {
+		Ok(()) => {
+			throttle
+				.reset(&username)
+				.await
+				.map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+			session.renew();
+			session.remove_pending_two_factor();
+			start_user_session(&request, &session, user_id, &publication.publication_id, &pool)
+				.await
+				.map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+			Ok(HttpResponse::SeeOther()
+				.insert_header((LOCATION, "/admin/dashboard"))
+				.finish())
+		}
+		Err(e) => {
+			let e = match e {
+				AuthError::InvalidCredential(_) => {
+					throttle
+						.record_failure(&username, &client_ip, &pool)
+						.await
+						.map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+					LoginError::AuthError(e.into())
+				}
+				AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into())
+			};
+
+			Err(two_factor_redirect(e))
+		}
+	}
+}
+
+fn two_factor_redirect(e: LoginError) -> InternalError<LoginError> {
+	record_login_attempt(e.outcome());
+	FlashMessage::error(e.to_string()).send();
+	let response = see_other("/login/two-factor");
+	InternalError::from_response(e, response)
+}
@@ -4,8 +4,16 @@ mod subscribe_confirm;
 mod home;
 mod login;
 mod admin;
+mod password_reset;
+mod subscriber_data;
+mod preferences;
+mod archive;
 
 pub use admin::*;
+pub use password_reset::*;
+pub use subscriber_data::*;
+pub use preferences::*;
+pub use archive::*;
 pub use login::*;
 pub use home::*;
 pub use health_check::*;
@@ -0,0 +1,53 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::authentication::password_reset::is_reset_token_valid;
+use crate::templates::{render, Layout};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct ResetParameters {
+	token: String
+}
+
+#[derive(Template)]
+#[template(path = "password_reset.html")]
+struct PasswordResetTemplate {
+	layout: Layout
+}
+
+#[derive(Template)]
+#[template(path = "password_reset_confirm.html")]
+struct PasswordResetConfirmTemplate<'a> {
+	layout: Layout,
+	token: &'a str
+}
+
+pub async fn password_reset_form(
+	flash_messages: IncomingFlashMessages
+) -> Result<HttpResponse, actix_web::Error> {
+	render(&PasswordResetTemplate {
+		layout: Layout::new(&flash_messages)
+	})
+}
+
+pub async fn password_reset_confirm_form(
+	param: web::Query<ResetParameters>,
+	pool: web::Data<PgPool>,
+	flash_messages: IncomingFlashMessages
+) -> Result<HttpResponse, actix_web::Error> {
+	if !is_reset_token_valid(&param.token, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		FlashMessage::error("The password reset link is invalid or has expired").send();
+		return Ok(see_other("/password-reset"));
+	}
+
+	render(&PasswordResetConfirmTemplate {
+		layout: Layout::new(&flash_messages),
+		token: &param.token
+	})
+}
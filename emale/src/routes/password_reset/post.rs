@@ -0,0 +1,160 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::audit::{record_audit_entry, AuditAction};
+use crate::authentication::password_reset::{
+	consume_reset_token, create_reset_token, get_reset_recipient,
+};
+use crate::authentication::{
+	change_password, revoke_all_sessions, validate_new_password, PasswordResetThrottle,
+};
+use crate::domain::SubscriberEmail;
+use crate::email_outbox::{enqueue_email, OutgoingEmail};
+use crate::publications::Publication;
+use crate::utils::{client_ip, error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestForm {
+	email: String
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetForm {
+	token: String,
+	new_password: Secret<String>,
+	new_password_check: Secret<String>
+}
+
+#[tracing::instrument(
+	name = "Request a password reset",
+	skip(request, form, pool, throttle, publication)
+)]
+pub async fn request_password_reset(
+	request: HttpRequest,
+	form: web::Form<PasswordResetRequestForm>,
+	pool: web::Data<PgPool>,
+	throttle: web::Data<PasswordResetThrottle>,
+	publication: Publication
+) -> Result<HttpResponse, actix_web::Error> {
+	let allowed = throttle
+		.allow_request(&form.email, &client_ip(&request))
+		.await
+		.map_err(error_500)?;
+	let recipient = if allowed {
+		get_reset_recipient(&form.email, &pool)
+			.await
+			.map_err(error_500)?
+	} else {
+		tracing::warn!("Too many password reset requests for the address or client");
+		None
+	};
+	// the email is queued rather than sent, so that the response takes
+	// as long whether or not the address belongs to an account
+	if let Some(recipient) = recipient {
+		let mut transaction = pool.begin().await.map_err(error_500)?;
+		let token = create_reset_token(&mut transaction, recipient.user_id)
+			.await
+			.map_err(error_500)?;
+		enqueue_password_reset_email(&mut transaction, &recipient.email, &publication, &token)
+			.await
+			.map_err(error_500)?;
+		transaction.commit().await.map_err(error_500)?;
+	}
+	FlashMessage::info(
+		"If the address belongs to an account, a link to reset the password has been sent to it"
+	)
+	.send();
+	Ok(see_other("/login"))
+}
+
+#[tracing::instrument(name = "Reset a password", skip(request, form, pool, publication))]
+pub async fn reset_password(
+	request: HttpRequest,
+	form: web::Form<PasswordResetForm>,
+	pool: web::Data<PgPool>,
+	publication: Publication
+) -> Result<HttpResponse, actix_web::Error> {
+	let PasswordResetForm {
+		token,
+		new_password,
+		new_password_check
+	} = form.0;
+	if let Err(e) = validate_new_password(&new_password, &new_password_check) {
+		FlashMessage::error(e.to_string()).send();
+		return Ok(see_other(&format!(
+			"/password-reset/confirm?token={}",
+			urlencoding::encode(&token)
+		)));
+	}
+
+	// the token is only spent if the password is changed
+	let mut transaction = pool.begin().await.map_err(error_500)?;
+	let user_id = match consume_reset_token(&token, &mut transaction)
+		.await
+		.map_err(error_500)?
+	{
+		Some(user_id) => user_id,
+		None => {
+			FlashMessage::error("The password reset link is invalid or has expired").send();
+			return Ok(see_other("/password-reset"));
+		}
+	};
+	change_password(user_id, new_password, &mut transaction)
+		.await
+		.map_err(error_500)?;
+	revoke_all_sessions(user_id, &mut transaction)
+		.await
+		.map_err(error_500)?;
+	record_audit_entry(
+		&mut transaction,
+		user_id,
+		&publication.publication_id,
+		AuditAction::PasswordReset,
+		Some(&user_id.to_string()),
+		Some(&client_ip(&request))
+	)
+	.await
+	.map_err(error_500)?;
+	transaction.commit().await.map_err(error_500)?;
+
+	FlashMessage::info("Your password has been reset, you can now log in").send();
+	Ok(see_other("/login"))
+}
+
+pub async fn enqueue_password_reset_email(
+	transaction: &mut Transaction<'_, Postgres>,
+	recipient: &SubscriberEmail,
+	publication: &Publication,
+	token: &str
+) -> Result<(), sqlx::Error> {
+	let reset_link = format!(
+		"{}/password-reset/confirm?token={}",
+		publication.base_url, token
+	);
+	let plain_body = format!(
+		"Someone asked to reset the password of your account.\n\
+		Visit {} to choose a new one. The link expires in one hour.\n\
+		If it wasn't you, you can ignore this email.",
+		reset_link
+	);
+	let html_body = format!(
+		"Someone asked to reset the password of your account.<br />\
+		Click <a href=\"{}\">here</a> to choose a new one. The link expires in one hour.<br />\
+		If it wasn't you, you can ignore this email.",
+		reset_link
+	);
+
+	enqueue_email(
+		transaction,
+		OutgoingEmail {
+			publication_id: &publication.publication_id,
+			recipient,
+			subject: "Reset your password",
+			html_content: &html_body,
+			text_content: &plain_body,
+		}
+	)
+	.await
+}
@@ -0,0 +1,103 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::i18n::Locale;
+use crate::preferences::{
+	confirm_email_change, get_preferences, is_preferences_signature_valid, preferences_path,
+	EmailChangeOutcome, ListPreference,
+};
+use crate::startup::HmacSecret;
+use crate::templates::{render, Layout};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+	subscriber_id: Uuid,
+	signature: String
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeParameters {
+	token: String
+}
+
+#[derive(Template)]
+#[template(path = "preferences.html")]
+struct PreferencesTemplate<'a> {
+	layout: Layout,
+	subscriber_id: Uuid,
+	signature: &'a str,
+	name: String,
+	email: String,
+	pending_email: Option<String>,
+	lists: Vec<ListPreference>,
+	locales: [Locale; 2]
+}
+
+// Signed links are only handed out to the subscriber, anything
+// else gets the same answer as an unknown subscriber.
+pub fn invalid_preferences_link(locale: Locale) -> HttpResponse {
+	HttpResponse::Unauthorized().body(locale.t("preferences.invalid_link").to_owned())
+}
+
+// Displayed in the language the subscriber picked rather than the one of
+// the browser.
+pub async fn preferences_form(
+	param: web::Query<PreferencesParameters>,
+	pool: web::Data<PgPool>,
+	hmac_secret: web::Data<HmacSecret>,
+	flash_messages: IncomingFlashMessages,
+	browser_locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	if !is_preferences_signature_valid(&hmac_secret.0, param.subscriber_id, &param.signature) {
+		return Ok(invalid_preferences_link(browser_locale));
+	}
+	let preferences = match get_preferences(param.subscriber_id, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		Some(preferences) => preferences,
+		None => return Ok(invalid_preferences_link(browser_locale)),
+	};
+
+	render(&PreferencesTemplate {
+		layout: Layout::new(&flash_messages).with_locale(preferences.locale),
+		subscriber_id: param.subscriber_id,
+		signature: &param.signature,
+		name: preferences.name,
+		email: preferences.email,
+		pending_email: preferences.pending_email,
+		lists: preferences.lists,
+		locales: Locale::ALL
+	})
+}
+
+#[tracing::instrument(name = "Confirm a subscriber email change", skip(param, pool, hmac_secret, locale))]
+pub async fn confirm_preferences_email(
+	param: web::Query<EmailChangeParameters>,
+	pool: web::Data<PgPool>,
+	hmac_secret: web::Data<HmacSecret>,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	let subscriber_id = match confirm_email_change(&param.token, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		EmailChangeOutcome::Changed { subscriber_id } => {
+			FlashMessage::info(locale.t("preferences.email_changed")).send();
+			subscriber_id
+		}
+		EmailChangeOutcome::AddressTaken { subscriber_id } => {
+			FlashMessage::error(locale.t("preferences.address_taken")).send();
+			subscriber_id
+		}
+		EmailChangeOutcome::InvalidToken => {
+			FlashMessage::error(locale.t("preferences.invalid_confirmation")).send();
+			return Ok(see_other("/"));
+		}
+	};
+	Ok(see_other(&preferences_path(&hmac_secret.0, subscriber_id)))
+}
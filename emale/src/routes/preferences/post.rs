@@ -0,0 +1,157 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::email_validation::EmailValidator;
+use crate::i18n::Locale;
+use crate::preferences::{
+	get_preferences, is_preferences_signature_valid, preferences_path, request_email_change,
+	update_preferences,
+};
+use crate::publications::Publication;
+use crate::startup::HmacSecret;
+use crate::utils::{error_400, error_500, see_other};
+use super::invalid_preferences_link;
+
+// The checkboxes of the lists share the `list_id` name, which
+// `serde_urlencoded` can only collect as key-value pairs.
+pub struct PreferencesForm {
+	subscriber_id: Uuid,
+	signature: String,
+	name: String,
+	email: String,
+	// the current locale is kept when missing
+	locale: Option<Locale>,
+	received_lists: Vec<String>
+}
+
+impl TryFrom<Vec<(String, String)>> for PreferencesForm {
+	type Error = String;
+
+	fn try_from(pairs: Vec<(String, String)>) -> Result<Self, Self::Error> {
+		let mut subscriber_id = None;
+		let mut signature = None;
+		let mut name = None;
+		let mut email = None;
+		let mut locale = None;
+		let mut received_lists = Vec::new();
+		for (key, value) in pairs {
+			match key.as_str() {
+				"subscriber_id" => subscriber_id = Some(value),
+				"signature" => signature = Some(value),
+				"name" => name = Some(value),
+				"email" => email = Some(value),
+				"locale" => locale = Some(Locale::parse(&value).ok_or("unsupported `locale`")?),
+				"list_id" => received_lists.push(value),
+				_ => {}
+			}
+		}
+		let subscriber_id: Uuid = subscriber_id
+			.ok_or("missing field `subscriber_id`")?
+			.parse()
+			.map_err(|_| "invalid `subscriber_id`")?;
+		Ok(Self {
+			subscriber_id,
+			signature: signature.ok_or("missing field `signature`")?,
+			name: name.ok_or("missing field `name`")?,
+			email: email.ok_or("missing field `email`")?,
+			locale,
+			received_lists
+		})
+	}
+}
+
+#[tracing::instrument(
+	name = "Update subscriber preferences on request",
+	skip(form, pool, email_client, email_validator, hmac_secret, publication, browser_locale)
+)]
+pub async fn update_subscriber_preferences(
+	form: web::Form<Vec<(String, String)>>,
+	pool: web::Data<PgPool>,
+	email_client: web::Data<EmailClient>,
+	email_validator: web::Data<EmailValidator>,
+	hmac_secret: web::Data<HmacSecret>,
+	publication: Publication,
+	browser_locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	let form = PreferencesForm::try_from(form.0).map_err(error_400)?;
+	if !is_preferences_signature_valid(&hmac_secret.0, form.subscriber_id, &form.signature) {
+		return Ok(invalid_preferences_link(browser_locale));
+	}
+	let current = match get_preferences(form.subscriber_id, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		Some(current) => current,
+		None => return Ok(invalid_preferences_link(browser_locale)),
+	};
+	let locale = form.locale.unwrap_or(current.locale);
+	let back = see_other(&preferences_path(&hmac_secret.0, form.subscriber_id));
+
+	let name = match SubscriberName::parse(form.name) {
+		Ok(name) => name,
+		Err(e) => {
+			FlashMessage::error(e).send();
+			return Ok(back);
+		}
+	};
+	let new_email = if form.email.trim().eq_ignore_ascii_case(&current.email) {
+		None
+	} else {
+		let new_email = match SubscriberEmail::parse(form.email.trim().to_owned()) {
+			Ok(new_email) => new_email,
+			Err(e) => {
+				FlashMessage::error(e).send();
+				return Ok(back);
+			}
+		};
+		if let Err(e) = email_validator.validate(&new_email).await {
+			FlashMessage::error(e.to_string()).send();
+			return Ok(back);
+		}
+		Some(new_email)
+	};
+
+	update_preferences(form.subscriber_id, &name, locale, &form.received_lists, &pool)
+		.await
+		.map_err(error_500)?;
+	FlashMessage::info(locale.t("preferences.saved")).send();
+
+	if let Some(new_email) = new_email {
+		let token = request_email_change(form.subscriber_id, &new_email, &pool)
+			.await
+			.map_err(error_500)?;
+		let email_client = publication.email_client(&email_client).map_err(error_500)?;
+		send_email_change_confirmation(&email_client, &new_email, &publication.base_url, &token, locale)
+			.await
+			.map_err(error_500)?;
+		FlashMessage::info(
+			locale.format("preferences.confirmation_sent", &[("email", new_email.as_ref())])
+		)
+		.send();
+	}
+	Ok(back)
+}
+
+pub async fn send_email_change_confirmation(
+	email_client: &EmailClient,
+	new_email: &SubscriberEmail,
+	base_url: &str,
+	token: &str,
+	locale: Locale
+) -> Result<(), reqwest::Error> {
+	let confirmation_link = format!(
+		"{}/preferences/confirm-email?token={}",
+		base_url, token
+	);
+	let args = [("link", confirmation_link.as_str())];
+	let plain_body = locale.format("email.email_change.text", &args);
+	let html_body = locale.format("email.email_change.html", &args);
+
+	email_client
+		.send_email(new_email, locale.t("email.email_change.subject"), &html_body, &plain_body)
+		.await
+}
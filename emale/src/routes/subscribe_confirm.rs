@@ -1,19 +1,40 @@
 use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::automation::enroll_subscriber;
+use crate::i18n::Locale;
+use crate::preferences::preferences_link;
+use crate::publications::Publication;
+use crate::startup::HmacSecret;
+use crate::templates::{render, Layout};
+use crate::webhooks::{record_event, EventType};
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
+#[derive(Template)]
+#[template(path = "subscribe_confirmed.html")]
+struct SubscribeConfirmedTemplate {
+	layout: Layout,
+	preferences_link: String,
+}
+
 #[tracing::instrument(
-	skip(param, pool)
+	skip(param, pool, publication, hmac_secret, flash_messages, locale)
 	name = "Confirm a pending subscriber"
 )]
 pub async fn confirm(
 	param: web::Query<Parameters>, 
-	pool: web::Data<PgPool>
+	pool: web::Data<PgPool>,
+	publication: Publication,
+	hmac_secret: web::Data<HmacSecret>,
+	flash_messages: IncomingFlashMessages,
+	locale: Locale
 ) -> HttpResponse {
     let id = match get_subcriber_id_from_token(
 			&pool, 
@@ -31,7 +52,11 @@ pub async fn confirm(
             if confirm_subscriber(subs_id, &pool).await.is_err() {
                 return HttpResponse::InternalServerError().finish();
             }
-			HttpResponse::Ok().finish()
+			let page = render(&SubscribeConfirmedTemplate {
+				layout: Layout::new(&flash_messages).with_locale(locale),
+				preferences_link: preferences_link(&publication.base_url, &hmac_secret.0, subs_id),
+			});
+			page.unwrap_or_else(|_| HttpResponse::InternalServerError().finish())
 		}
 	}
 }
@@ -44,16 +69,36 @@ pub async fn confirm_subscriber(
 	subs_id: Uuid, 
 	pool: &PgPool
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriber SET status = 'confirmed' WHERE id = $1"#,
+	let mut transaction = pool.begin().await?;
+    let newly_confirmed = sqlx::query!(
+        r#"UPDATE subscriber SET status = 'confirmed' WHERE id = $1 AND status <> 'confirmed'"#,
         subs_id
     )
-    .execute(pool)
+    .execute(&mut transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
+    })?
+    .rows_affected() == 1;
+	if !newly_confirmed {
+		// a second click on the link changes nothing
+		return Ok(());
+	}
+	// starts the welcome mails and the other automation sequences
+	enroll_subscriber(&mut transaction, subs_id)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to enroll the subscriber: {:?}", e);
+			e
+		})?;
+	record_event(&mut transaction, EventType::SubscriberConfirmed, subs_id)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to record the webhook event: {:?}", e);
+			e
+		})?;
+	transaction.commit().await?;
 
     Ok(())
 }
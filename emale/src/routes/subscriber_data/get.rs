@@ -0,0 +1,90 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::i18n::Locale;
+use crate::subscriber_data::{export_subscriber_data, get_subscriber_from_access_token};
+use crate::templates::{render, Layout};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct DataAccessParameters {
+	token: String
+}
+
+#[derive(Template)]
+#[template(path = "subscriber_data.html")]
+struct SubscriberDataTemplate {
+	layout: Layout
+}
+
+#[derive(Template)]
+#[template(path = "subscriber_data_access.html")]
+struct SubscriberDataAccessTemplate<'a> {
+	layout: Layout,
+	token: &'a str
+}
+
+pub fn invalid_link(locale: Locale) -> HttpResponse {
+	FlashMessage::error(locale.t("subscriber_data.invalid_link")).send();
+	see_other("/subscriber-data")
+}
+
+pub async fn subscriber_data_form(
+	flash_messages: IncomingFlashMessages,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	render(&SubscriberDataTemplate {
+		layout: Layout::new(&flash_messages).with_locale(locale)
+	})
+}
+
+pub async fn subscriber_data_access(
+	param: web::Query<DataAccessParameters>,
+	pool: web::Data<PgPool>,
+	flash_messages: IncomingFlashMessages,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	if get_subscriber_from_access_token(&param.token, &pool)
+		.await
+		.map_err(error_500)?
+		.is_none()
+	{
+		return Ok(invalid_link(locale));
+	}
+
+	render(&SubscriberDataAccessTemplate {
+		layout: Layout::new(&flash_messages).with_locale(locale),
+		token: &param.token
+	})
+}
+
+pub async fn export_subscriber_data_json(
+	param: web::Query<DataAccessParameters>,
+	pool: web::Data<PgPool>,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	let subscriber_id = match get_subscriber_from_access_token(&param.token, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		Some(subscriber_id) => subscriber_id,
+		None => return Ok(invalid_link(locale)),
+	};
+	let export = match export_subscriber_data(subscriber_id, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		Some(export) => export,
+		None => return Ok(invalid_link(locale)),
+	};
+
+	Ok(HttpResponse::Ok()
+		.insert_header(ContentDisposition {
+			disposition: DispositionType::Attachment,
+			parameters: vec![DispositionParam::Filename("subscriber-data.json".into())]
+		})
+		.json(export))
+}
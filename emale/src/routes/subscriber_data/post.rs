@@ -0,0 +1,106 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::i18n::Locale;
+use crate::publications::Publication;
+use crate::subscriber_data::{
+	create_data_access_token, erase_subscriber, get_data_access_recipient,
+	get_subscriber_from_access_token,
+};
+use crate::utils::{error_500, see_other};
+use super::invalid_link;
+
+#[derive(serde::Deserialize)]
+pub struct DataAccessRequestForm {
+	email: String
+}
+
+#[derive(serde::Deserialize)]
+pub struct EraseDataForm {
+	token: String
+}
+
+#[tracing::instrument(
+	name = "Request access to subscriber data",
+	skip(form, pool, email_client, publication, locale)
+)]
+pub async fn request_subscriber_data_access(
+	form: web::Form<DataAccessRequestForm>,
+	pool: web::Data<PgPool>,
+	email_client: web::Data<EmailClient>,
+	publication: Publication,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	if let Some(recipient) = get_data_access_recipient(&form.email, &publication.publication_id, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		let email_client = publication.email_client(&email_client).map_err(error_500)?;
+		let token = create_data_access_token(recipient.subscriber_id, &pool)
+			.await
+			.map_err(error_500)?;
+		// failures are only logged: the response must look the same
+		// whether or not the address is subscribed
+		if let Err(e) = send_data_access_email(
+			&email_client,
+			&recipient.email,
+			&publication.base_url,
+			&token,
+			recipient.locale
+		)
+		.await
+		{
+			tracing::error!(
+				error.cause_chain = ?e,
+				error.message = %e,
+				"Failed to send data access email"
+			);
+		}
+	}
+	FlashMessage::info(locale.t("subscriber_data.link_sent")).send();
+	Ok(see_other("/subscriber-data"))
+}
+
+#[tracing::instrument(name = "Erase subscriber data on request", skip(form, pool, locale))]
+pub async fn erase_subscriber_data(
+	form: web::Form<EraseDataForm>,
+	pool: web::Data<PgPool>,
+	locale: Locale
+) -> Result<HttpResponse, actix_web::Error> {
+	let subscriber_id = match get_subscriber_from_access_token(&form.token, &pool)
+		.await
+		.map_err(error_500)?
+	{
+		Some(subscriber_id) => subscriber_id,
+		None => return Ok(invalid_link(locale)),
+	};
+	erase_subscriber(subscriber_id, &pool)
+		.await
+		.map_err(error_500)?;
+
+	FlashMessage::info(locale.t("subscriber_data.erased")).send();
+	Ok(see_other("/subscriber-data"))
+}
+
+pub async fn send_data_access_email(
+	email_client: &EmailClient,
+	recipient: &SubscriberEmail,
+	base_url: &str,
+	token: &str,
+	locale: Locale
+) -> Result<(), reqwest::Error> {
+	let access_link = format!(
+		"{}/subscriber-data/access?token={}",
+		base_url, token
+	);
+	let args = [("link", access_link.as_str())];
+	let plain_body = locale.format("email.data_access.text", &args);
+	let html_body = locale.format("email.data_access.html", &args);
+
+	email_client
+		.send_email(recipient, locale.t("email.data_access.subject"), &html_body, &plain_body)
+		.await
+}
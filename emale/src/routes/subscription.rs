@@ -1,8 +1,15 @@
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::email_outbox::{enqueue_email, OutgoingEmail};
+use crate::email_validation::EmailValidator;
+use crate::i18n::Locale;
+use crate::publications::Publication;
+use crate::routes::{render_home, PendingSubscription};
+use crate::subscribe_protection::{record_rejection, SubmittedForm, SubscribeProtection};
+use crate::templates::Layout;
+use crate::utils::client_ip;
+use crate::webhooks::{record_event, EventType};
 use actix_http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
@@ -15,6 +22,15 @@ use anyhow::Context;
 pub struct SubscribeFormData {
     name: String,
     email: String,
+    // honeypot, left empty by humans
+    website: Option<String>,
+    form_token: Option<String>,
+    challenge_response: Option<String>,
+    // picked on the form, the browser language otherwise
+    locale: Option<String>,
+    // set when the address was submitted again after a typo warning
+    #[serde(default)]
+    confirm_email: bool,
 }
 
 impl TryFrom<SubscribeFormData> for NewSubscriber {
@@ -37,25 +53,71 @@ fn generate_subs_token() -> String {
 
 #[tracing::instrument(
   name = "Add new subscriber",
-  skip(req, pool, email_client, base_url),
+  skip(request, req, pool, publication, protection, email_validator, browser_locale),
   fields(
     email   = %req.email,
     name    = %req.name
   )
 )]
 pub async fn subscribe(
-    req: web::Form<SubscribeFormData>,
+    request: HttpRequest,
+    mut req: web::Form<SubscribeFormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>,
+    publication: Publication,
+    protection: web::Data<SubscribeProtection>,
+    email_validator: web::Data<EmailValidator>,
+    browser_locale: Locale,
 ) -> Result<HttpResponse, SubscribeError> {
+    let submitted = SubmittedForm {
+        honeypot: req.website.take(),
+        form_token: req.form_token.take(),
+        challenge_response: req.challenge_response.take(),
+    };
+    let locale = req
+        .locale
+        .take()
+        .and_then(|tag| Locale::parse(&tag))
+        .unwrap_or(browser_locale);
+    let confirm_email = req.confirm_email;
+    let (name, email) = (req.name.clone(), req.email.clone());
     let subs: NewSubscriber = req.0.try_into().map_err(SubscribeError::ValidationError)?;
 
+    // a rejected attempt looks exactly like an accepted one, bots
+    // learn nothing about the checks they failed
+    if let Some(reason) = protection
+        .rejection_reason(&submitted, subs.email.domain(), &client_ip(&request))
+        .await
+        .context("failed to check the subscribe attempt")?
+    {
+        tracing::warn!(reason = reason.as_str(), "Rejected a subscribe attempt");
+        record_rejection(reason);
+        return Ok(HttpResponse::Ok().finish());
+    }
+    email_validator
+        .validate(&subs.email)
+        .await
+        .map_err(|e| SubscribeError::ValidationError(e.to_string()))?;
+    // a likely typo is pointed out, not rejected: the form comes back
+    // filled in and the address is used as is when submitted again
+    if !confirm_email {
+        if let Some(suggestion) = email_validator.suggest(&subs.email) {
+            let pending = PendingSubscription {
+                name,
+                email,
+                warning: locale.format("home.did_you_mean", &[("suggestion", suggestion.as_str())]),
+            };
+            return Ok(
+                render_home(Layout::default().with_locale(locale), &protection, Some(pending))
+                    .context("failed to render the typo warning")?,
+            );
+        }
+    }
+
     let mut transaction =pool.begin()
         .await
         .context("failed to get postgre connection from pool")?;
 
-    let subs_id = insert_subscriber(&mut transaction, &subs)
+    let subs_id = insert_subscriber(&mut transaction, &subs, &publication.publication_id, locale)
         .await
         .context("failed to insert new subs to database")?;
 
@@ -63,21 +125,27 @@ pub async fn subscribe(
     store_token(&mut transaction, subs_id, &subs_token)
         .await
         .context("failed to save subs token to database")?;
+    record_event(&mut transaction, EventType::SubscriberCreated, subs_id)
+        .await
+        .context("failed to record the webhook event")?;
+
+    // sent by the worker, the provider being slow or down does not
+    // fail the subscription
+    enqueue_confirmation_email(
+        &mut transaction,
+        &subs,
+        &publication,
+        &subs_token,
+        locale
+        )
+        .await
+        .context("failed to queue email confirmation")?;
 
     transaction
         .commit()
         .await
         .context("failed to commit SQL transaction")?;
 
-    send_confirmation_email(
-        &email_client, 
-        subs, 
-        &base_url.0,
-        &subs_token
-        )
-        .await
-        .context("failed to send email confirmation")?;
-
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -104,31 +172,32 @@ pub async fn store_token(
     Ok(())
 }
 
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    subs: NewSubscriber,
-    base_url: &str,
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subs: &NewSubscriber,
+    publication: &Publication,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    locale: Locale,
+) -> Result<(), sqlx::Error> {
     let confirmation_link = format!(
         "{}/subscribe/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-    let plain_body = format!(
-        "Hello<br />\
-		Click <a href=\"{}\"> here</a> to confirm",
-        confirmation_link
+        publication.base_url, subscription_token
     );
-    let html_body = format!(
-        "Hello<br />\
-  		Click <a href=\"{}\"> here</a> to confirm",
-        confirmation_link
-    );
-    let subject = "hello";
+    let args = [("link", confirmation_link.as_str())];
+    let plain_body = locale.format("email.confirm_subscription.text", &args);
+    let html_body = locale.format("email.confirm_subscription.html", &args);
 
-    email_client
-        .send_email(&subs.email, &subject, &html_body, &plain_body)
-        .await
+    enqueue_email(
+        transaction,
+        OutgoingEmail {
+            publication_id: &publication.publication_id,
+            recipient: &subs.email,
+            subject: locale.t("email.confirm_subscription.subject"),
+            html_content: &html_body,
+            text_content: &plain_body,
+        },
+    )
+    .await
 }
 
 #[tracing::instrument(
@@ -139,17 +208,21 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     req: &NewSubscriber, 
+    publication_id: &str,
+    locale: Locale,
 ) -> Result<Uuid, sqlx::Error> {
     let subs_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-    INSERT INTO subscriber (id, email, name, subscribed_at, status)
-    VALUES($1, $2, $3, $4, 'pending')
+    INSERT INTO subscriber (id, email, name, subscribed_at, status, locale, publication_id)
+    VALUES($1, $2, $3, $4, 'pending', $5, $6)
     "#,
         subs_id,
         req.email.as_ref(),
         req.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        locale.as_str(),
+        publication_id
     )
     .execute(transaction)
     .await
@@ -11,6 +11,9 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const PENDING_TWO_FACTOR_KEY: &'static str = "pending_two_factor_user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -24,6 +27,38 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    // Id of the row in `user_sessions` backing this session
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    // Password has been verified but the second factor has not been
+    // provided yet: the user id is kept aside so that `reject_users`
+    // still treats the session as anonymous.
+    pub fn insert_pending_two_factor(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_TWO_FACTOR_KEY, user_id)
+    }
+
+    pub fn get_pending_two_factor(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_TWO_FACTOR_KEY)
+    }
+
+    pub fn remove_pending_two_factor(&self) {
+        self.0.remove(Self::PENDING_TWO_FACTOR_KEY);
+    }
+
+    pub fn insert_csrf_token(&self, token: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
     pub fn log_out(self) {
         self.0.purge()
     }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{
+    LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::PgPool;
+
+use crate::authentication::purge_user_sessions;
+use crate::shutdown::ShutdownState;
+
+type SessionState = HashMap<String, String>;
+
+const SESSION_KEY_LENGTH: usize = 64;
+// `update_ttl` does not write when the session was renewed this recently,
+// most requests only have to read the session row.
+const RENEWAL_SLACK_SECONDS: f64 = 60.;
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+// Session state kept in the `sessions` table, for deployments without Redis.
+// Expired rows are never loaded, they are deleted by `cleanup_expired_sessions`.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn generate_session_key() -> SessionKey {
+    let mut rng = thread_rng();
+    let key: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(SESSION_KEY_LENGTH)
+        .collect();
+    // 64 characters are well within the length accepted by `SessionKey`
+    key.try_into().expect("Generated an invalid session key")
+}
+
+fn ttl_seconds(ttl: &Duration) -> f64 {
+    ttl.whole_seconds() as f64
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state
+            FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load the session state")
+        .map_err(LoadError::Other)?;
+        match row {
+            Some(row) => serde_json::from_value(row.state)
+                .context("Failed to deserialize the session state")
+                .map(Some)
+                .map_err(LoadError::Deserialization),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, session_state: SessionState, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize the session state")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, now() + make_interval(secs => $3))
+            ON CONFLICT DO NOTHING
+            "#,
+            session_key.as_ref(),
+            state,
+            ttl_seconds(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save the session state")
+        .map_err(SaveError::Other)?
+        .rows_affected();
+        if inserted == 0 {
+            return Err(SaveError::Other(anyhow::anyhow!(
+                "The generated session key collides with an existing session"
+            )));
+        }
+        Ok(session_key)
+    }
+
+    // Like the Redis store, a session that expired in the meantime
+    // is saved again under a new key.
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize the session state")
+            .map_err(UpdateError::Serialization)?;
+        let updated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET state = $2, expires_at = now() + make_interval(secs => $3)
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+            state,
+            ttl_seconds(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session state")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+        if updated == 0 {
+            return self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            });
+        }
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET expires_at = now() + make_interval(secs => $2)
+            WHERE
+                session_key = $1 AND
+                expires_at > now() AND
+                expires_at < now() + make_interval(secs => $3)
+            "#,
+            session_key.as_ref(),
+            ttl_seconds(ttl),
+            ttl_seconds(ttl) - RENEWAL_SLACK_SECONDS
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to renew the session")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session")?;
+        Ok(())
+    }
+}
+
+// Returns the number of sessions deleted.
+#[tracing::instrument(skip_all)]
+pub async fn cleanup_expired_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= now()"#)
+        .execute(pool)
+        .await
+        .context("Failed to delete the expired sessions")?
+        .rows_affected();
+    Ok(deleted)
+}
+
+// Runs `cleanup_expired_sessions` and `purge_user_sessions` every ten
+// minutes until shutdown. Logged in sessions are registered in Postgres
+// whatever the store, so this runs with Redis too.
+pub async fn cleanup_expired_sessions_periodically(pool: PgPool, shutdown: ShutdownState) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(CLEANUP_INTERVAL) => {}
+            _ = shutdown.wait() => return,
+        }
+        if let Err(e) = cleanup_expired_sessions(&pool).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to delete the expired sessions"
+            );
+        }
+        if let Err(e) = purge_user_sessions(&pool).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to delete the expired user sessions"
+            );
+        }
+    }
+}
+
+// The store selected by the `session_store` setting. `SessionMiddleware`
+// is generic over its store, the choice is made at runtime.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Redis(RedisSessionStore),
+    Postgres(PgSessionStore),
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            AppSessionStore::Redis(store) => store.load(session_key).await,
+            AppSessionStore::Postgres(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(&self, session_state: SessionState, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        match self {
+            AppSessionStore::Redis(store) => store.save(session_state, ttl).await,
+            AppSessionStore::Postgres(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            AppSessionStore::Redis(store) => store.update(session_key, session_state, ttl).await,
+            AppSessionStore::Postgres(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        match self {
+            AppSessionStore::Redis(store) => store.update_ttl(session_key, ttl).await,
+            AppSessionStore::Postgres(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            AppSessionStore::Redis(store) => store.delete(session_key).await,
+            AppSessionStore::Postgres(store) => store.delete(session_key).await,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinError;
+
+// Shared by the API and the delivery worker. Once shutdown has begun the
+// instance reports itself as not ready, the server stops accepting
+// connections and the worker stops picking up new tasks.
+#[derive(Clone)]
+pub struct ShutdownState {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+    // held by the work that must not be cut short once started, see
+    // `shielded`
+    in_flight: Arc<RwLock<()>>,
+}
+
+impl Default for ShutdownState {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+            in_flight: Arc::new(RwLock::new(())),
+        }
+    }
+}
+
+impl ShutdownState {
+    pub fn begin(&self) {
+        // there is always at least one receiver, the one we hold
+        let _ = self.sender.send(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // Resolves as soon as shutdown has begun.
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Runs `task` to completion, unless it is still running `deadline` after
+// shutdown has begun: it is then dropped and `None` is returned.
+pub async fn with_deadline<F: Future>(
+    task: F,
+    shutdown: &ShutdownState,
+    deadline: Duration,
+) -> Option<F::Output> {
+    tokio::pin!(task);
+    tokio::select! {
+        output = &mut task => return Some(output),
+        _ = shutdown.wait() => {}
+    }
+    tokio::time::timeout(deadline, task).await.ok()
+}
+
+// Runs `task` on its own: dropping the caller, e.g. past the shutdown
+// deadline, does not drop it. `in_flight_drained` waits for it.
+pub async fn shielded<F>(task: F, shutdown: &ShutdownState) -> Result<F::Output, JoinError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let guard = shutdown.in_flight.clone().read_owned().await;
+    tokio::spawn(async move {
+        let output = task.await;
+        drop(guard);
+        output
+    })
+    .await
+}
+
+// Resolves once no task shielded with `shutdown` is running.
+pub async fn in_flight_drained(shutdown: &ShutdownState) {
+    let _ = shutdown.in_flight.write().await;
+}
+
+// SIGINT everywhere, SIGTERM (sent by orchestrators) on unix.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{in_flight_drained, shielded, with_deadline, ShutdownState};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn tasks_run_to_completion_without_shutdown() {
+        let shutdown = ShutdownState::default();
+        let outcome = with_deadline(async { 42 }, &shutdown, Duration::from_millis(10)).await;
+        assert_eq!(outcome, Some(42));
+    }
+
+    #[tokio::test]
+    async fn tasks_finishing_within_the_deadline_complete() {
+        let shutdown = ShutdownState::default();
+        shutdown.begin();
+        let task = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            42
+        };
+        let outcome = with_deadline(task, &shutdown, Duration::from_secs(5)).await;
+        assert_eq!(outcome, Some(42));
+    }
+
+    #[tokio::test]
+    async fn tasks_are_dropped_past_the_deadline() {
+        let shutdown = ShutdownState::default();
+        let task = std::future::pending::<()>();
+        let signal = shutdown.clone();
+        tokio::spawn(async move { signal.begin() });
+        let outcome = with_deadline(task, &shutdown, Duration::from_millis(10)).await;
+        assert_eq!(outcome, None);
+        assert!(shutdown.is_shutting_down());
+    }
+
+    #[tokio::test]
+    async fn shielded_tasks_outlive_their_caller() {
+        let shutdown = ShutdownState::default();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let task = shielded(
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let _ = sender.send(42);
+            },
+            &shutdown,
+        );
+        shutdown.begin();
+        let outcome = with_deadline(task, &shutdown, Duration::from_millis(10)).await;
+        assert!(outcome.is_none());
+        in_flight_drained(&shutdown).await;
+        assert_eq!(receiver.await, Ok(42));
+    }
+
+    #[tokio::test]
+    async fn each_state_drains_its_own_tasks() {
+        let busy = ShutdownState::default();
+        let idle = ShutdownState::default();
+        let task = tokio::spawn({
+            let busy = busy.clone();
+            async move { shielded(std::future::pending::<()>(), &busy).await }
+        });
+        tokio::task::yield_now().await;
+        let drained =
+            tokio::time::timeout(Duration::from_millis(100), in_flight_drained(&idle)).await;
+        assert!(drained.is_ok());
+        task.abort();
+    }
+}
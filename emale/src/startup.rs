@@ -1,23 +1,55 @@
+use crate::authentication::csrf::reject_invalid_csrf_token;
 use crate::authentication::middleware::reject_users;
+use crate::authentication::{LoginThrottle, PasswordResetThrottle};
+use crate::authentication::two_factor::encrypt_legacy_totp_secrets;
 use crate::configuration::DatabaseSettings;
+use crate::configuration::EmailValidationSettings;
+use crate::configuration::HealthSettings;
+use crate::configuration::LoginThrottleSettings;
+use crate::configuration::PasswordResetThrottleSettings;
+use crate::configuration::SessionStoreKind;
 use crate::configuration::Settings;
+use crate::configuration::SubscribeProtectionSettings;
+use crate::counters::{purge_expired_counters_periodically, CounterStore};
 use crate::email_client::EmailClient;
+use crate::email_validation::EmailValidator;
+use crate::health::ReadinessProbe;
+use crate::idempotency::{enforce_idempotency, require_idempotency};
+use crate::session_store::{
+    cleanup_expired_sessions_periodically, AppSessionStore, PgSessionStore,
+};
+use crate::shutdown::ShutdownState;
+use crate::subscribe_protection::SubscribeProtection;
+use crate::metrics::{metrics, track_http_requests, MetricsBearerToken};
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, login,
-    login_form, logout, publish_newsletter, publish_newsletter_form, subscribe,
+    admin_dashboard, audit_log, change_password, change_password_form, confirm, confirm_two_factor,
+    health_check, health_live, health_ready, home, list_sessions, login, login_form,
+    login_two_factor, login_two_factor_form, logout, password_reset_confirm_form,
+    password_reset_form, publish_newsletter, publish_newsletter_form, request_password_reset,
+    reset_password, revoke_all_other_sessions, revoke_session_by_id, subscribe,
+    turn_off_two_factor, two_factor_form, erase_subscriber_data, export_subscriber_data_json,
+    request_subscriber_data_access, subscriber_data_access, subscriber_data_form,
+    confirm_preferences_email, preferences_form, update_subscriber_preferences,
+    archive_index, archived_issue, feed_atom, feed_rss, list_issues, change_issue_visibility,
+    change_issue_delivery,
+    list_sequences, create_automation_sequence, add_automation_step, change_sequence_activity,
+    list_webhooks, create_webhook_endpoint, webhook_deliveries, change_webhook_activity,
+    resend_webhook_delivery,
 };
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
-use actix_web::cookie::Key;
+use actix_web::cookie::{Key, SameSite};
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
+use sqlx::migrate::Migrator;
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
 use tracing_actix_web::TracingLogger;
 
 #[derive(Serialize)]
@@ -28,6 +60,9 @@ struct Power {
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_server: Option<Server>,
+    shutdown: ShutdownState,
+    readiness_grace: std::time::Duration,
 }
 
 // define a wrapper type in order to retrieve the URL
@@ -36,33 +71,101 @@ pub struct Application {
 // a raw `String` would expose to conflicts.
 pub struct ApplicationBaseUrl(pub String);
 
+// Signs the links to the preferences page of the subscribers.
+pub struct HmacSecret(pub Secret<String>);
+
+// Encrypts the TOTP secrets at rest.
+pub struct TotpEncryptionKey(pub Secret<String>);
+
+// Reverse proxies whose `X-Forwarded-For` is honoured by `client_ip`.
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
 impl Application {
     pub async fn build(config: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&config.database);
         let email_client = config.email_client.client();
         let address = format!("{}:{}", config.application.host, config.application.port);
+        let shutdown = ShutdownState::default();
+        encrypt_legacy_totp_secrets(&config.application.totp_encryption_key, &connection_pool)
+            .await?;
 
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+
+        // with a dedicated listener `/metrics` is not exposed on the application
+        let (metrics_server, metrics_token) = match config.metrics.bind_address {
+            Some(metrics_address) => {
+                let metrics_server = run_metrics_server(
+                    TcpListener::bind(metrics_address)?,
+                    connection_pool.clone(),
+                    config.metrics.bearer_token,
+                )?;
+                (Some(metrics_server), None)
+            }
+            None => (None, config.metrics.bearer_token),
+        };
+
         let server = run(
             listener,
             connection_pool,
             email_client,
             config.application.base_url,
             config.application.hmac_secret,
+            config.application.totp_encryption_key,
+            config.application.secure_cookies,
+            config.application.trusted_proxies,
             config.redis_uri,
+            config.session_store,
+            config.login_throttle,
+            config.password_reset_throttle,
+            config.subscribe_protection,
+            config.email_validation,
+            metrics_token,
+            config.health,
+            shutdown.clone(),
+            config.shutdown.deadline(),
         )
         .await?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_server,
+            shutdown,
+            readiness_grace: config.shutdown.readiness_grace(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    // Flipped when a shutdown starts, `/health/ready` fails from then on.
+    pub fn shutdown_state(&self) -> ShutdownState {
+        self.shutdown.clone()
+    }
+
+    // Signals are not handled by actix: the servers stop gracefully, after
+    // the readiness grace period, once the shutdown state is flipped.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let mut handles = vec![self.server.handle()];
+        if let Some(metrics_server) = &self.metrics_server {
+            handles.push(metrics_server.handle());
+        }
+        let shutdown = self.shutdown.clone();
+        let readiness_grace = self.readiness_grace;
+        tokio::spawn(async move {
+            shutdown.wait().await;
+            tokio::time::sleep(readiness_grace).await;
+            for handle in handles {
+                handle.stop(true).await;
+            }
+        });
+
+        match self.metrics_server {
+            Some(metrics_server) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+            None => self.server.await,
+        }
     }
 }
 
@@ -75,6 +178,9 @@ async fn pow2(req: HttpRequest) -> impl Responder {
     HttpResponse::Ok().json(&res)
 }
 
+// Migrations embedded in the binary, applied by `emale migrate`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .connect_timeout(std::time::Duration::from_secs(2))
@@ -87,47 +193,222 @@ pub async fn run(
     email_client: EmailClient,
     base_url: String,
     hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    totp_encryption_key: Secret<String>,
+    secure_cookies: bool,
+    trusted_proxies: Vec<IpAddr>,
+    redis_uri: Option<Secret<String>>,
+    session_store: SessionStoreKind,
+    login_throttle: LoginThrottleSettings,
+    password_reset_throttle: PasswordResetThrottleSettings,
+    subscribe_protection: SubscribeProtectionSettings,
+    email_validation: EmailValidationSettings,
+    // `/metrics` is only mounted when a token is configured
+    metrics_token: Option<Secret<String>>,
+    health: HealthSettings,
+    shutdown: ShutdownState,
+    // in-flight requests are dropped if they are still running this
+    // long after the server was asked to stop
+    shutdown_deadline: std::time::Duration,
 ) -> Result<Server, anyhow::Error> {
+    let session_store = match session_store {
+        SessionStoreKind::Redis => {
+            let redis_uri = redis_uri
+                .as_ref()
+                .context("`session_store: redis` requires a `redis_uri`")?;
+            AppSessionStore::Redis(RedisSessionStore::new(redis_uri.expose_secret()).await?)
+        }
+        SessionStoreKind::Postgres => AppSessionStore::Postgres(PgSessionStore::new(db_pool.clone())),
+    };
+    tokio::spawn(cleanup_expired_sessions_periodically(
+        db_pool.clone(),
+        shutdown.clone(),
+    ));
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let trusted_proxies = web::Data::new(TrustedProxies(trusted_proxies));
+    let counters = CounterStore::connect(redis_uri.as_ref(), &db_pool).await?;
+    if let CounterStore::Postgres(pool) = &counters {
+        tokio::spawn(purge_expired_counters_periodically(
+            pool.clone(),
+            shutdown.clone(),
+        ));
+    }
+    let login_throttle = web::Data::new(LoginThrottle::new(counters.clone(), login_throttle));
+    let password_reset_throttle = web::Data::new(PasswordResetThrottle::new(
+        counters.clone(),
+        password_reset_throttle,
+    ));
+    let subscribe_protection = web::Data::new(SubscribeProtection::new(
+        counters,
+        subscribe_protection,
+        hmac_secret.clone(),
+    ));
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let totp_encryption_key = web::Data::new(TotpEncryptionKey(totp_encryption_key));
+    let email_validator = web::Data::new(EmailValidator::from_settings(email_validation)?);
+    let readiness_probe = web::Data::new(ReadinessProbe::new(
+        redis_uri.as_ref().map(|uri| uri.expose_secret().as_str()),
+        health,
+    )?);
+    let shutdown = web::Data::new(shutdown);
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
+    let mount_metrics = metrics_token.is_some();
+    let metrics_token = web::Data::new(MetricsBearerToken(metrics_token));
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             // middlewares
+            .wrap(from_fn(track_http_requests))
             .wrap(TracingLogger::default())
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            // `Lax` keeps the session on top-level navigations from other
+            // sites (e.g. links in emails) but not on cross-site POSTs
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .cookie_secure(secure_cookies)
+                    .cookie_same_site(SameSite::Lax)
+                    .build(),
+            )
             // routes
             .route("/", web::get().to(home))
             .route("/pow2/{num}", web::get().to(pow2))
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
-            .route("/subscribe", web::post().to(subscribe))
+            .route("/login/two-factor", web::get().to(login_two_factor_form))
+            .route("/login/two-factor", web::post().to(login_two_factor))
+            .route("/password-reset", web::get().to(password_reset_form))
+            .route("/password-reset", web::post().to(request_password_reset))
+            .route("/password-reset/confirm", web::get().to(password_reset_confirm_form))
+            .route("/password-reset/confirm", web::post().to(reset_password))
+            .service(
+                web::resource("/subscribe")
+                    .wrap(from_fn(enforce_idempotency))
+                    .route(web::post().to(subscribe)),
+            )
             .route("/subscribe/confirm", web::get().to(confirm))
+            .route("/preferences", web::get().to(preferences_form))
+            .route("/preferences", web::post().to(update_subscriber_preferences))
+            .route("/preferences/confirm-email", web::get().to(confirm_preferences_email))
+            .route("/subscriber-data", web::get().to(subscriber_data_form))
+            .route("/subscriber-data", web::post().to(request_subscriber_data_access))
+            .route("/subscriber-data/access", web::get().to(subscriber_data_access))
+            .route("/subscriber-data/export", web::get().to(export_subscriber_data_json))
+            .route("/subscriber-data/erase", web::post().to(erase_subscriber_data))
+            .route("/archive", web::get().to(archive_index))
+            .route("/archive/{issue_id}", web::get().to(archived_issue))
+            .route("/feed.rss", web::get().to(feed_rss))
+            .route("/feed.atom", web::get().to(feed_atom))
             .service(
                 web::scope("/admin")
+                    .wrap(from_fn(reject_invalid_csrf_token))
                     .wrap(from_fn(reject_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
-                    .route("/newsletters", web::get().to(publish_newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter))
-                    .route("/password", web::get().to(change_password_form))
-                    .route("/password", web::post().to(change_password))
+                    .service(
+                        web::resource("/newsletters")
+                            .wrap(from_fn(require_idempotency))
+                            .route(web::get().to(publish_newsletter_form))
+                            .route(web::post().to(publish_newsletter)),
+                    )
+                    .route("/issues", web::get().to(list_issues))
+                    .route(
+                        "/issues/{issue_id}/visibility",
+                        web::post().to(change_issue_visibility),
+                    )
+                    .route(
+                        "/issues/{issue_id}/delivery",
+                        web::post().to(change_issue_delivery),
+                    )
+                    .route("/sequences", web::get().to(list_sequences))
+                    .route("/sequences", web::post().to(create_automation_sequence))
+                    .route(
+                        "/sequences/{sequence_id}/steps",
+                        web::post().to(add_automation_step),
+                    )
+                    .route(
+                        "/sequences/{sequence_id}/active",
+                        web::post().to(change_sequence_activity),
+                    )
+                    .route("/webhooks", web::get().to(list_webhooks))
+                    .route("/webhooks", web::post().to(create_webhook_endpoint))
+                    .route("/webhooks/{endpoint_id}", web::get().to(webhook_deliveries))
+                    .route(
+                        "/webhooks/{endpoint_id}/active",
+                        web::post().to(change_webhook_activity),
+                    )
+                    .route(
+                        "/webhooks/deliveries/{delivery_id}/resend",
+                        web::post().to(resend_webhook_delivery),
+                    )
+                    .service(
+                        web::resource("/password")
+                            .wrap(from_fn(enforce_idempotency))
+                            .route(web::get().to(change_password_form))
+                            .route(web::post().to(change_password)),
+                    )
+                    .route("/two-factor", web::get().to(two_factor_form))
+                    .route("/two-factor", web::post().to(confirm_two_factor))
+                    .route("/two-factor/disable", web::post().to(turn_off_two_factor))
+                    .route("/audit", web::get().to(audit_log))
+                    .route("/sessions", web::get().to(list_sessions))
+                    .route(
+                        "/sessions/revoke-others",
+                        web::post().to(revoke_all_other_sessions),
+                    )
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(revoke_session_by_id),
+                    )
                     .route("/logout", web::post().to(logout)),
             )
             // app pool
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(totp_encryption_key.clone())
+            .app_data(login_throttle.clone())
+            .app_data(password_reset_throttle.clone())
+            .app_data(subscribe_protection.clone())
+            .app_data(email_validator.clone())
+            .app_data(metrics_token.clone())
+            .app_data(readiness_probe.clone())
+            .app_data(shutdown.clone());
+        if mount_metrics {
+            app.route("/metrics", web::get().to(metrics))
+        } else {
+            app
+        }
+    })
+    .disable_signals()
+    .shutdown_timeout(shutdown_deadline.as_secs())
+    .listen(address)?
+    .run();
+    Ok(server)
+}
+
+// Serves nothing but `/metrics`, meant to be bound to an address
+// that only the scraper can reach.
+pub fn run_metrics_server(
+    address: TcpListener,
+    db_pool: PgPool,
+    bearer_token: Option<Secret<String>>,
+) -> Result<Server, anyhow::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let bearer_token = web::Data::new(MetricsBearerToken(bearer_token));
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::default())
+            .route("/metrics", web::get().to(metrics))
+            .app_data(db_pool.clone())
+            .app_data(bearer_token.clone())
     })
+    .disable_signals()
     .listen(address)?
     .run();
     Ok(server)
@@ -0,0 +1,106 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::configuration::ChallengeSettings;
+
+// Checks the response of a challenge (captcha-like) widget submitted with
+// the subscribe form, in its `challenge_response` field.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+	async fn verify(&self, response: Option<&str>, client_ip: &str) -> Result<bool, anyhow::Error>;
+}
+
+// No challenge is configured, every submission passes.
+pub struct NoChallenge;
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for NoChallenge {
+	async fn verify(&self, _response: Option<&str>, _client_ip: &str) -> Result<bool, anyhow::Error> {
+		Ok(true)
+	}
+}
+
+// Stand-in for tests and local development: the expected
+// response is known in advance.
+pub struct LocalChallenge {
+	expected_response: String,
+}
+
+impl LocalChallenge {
+	pub fn new(expected_response: String) -> Self {
+		Self { expected_response }
+	}
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for LocalChallenge {
+	async fn verify(&self, response: Option<&str>, _client_ip: &str) -> Result<bool, anyhow::Error> {
+		Ok(response == Some(self.expected_response.as_str()))
+	}
+}
+
+// The `siteverify` API shared by reCAPTCHA, hCaptcha and Turnstile.
+pub struct SiteVerifyChallenge {
+	http_client: reqwest::Client,
+	verify_url: String,
+	secret: Secret<String>,
+}
+
+impl SiteVerifyChallenge {
+	pub fn new(verify_url: String, secret: Secret<String>) -> Self {
+		let http_client = reqwest::Client::builder()
+			.timeout(std::time::Duration::from_secs(5))
+			.build()
+			.expect("Failed to build the challenge HTTP client");
+		Self {
+			http_client,
+			verify_url,
+			secret,
+		}
+	}
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+	success: bool,
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for SiteVerifyChallenge {
+	#[tracing::instrument(name = "Verify challenge response", skip(self, response))]
+	async fn verify(&self, response: Option<&str>, client_ip: &str) -> Result<bool, anyhow::Error> {
+		let response = match response {
+			Some(response) if !response.is_empty() => response,
+			_ => return Ok(false),
+		};
+		let outcome = self
+			.http_client
+			.post(&self.verify_url)
+			.form(&[
+				("secret", self.secret.expose_secret().as_str()),
+				("response", response),
+				("remoteip", client_ip),
+			])
+			.send()
+			.await
+			.context("Failed to reach the challenge provider")?
+			.error_for_status()
+			.context("The challenge provider returned an error")?
+			.json::<SiteVerifyResponse>()
+			.await
+			.context("Failed to parse the challenge provider response")?;
+		Ok(outcome.success)
+	}
+}
+
+pub fn challenge_verifier(settings: &ChallengeSettings) -> Box<dyn ChallengeVerifier> {
+	match settings {
+		ChallengeSettings::None => Box::new(NoChallenge),
+		ChallengeSettings::Local { expected_response } => {
+			Box::new(LocalChallenge::new(expected_response.clone()))
+		}
+		ChallengeSettings::SiteVerify { verify_url, secret, .. } => {
+			Box::new(SiteVerifyChallenge::new(verify_url.clone(), secret.clone()))
+		}
+	}
+}
@@ -0,0 +1,67 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+// `<issued at, unix seconds>.<hex HMAC-SHA256 of it>`, embedded in the
+// subscribe form so that the time spent filling it can be checked
+// without keeping any state server side.
+
+fn mac(secret: &Secret<String>, issued_at: i64) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+		.expect("HMAC can take a key of any size");
+	mac.update(format!("subscribe-form:{}", issued_at).as_bytes());
+	mac
+}
+
+pub fn issue_form_token(secret: &Secret<String>, issued_at: i64) -> String {
+	let signature = hex::encode(mac(secret, issued_at).finalize().into_bytes());
+	format!("{}.{}", issued_at, signature)
+}
+
+// Seconds elapsed since the token was issued, `None` if it was not
+// issued by us.
+pub fn form_token_age(secret: &Secret<String>, token: &str, now: i64) -> Option<i64> {
+	let (issued_at, signature) = token.split_once('.')?;
+	let issued_at: i64 = issued_at.parse().ok()?;
+	let signature = hex::decode(signature).ok()?;
+	mac(secret, issued_at).verify_slice(&signature).ok()?;
+	Some(now - issued_at)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{form_token_age, issue_form_token};
+	use claim::{assert_none, assert_some_eq};
+	use secrecy::Secret;
+
+	fn secret() -> Secret<String> {
+		Secret::new("form-token-secret".into())
+	}
+
+	#[test]
+	fn age_of_a_valid_token_is_returned() {
+		let token = issue_form_token(&secret(), 1_000);
+		assert_some_eq!(form_token_age(&secret(), &token, 1_005), 5);
+	}
+
+	#[test]
+	fn tampered_timestamps_are_rejected() {
+		let token = issue_form_token(&secret(), 1_000);
+		let (_, signature) = token.split_once('.').unwrap();
+		let forged = format!("900.{}", signature);
+		assert_none!(form_token_age(&secret(), &forged, 1_005));
+	}
+
+	#[test]
+	fn tokens_signed_with_another_secret_are_rejected() {
+		let token = issue_form_token(&Secret::new("another-secret".into()), 1_000);
+		assert_none!(form_token_age(&secret(), &token, 1_005));
+	}
+
+	#[test]
+	fn malformed_tokens_are_rejected() {
+		for token in ["", "1000", "1000.", "abc.def", "1000.not-hex"] {
+			assert_none!(form_token_age(&secret(), token, 1_005));
+		}
+	}
+}
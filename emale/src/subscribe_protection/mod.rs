@@ -0,0 +1,151 @@
+mod challenge;
+mod form_token;
+
+pub use challenge::{challenge_verifier, ChallengeVerifier, LocalChallenge, NoChallenge, SiteVerifyChallenge};
+pub use form_token::{form_token_age, issue_form_token};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use secrecy::Secret;
+
+use crate::configuration::{ChallengeSettings, SubscribeProtectionSettings};
+use crate::counters::CounterStore;
+
+static SUBSCRIBE_REJECTIONS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+	register_int_counter_vec!(
+		"subscribe_rejections_total",
+		"Number of subscribe attempts silently rejected, per reason",
+		&["reason"]
+	)
+	.expect("Failed to register subscribe_rejections_total")
+});
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RejectionReason {
+	IpRateLimit,
+	Honeypot,
+	InvalidFormToken,
+	FilledTooFast,
+	Challenge,
+	DomainRateLimit,
+}
+
+impl RejectionReason {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			RejectionReason::IpRateLimit => "ip_rate_limit",
+			RejectionReason::Honeypot => "honeypot",
+			RejectionReason::InvalidFormToken => "invalid_form_token",
+			RejectionReason::FilledTooFast => "filled_too_fast",
+			RejectionReason::Challenge => "challenge",
+			RejectionReason::DomainRateLimit => "domain_rate_limit",
+		}
+	}
+}
+
+pub fn record_rejection(reason: RejectionReason) {
+	SUBSCRIBE_REJECTIONS_TOTAL
+		.with_label_values(&[reason.as_str()])
+		.inc();
+}
+
+// The anti-abuse fields of the subscribe form.
+pub struct SubmittedForm {
+	// hidden from humans, only bots fill it
+	pub honeypot: Option<String>,
+	pub form_token: Option<String>,
+	pub challenge_response: Option<String>,
+}
+
+// Rendered next to the subscribe form when a `site_verify` challenge is configured.
+pub struct ChallengeWidget {
+	pub script_url: String,
+	pub site_key: String,
+}
+
+// Checks run on every `POST /subscribe` before anything is stored or sent,
+// so that the endpoint cannot be used to spam third parties.
+pub struct SubscribeProtection {
+	counters: CounterStore,
+	settings: SubscribeProtectionSettings,
+	form_secret: Secret<String>,
+	challenge: Box<dyn ChallengeVerifier>,
+}
+
+impl SubscribeProtection {
+	pub fn new(
+		counters: CounterStore,
+		settings: SubscribeProtectionSettings,
+		form_secret: Secret<String>
+	) -> Self {
+		let challenge = challenge_verifier(&settings.challenge);
+		Self { counters, settings, form_secret, challenge }
+	}
+
+	pub fn issue_form_token(&self) -> String {
+		issue_form_token(&self.form_secret, chrono::Utc::now().timestamp())
+	}
+
+	pub fn challenge_widget(&self) -> Option<ChallengeWidget> {
+		match &self.settings.challenge {
+			ChallengeSettings::SiteVerify { script_url, site_key, .. } => Some(ChallengeWidget {
+				script_url: script_url.clone(),
+				site_key: site_key.clone(),
+			}),
+			_ => None,
+		}
+	}
+
+	// `None` when the confirmation email can be sent. Every attempt counts
+	// against the client IP, only the accepted ones against the domain.
+	#[tracing::instrument(name = "Check subscribe attempt", skip(self, form))]
+	pub async fn rejection_reason(
+		&self,
+		form: &SubmittedForm,
+		email_domain: &str,
+		client_ip: &str
+	) -> Result<Option<RejectionReason>, anyhow::Error> {
+		if self.count("ip", client_ip).await? > self.settings.max_attempts_per_ip {
+			return Ok(Some(RejectionReason::IpRateLimit));
+		}
+		if form.honeypot.as_deref().map_or(false, |h| !h.is_empty()) {
+			return Ok(Some(RejectionReason::Honeypot));
+		}
+		let now = chrono::Utc::now().timestamp();
+		let age = form
+			.form_token
+			.as_deref()
+			.and_then(|token| form_token_age(&self.form_secret, token, now));
+		match age {
+			None => return Ok(Some(RejectionReason::InvalidFormToken)),
+			Some(age) if age > self.settings.max_form_age_seconds as i64 => {
+				return Ok(Some(RejectionReason::InvalidFormToken))
+			}
+			Some(age) if age < self.settings.min_fill_seconds as i64 => {
+				return Ok(Some(RejectionReason::FilledTooFast))
+			}
+			Some(_) => {}
+		}
+		if !self
+			.challenge
+			.verify(form.challenge_response.as_deref(), client_ip)
+			.await?
+		{
+			return Ok(Some(RejectionReason::Challenge));
+		}
+		if self.count("domain", &email_domain.to_lowercase()).await? > self.settings.max_emails_per_domain {
+			return Ok(Some(RejectionReason::DomainRateLimit));
+		}
+		Ok(None)
+	}
+
+	// Same sliding window as the login throttle.
+	async fn count(&self, scope: &str, subject: &str) -> Result<u64, anyhow::Error> {
+		let key = format!("{}:{}:{}", self.settings.key_prefix, scope, subject);
+		self.counters
+			.hit(&key, self.settings.window_seconds)
+			.await
+			.context("Failed to count subscribe attempts")
+	}
+}
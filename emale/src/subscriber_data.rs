@@ -0,0 +1,321 @@
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::automation::{get_subscriber_progress, SequenceProgress};
+use crate::domain::SubscriberEmail;
+use crate::i18n::Locale;
+use crate::issue_delivery_worker::record_delivery;
+use crate::webhooks::{forget_subscriber_events, record_event, EventType};
+
+// Everything we hold about a subscriber is reached through a short-lived
+// link sent to their address: the link grants access to the export and
+// to the erasure of their data, nothing else.
+
+const ACCESS_TOKEN_LENGTH: usize = 32;
+const ACCESS_TOKEN_TTL_MINUTES: i64 = 60;
+
+pub struct DataAccessRecipient {
+	pub subscriber_id: Uuid,
+	pub email: SubscriberEmail,
+	pub locale: Locale,
+}
+
+fn generate_access_token() -> String {
+	let mut rng = thread_rng();
+	std::iter::repeat_with(|| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(ACCESS_TOKEN_LENGTH)
+		.collect()
+}
+
+fn hash_access_token(token: &str) -> String {
+	hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[tracing::instrument(name = "Get data access recipient", skip(email, pool))]
+pub async fn get_data_access_recipient(
+	email: &str,
+	publication_id: &str,
+	pool: &PgPool
+) -> Result<Option<DataAccessRecipient>, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT id, email, locale
+		FROM subscriber
+		WHERE lower(email) = lower($1) AND publication_id = $2
+		"#,
+		email,
+		publication_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to perform a query to retrieve the subscriber")?;
+	match row {
+		Some(row) => {
+			let email = SubscriberEmail::parse(row.email).map_err(|e| anyhow::anyhow!(e))?;
+			Ok(Some(DataAccessRecipient {
+				subscriber_id: row.id,
+				email,
+				locale: Locale::parse(&row.locale).unwrap_or_default(),
+			}))
+		}
+		None => Ok(None),
+	}
+}
+
+// Issuing a new token invalidates the ones sent before.
+#[tracing::instrument(name = "Create data access token", skip(pool))]
+pub async fn create_data_access_token(
+	subscriber_id: Uuid,
+	pool: &PgPool
+) -> Result<String, anyhow::Error> {
+	let token = generate_access_token();
+	let mut transaction = pool.begin().await?;
+	sqlx::query!(
+		r#"DELETE FROM subscriber_data_tokens WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to invalidate previous data access tokens")?;
+	sqlx::query!(
+		r#"
+		INSERT INTO subscriber_data_tokens (token_hash, subscriber_id, created_at, expires_at)
+		VALUES ($1, $2, now(), now() + make_interval(mins => $3))
+		"#,
+		hash_access_token(&token),
+		subscriber_id,
+		ACCESS_TOKEN_TTL_MINUTES as i32
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to store data access token")?;
+	transaction.commit().await?;
+	Ok(token)
+}
+
+// The subscriber the token was issued to, if it has not expired.
+#[tracing::instrument(name = "Check data access token", skip(token, pool))]
+pub async fn get_subscriber_from_access_token(
+	token: &str,
+	pool: &PgPool
+) -> Result<Option<Uuid>, anyhow::Error> {
+	let row = sqlx::query!(
+		r#"
+		SELECT subscriber_id
+		FROM subscriber_data_tokens
+		WHERE token_hash = $1 AND expires_at > now()
+		"#,
+		hash_access_token(token)
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to check data access token")?;
+	Ok(row.map(|r| r.subscriber_id))
+}
+
+#[derive(Serialize, Debug)]
+pub struct SubscriberRecord {
+	pub id: Uuid,
+	pub email: String,
+	pub name: String,
+	pub status: String,
+	pub subscribed_at: String,
+	pub locale: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PendingDelivery {
+	pub newsletter_issue_id: Uuid,
+	pub title: String,
+}
+
+// Timestamps are RFC 3339 strings.
+#[derive(Serialize, Debug)]
+pub struct SubscriberDataExport {
+	pub subscriber: SubscriberRecord,
+	pub confirmation_tokens: Vec<String>,
+	// issues queued for the subscriber and not delivered yet, delivered
+	// ones are not kept
+	pub pending_deliveries: Vec<PendingDelivery>,
+	// lists the subscriber asked not to receive
+	pub list_opt_outs: Vec<String>,
+	// new address waiting for confirmation
+	pub pending_email_change: Option<String>,
+	// where the subscriber stands in the automation sequences
+	pub sequence_progress: Vec<SequenceProgress>,
+	pub exported_at: String,
+}
+
+#[tracing::instrument(name = "Export subscriber data", skip(pool))]
+pub async fn export_subscriber_data(
+	subscriber_id: Uuid,
+	pool: &PgPool
+) -> Result<Option<SubscriberDataExport>, anyhow::Error> {
+	let (subscriber, publication_id) = match sqlx::query!(
+		r#"
+		SELECT id, email, name, status, subscribed_at, locale, publication_id
+		FROM subscriber
+		WHERE id = $1
+		"#,
+		subscriber_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to retrieve the subscriber")?
+	{
+		Some(r) => (
+			SubscriberRecord {
+				id: r.id,
+				email: r.email,
+				name: r.name,
+				status: r.status,
+				subscribed_at: r.subscribed_at.to_rfc3339(),
+				locale: r.locale,
+			},
+			r.publication_id,
+		),
+		None => return Ok(None),
+	};
+	let confirmation_tokens = sqlx::query!(
+		r#"SELECT subscriber_token FROM subscriber_tokens WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the confirmation tokens")?
+	.into_iter()
+	.map(|r| r.subscriber_token)
+	.collect();
+	let pending_deliveries = sqlx::query_as!(
+		PendingDelivery,
+		r#"
+		SELECT q.newsletter_issue_id, i.title
+		FROM issue_delivery_queue q
+		JOIN newsletter_issues i USING (newsletter_issue_id)
+		WHERE q.subscriber_email = $1 AND q.publication_id = $2
+		ORDER BY i.title
+		"#,
+		subscriber.email,
+		publication_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the pending deliveries")?;
+	let list_opt_outs = sqlx::query!(
+		r#"SELECT list_id FROM subscriber_list_opt_outs WHERE subscriber_id = $1 ORDER BY list_id"#,
+		subscriber_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the list opt-outs")?
+	.into_iter()
+	.map(|r| r.list_id)
+	.collect();
+	let pending_email_change = sqlx::query!(
+		r#"SELECT new_email FROM subscriber_email_changes WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to retrieve the pending email change")?
+	.map(|r| r.new_email);
+	let sequence_progress = get_subscriber_progress(pool, subscriber_id).await?;
+	Ok(Some(SubscriberDataExport {
+		subscriber,
+		confirmation_tokens,
+		pending_deliveries,
+		list_opt_outs,
+		pending_email_change,
+		sequence_progress,
+		exported_at: chrono::Utc::now().to_rfc3339(),
+	}))
+}
+
+// Hard-deletes the subscriber and everything referencing them. Their
+// pending deliveries are dropped and counted as erased, in the
+// `erased_deliveries` of their issue and in `issue_deliveries_total`, so
+// that the per-issue totals still add up.
+// Returns false if there was nothing to erase.
+#[tracing::instrument(name = "Erase subscriber data", skip(pool))]
+pub async fn erase_subscriber(subscriber_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	let subscriber = match sqlx::query!(
+		r#"SELECT email, publication_id FROM subscriber WHERE id = $1 FOR UPDATE"#,
+		subscriber_id
+	)
+	.fetch_optional(&mut transaction)
+	.await
+	.context("Failed to lock the subscriber")?
+	{
+		Some(r) => r,
+		None => return Ok(false),
+	};
+	// waits for a delivery to this subscriber that is in flight
+	let dropped_deliveries: Vec<Uuid> = sqlx::query!(
+		r#"
+		DELETE FROM issue_delivery_queue
+		WHERE subscriber_email = $1 AND publication_id = $2
+		RETURNING newsletter_issue_id
+		"#,
+		subscriber.email,
+		subscriber.publication_id
+	)
+	.fetch_all(&mut transaction)
+	.await
+	.context("Failed to drop the pending deliveries")?
+	.into_iter()
+	.map(|r| r.newsletter_issue_id)
+	.collect();
+	sqlx::query!(
+		r#"
+		UPDATE newsletter_issues
+		SET erased_deliveries = erased_deliveries + 1
+		WHERE newsletter_issue_id = ANY($1)
+		"#,
+		&dropped_deliveries
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to count the erased deliveries")?;
+	sqlx::query!(
+		r#"DELETE FROM email_outbox WHERE recipient = $1 AND publication_id = $2"#,
+		subscriber.email,
+		subscriber.publication_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to drop the queued emails")?;
+	sqlx::query!(
+		r#"DELETE FROM subscriber_tokens WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.execute(&mut transaction)
+	.await
+	.context("Failed to delete the confirmation tokens")?;
+	// the CRM hears about it by subscriber id; earlier events, which
+	// carry the details of the subscriber, are dropped
+	forget_subscriber_events(&mut transaction, subscriber_id)
+		.await
+		.context("Failed to drop the webhook events")?;
+	record_event(&mut transaction, EventType::SubscriberUnsubscribed, subscriber_id)
+		.await
+		.context("Failed to record the webhook event")?;
+	// access tokens, opt-outs, email changes and sequence progress go with
+	// the row
+	sqlx::query!(r#"DELETE FROM subscriber WHERE id = $1"#, subscriber_id)
+		.execute(&mut transaction)
+		.await
+		.context("Failed to delete the subscriber")?;
+	transaction.commit().await?;
+
+	for issue_id in dropped_deliveries {
+		record_delivery(issue_id, "erased");
+	}
+	Ok(true)
+}
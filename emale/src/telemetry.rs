@@ -1,14 +1,29 @@
+use std::collections::HashMap;
+
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
 use tokio::task::JoinHandle;
-use tracing::{subscriber::set_global_default, Subscriber};
+use tracing::{subscriber::set_global_default, Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::TelemetrySettings;
+
+const TRACEPARENT: &str = "traceparent";
+
+// `tracer` is the OTLP exporter returned by `get_otlp_tracer`, if any.
 pub fn get_tracing_subscriber<Sink>(
     name: String,
     env_fliter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 // a higher-ranked trait bound (HRTB)
 // It basically means that Sink implements the `MakeWriter`
@@ -20,13 +35,66 @@ where
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_fliter));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let otlp_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
 
     Registry::default()
+        .with(otlp_layer)
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
+// Installs the W3C trace context propagator, used by `TracingLogger` for
+// incoming requests and by `current_traceparent` for outgoing ones, and
+// builds the OTLP exporter when an endpoint is configured.
+// Must be called from within the tokio runtime.
+pub fn get_otlp_tracer(settings: &TelemetrySettings) -> Result<Option<Tracer>, anyhow::Error> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let endpoint = match &settings.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )])))
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    Ok(Some(tracer))
+}
+
+// Flushes the spans still buffered by the batch exporter.
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
+
+// `traceparent` header value for the current span, `None` when
+// the span is not exported.
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove(TRACEPARENT)
+}
+
+// Links the current span to the trace identified by `traceparent`,
+// e.g. the request that enqueued the work being done.
+pub fn link_to_traceparent(traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    let span_context = context.span().span_context().clone();
+    if span_context.is_valid() {
+        Span::current().add_link(span_context);
+    }
+}
+
 pub fn init_tracing_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to subcsribe log");
@@ -0,0 +1,130 @@
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::i18n::Locale;
+use crate::utils::error_500;
+
+// Values used by `base.html`, every page template has a `layout` field.
+#[derive(Default)]
+pub struct Layout {
+    pub flash_messages: Vec<String>,
+    // Only set for logged in users: it switches the navigation to the
+    // admin links and fills the hidden field of `csrf_field.html`.
+    pub csrf_token: Option<String>,
+    // Public pages are translated, admin pages stay in English.
+    pub locale: Locale,
+}
+
+impl Layout {
+    pub fn new(flash_messages: &IncomingFlashMessages) -> Self {
+        Self {
+            flash_messages: flash_messages
+                .iter()
+                .map(|m| m.content().to_owned())
+                .collect(),
+            csrf_token: None,
+            locale: Locale::default(),
+        }
+    }
+
+    pub fn with_csrf_token(mut self, csrf_token: String) -> Self {
+        self.csrf_token = Some(csrf_token);
+        self
+    }
+
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+}
+
+pub fn render<T: Template>(template: &T) -> Result<HttpResponse, actix_web::Error> {
+    try_render(template).map_err(error_500)
+}
+
+// For the handlers with an error type of their own.
+pub fn try_render<T: Template>(template: &T) -> Result<HttpResponse, askama::Error> {
+    let body = template.render()?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Layout;
+    use crate::i18n::Locale;
+    use crate::routes::PendingSubscription;
+    use crate::subscribe_protection::ChallengeWidget;
+    use askama::Template;
+    use uuid::Uuid;
+
+    #[derive(Template)]
+    #[template(path = "home.html")]
+    struct HomeTemplate {
+        layout: Layout,
+        form_token: String,
+        idempotency_key: Uuid,
+        challenge: Option<ChallengeWidget>,
+        locales: [Locale; 2],
+        pending: Option<PendingSubscription>,
+    }
+
+    fn home(layout: Layout) -> HomeTemplate {
+        HomeTemplate {
+            layout,
+            form_token: "form-token".into(),
+            idempotency_key: Uuid::nil(),
+            challenge: None,
+            locales: Locale::ALL,
+            pending: None,
+        }
+    }
+
+    #[test]
+    fn flash_messages_are_escaped() {
+        let template = home(Layout {
+            flash_messages: vec!["<script>alert('flash')</script>".into()],
+            ..Layout::default()
+        });
+        let html = template.render().unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<p><i>&lt;script&gt;alert(&#x27;flash&#x27;)&lt;/script&gt;</i></p>"));
+    }
+
+    #[test]
+    fn csrf_token_is_only_rendered_for_logged_in_users() {
+        let anonymous = home(Layout::default());
+        assert!(!anonymous.render().unwrap().contains("csrf_token"));
+
+        let logged_in = home(Layout::default().with_csrf_token("token".into()));
+        assert!(logged_in
+            .render()
+            .unwrap()
+            .contains(r#"<input hidden type="text" name="csrf_token" value="token">"#));
+    }
+
+    #[test]
+    fn public_pages_follow_the_locale() {
+        let html = home(Layout::default().with_locale(Locale::Fr)).render().unwrap();
+        assert!(html.contains(r#"<html lang="fr">"#));
+        assert!(html.contains("S&#x27;abonner"));
+    }
+
+    #[test]
+    fn pending_subscriptions_are_filled_back_in_and_escaped() {
+        let mut template = home(Layout::default());
+        template.pending = Some(PendingSubscription {
+            name: "<le guin>".into(),
+            email: "ursula@gmial.com".into(),
+            warning: "Did you mean ursula@gmail.com?".into(),
+        });
+        let html = template.render().unwrap();
+        assert!(html.contains(r#"value="&lt;le guin&gt;""#));
+        assert!(html.contains(r#"value="ursula@gmial.com""#));
+        assert!(html.contains("Did you mean ursula@gmail.com?"));
+        assert!(html.contains(r#"<input type="hidden" name="confirm_email" value="true">"#));
+    }
+}
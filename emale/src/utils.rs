@@ -1,5 +1,9 @@
-use actix_http::header::LOCATION;
-use actix_web::HttpResponse;
+use std::net::IpAddr;
+
+use actix_http::header::{HOST, LOCATION, USER_AGENT};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::startup::TrustedProxies;
 
 pub fn error_500<T>(e: T) -> actix_web::Error
 where
@@ -22,3 +26,79 @@ pub fn see_other(location: &str) -> HttpResponse {
 			.insert_header((LOCATION, location))
 			.finish()
 }
+
+fn trusted_proxies(req: &HttpRequest) -> &[IpAddr] {
+	req.app_data::<web::Data<TrustedProxies>>()
+		.map(|proxies| proxies.0.as_slice())
+		.unwrap_or_default()
+}
+
+// The address of the peer. `X-Forwarded-For` is only honoured when the
+// peer is one of the trusted proxies, anyone could set it otherwise.
+pub fn client_ip(req: &HttpRequest) -> String {
+	let peer = req.peer_addr().map(|addr| addr.ip());
+	let trusted_proxies = trusted_proxies(req);
+	match peer {
+		Some(ip) if trusted_proxies.contains(&ip) => forwarded_for(req, trusted_proxies)
+			.unwrap_or(ip)
+			.to_string(),
+		Some(ip) => ip.to_string(),
+		None => "unknown".to_owned(),
+	}
+}
+
+// The rightmost address that is not one of our proxies: the ones on its
+// left were sent by the client.
+fn forwarded_for(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+	let header = req.headers().get("X-Forwarded-For")?.to_str().ok()?;
+	for address in header.rsplit(',') {
+		let ip: IpAddr = address.trim().parse().ok()?;
+		if !trusted_proxies.contains(&ip) {
+			return Some(ip);
+		}
+	}
+	None
+}
+
+// The `Host` the request was sent to. As with `client_ip`,
+// `X-Forwarded-Host` is only honoured from a trusted proxy.
+pub fn request_host(req: &HttpRequest) -> String {
+	let from_proxy = req
+		.peer_addr()
+		.map_or(false, |addr| trusted_proxies(req).contains(&addr.ip()));
+	if let Some(host) = from_proxy.then(|| forwarded_host(req)).flatten() {
+		return host;
+	}
+	req.headers()
+		.get(HOST)
+		.and_then(|h| h.to_str().ok())
+		// HTTP/2 requests carry it in the URI
+		.or_else(|| req.uri().host())
+		.unwrap_or_default()
+		.to_owned()
+}
+
+// Proxies append to the header the client sent, the last host was set
+// by ours.
+fn forwarded_host(req: &HttpRequest) -> Option<String> {
+	let header = req.headers().get("X-Forwarded-Host")?.to_str().ok()?;
+	let host = header.rsplit(',').next()?.trim();
+	(!host.is_empty()).then(|| host.to_owned())
+}
+
+pub fn user_agent(req: &HttpRequest) -> Option<String> {
+  req.headers()
+			.get(USER_AGENT)
+			.and_then(|h| h.to_str().ok())
+			.map(|h| h.to_owned())
+}
+
+// Doubles `first` after each failed attempt, up to `max`.
+pub fn backoff_delay(
+	attempts: i32,
+	first: chrono::Duration,
+	max: chrono::Duration
+) -> chrono::Duration {
+	let factor = 1 << (attempts - 1).clamp(0, 20);
+	std::cmp::min(first * factor, max)
+}
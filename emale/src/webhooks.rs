@@ -0,0 +1,491 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::utils::backoff_delay;
+
+// Subscriber lifecycle events are pushed to the endpoints configured by the
+// admins. An event is written to `webhook_events`, with one delivery per
+// active endpoint, in the same transaction as the change it reports: the
+// outbox is drained by the delivery worker, failed deliveries are retried
+// with an exponential backoff.
+//
+// Deliveries are JSON POSTs signed with the secret of the endpoint:
+//
+//     X-Emale-Signature: t=<unix timestamp>,v1=<hex HMAC-SHA256 of "<t>.<body>">
+//
+// There is no bounce handling in emale, so there is no bounce event.
+
+const SECRET_LENGTH: usize = 32;
+const MAX_ATTEMPTS: i32 = 12;
+const FIRST_RETRY_DELAY_SECONDS: i64 = 30;
+const MAX_RETRY_DELAY_SECONDS: i64 = 6 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventType {
+	SubscriberCreated,
+	SubscriberConfirmed,
+	// the subscriber erased their data, the only way to leave for now
+	SubscriberUnsubscribed,
+}
+
+impl EventType {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			EventType::SubscriberCreated => "subscriber.created",
+			EventType::SubscriberConfirmed => "subscriber.confirmed",
+			EventType::SubscriberUnsubscribed => "subscriber.unsubscribed",
+		}
+	}
+}
+
+fn generate_secret() -> String {
+	let mut rng = thread_rng();
+	let secret: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+		.map(char::from)
+		.take(SECRET_LENGTH)
+		.collect();
+	format!("whsec_{}", secret)
+}
+
+pub fn signature(secret: &Secret<String>, timestamp: i64, body: &str) -> String {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+		.expect("HMAC can take a key of any size");
+	mac.update(format!("{}.{}", timestamp, body).as_bytes());
+	hex::encode(mac.finalize().into_bytes())
+}
+
+// `None` once the delivery should be given up on.
+fn retry_delay(attempts: i32) -> Option<Duration> {
+	if attempts >= MAX_ATTEMPTS {
+		return None;
+	}
+	Some(backoff_delay(
+		attempts,
+		Duration::seconds(FIRST_RETRY_DELAY_SECONDS),
+		Duration::seconds(MAX_RETRY_DELAY_SECONDS)
+	))
+}
+
+// Records the event for the subscriber, who must still exist: their
+// details are copied into the payload, except for `unsubscribed` which
+// outlives the erased subscriber and only carries their id. Only the
+// endpoints of the publication of the subscriber get it, nothing is
+// recorded when none of them is active.
+#[tracing::instrument(name = "Record webhook event", skip(transaction))]
+pub async fn record_event(
+	transaction: &mut Transaction<'_, Postgres>,
+	event_type: EventType,
+	subscriber_id: Uuid
+) -> Result<(), sqlx::Error> {
+	let endpoints: Vec<Uuid> = sqlx::query!(
+		r#"
+		SELECT endpoint_id FROM webhook_endpoints
+		WHERE
+			is_active AND
+			publication_id = (SELECT publication_id FROM subscriber WHERE id = $1)
+		"#,
+		subscriber_id
+	)
+	.fetch_all(&mut *transaction)
+	.await?
+	.into_iter()
+	.map(|r| r.endpoint_id)
+	.collect();
+	if endpoints.is_empty() {
+		return Ok(());
+	}
+	let event_id = Uuid::new_v4();
+	let with_details = event_type != EventType::SubscriberUnsubscribed;
+	sqlx::query!(
+		r#"
+		INSERT INTO webhook_events (event_id, event_type, subscriber_id, payload, created_at)
+		SELECT
+			$1,
+			$2,
+			id,
+			jsonb_build_object(
+				'id', $1::uuid,
+				'type', $2::text,
+				'created_at', now(),
+				'data', CASE WHEN $4 THEN jsonb_build_object(
+					'subscriber_id', id,
+					'email', email,
+					'name', name,
+					'status', status,
+					'locale', locale
+				) ELSE jsonb_build_object(
+					'subscriber_id', id
+				) END
+			),
+			now()
+		FROM subscriber
+		WHERE id = $3
+		"#,
+		event_id,
+		event_type.as_str(),
+		subscriber_id,
+		with_details
+	)
+	.execute(&mut *transaction)
+	.await?;
+	for endpoint_id in endpoints {
+		sqlx::query!(
+			r#"
+			INSERT INTO webhook_deliveries (delivery_id, event_id, endpoint_id, next_attempt_at, created_at)
+			VALUES ($1, $2, $3, now(), now())
+			"#,
+			Uuid::new_v4(),
+			event_id,
+			endpoint_id
+		)
+		.execute(&mut *transaction)
+		.await?;
+	}
+	Ok(())
+}
+
+// Earlier events carry the details of the subscriber, they go when the
+// subscriber is erased. Call before recording the `unsubscribed` event.
+#[tracing::instrument(name = "Forget webhook events", skip(transaction))]
+pub async fn forget_subscriber_events(
+	transaction: &mut Transaction<'_, Postgres>,
+	subscriber_id: Uuid
+) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"DELETE FROM webhook_events WHERE subscriber_id = $1"#,
+		subscriber_id
+	)
+	.execute(&mut *transaction)
+	.await?;
+	Ok(())
+}
+
+pub struct WebhookEndpoint {
+	pub endpoint_id: Uuid,
+	pub url: String,
+	pub secret: String,
+	pub is_active: bool,
+	pub pending: i64,
+	pub failed: i64,
+}
+
+#[tracing::instrument(name = "Get webhook endpoints", skip(pool))]
+pub async fn get_endpoints(
+	pool: &PgPool,
+	publication_id: &str
+) -> Result<Vec<WebhookEndpoint>, anyhow::Error> {
+	let endpoints = sqlx::query_as!(
+		WebhookEndpoint,
+		r#"
+		SELECT
+			ep.endpoint_id,
+			ep.url,
+			ep.secret,
+			ep.is_active,
+			COUNT(d.delivery_id) FILTER (WHERE d.next_attempt_at IS NOT NULL) AS "pending!",
+			COUNT(d.delivery_id) FILTER (
+				WHERE d.next_attempt_at IS NULL AND d.delivered_at IS NULL
+			) AS "failed!"
+		FROM webhook_endpoints ep
+		LEFT JOIN webhook_deliveries d USING (endpoint_id)
+		WHERE ep.publication_id = $1
+		GROUP BY ep.endpoint_id
+		ORDER BY ep.created_at
+		"#,
+		publication_id
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the webhook endpoints")?;
+	Ok(endpoints)
+}
+
+// Returns the id of the endpoint, its secret is generated.
+#[tracing::instrument(name = "Create webhook endpoint", skip(pool))]
+pub async fn create_endpoint(
+	pool: &PgPool,
+	publication_id: &str,
+	url: &reqwest::Url
+) -> Result<Uuid, anyhow::Error> {
+	let endpoint_id = Uuid::new_v4();
+	sqlx::query!(
+		r#"
+		INSERT INTO webhook_endpoints (endpoint_id, url, secret, is_active, created_at, publication_id)
+		VALUES ($1, $2, $3, true, now(), $4)
+		"#,
+		endpoint_id,
+		url.as_str(),
+		generate_secret(),
+		publication_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to create the webhook endpoint")?;
+	Ok(endpoint_id)
+}
+
+// Inactive endpoints get no new events, their pending deliveries wait
+// until they are reactivated. Returns false if the publication has no
+// such endpoint.
+#[tracing::instrument(name = "Set webhook endpoint activity", skip(pool))]
+pub async fn set_endpoint_active(
+	pool: &PgPool,
+	publication_id: &str,
+	endpoint_id: Uuid,
+	is_active: bool
+) -> Result<bool, anyhow::Error> {
+	let updated = sqlx::query!(
+		r#"
+		UPDATE webhook_endpoints SET is_active = $2
+		WHERE endpoint_id = $1 AND publication_id = $3
+		"#,
+		endpoint_id,
+		is_active,
+		publication_id
+	)
+	.execute(pool)
+	.await
+	.context("Failed to update the webhook endpoint")?
+	.rows_affected();
+	Ok(updated == 1)
+}
+
+pub struct WebhookDelivery {
+	pub delivery_id: Uuid,
+	pub event_type: String,
+	pub attempts: i32,
+	pub next_attempt_at: Option<DateTime<Utc>>,
+	pub delivered_at: Option<DateTime<Utc>>,
+	pub last_status: Option<i16>,
+	pub last_error: Option<String>,
+	pub created_at: DateTime<Utc>,
+}
+
+impl WebhookDelivery {
+	pub fn state(&self) -> &'static str {
+		match (self.delivered_at, self.next_attempt_at) {
+			(Some(_), _) => "delivered",
+			(None, Some(_)) => "pending",
+			(None, None) => "failed",
+		}
+	}
+}
+
+// The delivery log of an endpoint, newest first. `None` if the
+// publication has no such endpoint.
+#[tracing::instrument(name = "Get webhook deliveries", skip(pool))]
+pub async fn get_deliveries(
+	pool: &PgPool,
+	publication_id: &str,
+	endpoint_id: Uuid,
+	limit: i64
+) -> Result<Option<(String, Vec<WebhookDelivery>)>, anyhow::Error> {
+	let url = match sqlx::query!(
+		r#"SELECT url FROM webhook_endpoints WHERE endpoint_id = $1 AND publication_id = $2"#,
+		endpoint_id,
+		publication_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to retrieve the webhook endpoint")?
+	{
+		Some(r) => r.url,
+		None => return Ok(None),
+	};
+	let deliveries = sqlx::query_as!(
+		WebhookDelivery,
+		r#"
+		SELECT
+			d.delivery_id,
+			e.event_type,
+			d.attempts,
+			d.next_attempt_at,
+			d.delivered_at,
+			d.last_status,
+			d.last_error,
+			d.created_at
+		FROM webhook_deliveries d
+		JOIN webhook_events e USING (event_id)
+		WHERE d.endpoint_id = $1
+		ORDER BY d.created_at DESC
+		LIMIT $2
+		"#,
+		endpoint_id,
+		limit
+	)
+	.fetch_all(pool)
+	.await
+	.context("Failed to retrieve the webhook deliveries")?;
+	Ok(Some((url, deliveries)))
+}
+
+// Queues the event of the delivery again for the same endpoint, as a new
+// delivery so that the log keeps the earlier attempts. Returns the id of
+// the endpoint, `None` if the publication has no such delivery.
+#[tracing::instrument(name = "Resend webhook delivery", skip(pool))]
+pub async fn resend_delivery(
+	pool: &PgPool,
+	publication_id: &str,
+	delivery_id: Uuid
+) -> Result<Option<Uuid>, anyhow::Error> {
+	let endpoint = sqlx::query!(
+		r#"
+		INSERT INTO webhook_deliveries (delivery_id, event_id, endpoint_id, next_attempt_at, created_at)
+		SELECT $2, d.event_id, d.endpoint_id, now(), now()
+		FROM webhook_deliveries d
+		JOIN webhook_endpoints ep USING (endpoint_id)
+		WHERE d.delivery_id = $1 AND ep.publication_id = $3
+		RETURNING endpoint_id
+		"#,
+		delivery_id,
+		Uuid::new_v4(),
+		publication_id
+	)
+	.fetch_optional(pool)
+	.await
+	.context("Failed to queue the webhook delivery again")?;
+	Ok(endpoint.map(|r| r.endpoint_id))
+}
+
+pub fn webhook_client() -> reqwest::Client {
+	reqwest::Client::builder()
+		.timeout(std::time::Duration::from_secs(10))
+		.build()
+		.expect("Failed to build the webhook HTTP client")
+}
+
+// Attempts the delivery that has waited the longest. A 2xx response
+// delivers the event, anything else schedules a retry.
+#[tracing::instrument(
+	skip_all,
+	fields(delivery_id=tracing::field::Empty, event_type=tracing::field::Empty),
+	err
+)]
+pub async fn try_deliver_webhook(
+	pool: &PgPool,
+	http_client: &reqwest::Client
+) -> Result<ExecutionOutcome, anyhow::Error> {
+	let mut transaction = pool.begin().await?;
+	let delivery = sqlx::query!(
+		r#"
+		SELECT
+			d.delivery_id,
+			d.attempts,
+			e.event_type,
+			e.payload::text AS "payload!",
+			ep.url,
+			ep.secret
+		FROM webhook_deliveries d
+		JOIN webhook_events e USING (event_id)
+		JOIN webhook_endpoints ep USING (endpoint_id)
+		WHERE d.next_attempt_at <= now() AND ep.is_active
+		ORDER BY d.next_attempt_at
+		FOR UPDATE OF d
+		SKIP LOCKED
+		LIMIT 1
+		"#
+	)
+	.fetch_optional(&mut transaction)
+	.await?;
+	let delivery = match delivery {
+		Some(delivery) => delivery,
+		None => return Ok(ExecutionOutcome::EmptyQueue),
+	};
+	tracing::Span::current()
+		.record("delivery_id", &tracing::field::display(delivery.delivery_id))
+		.record("event_type", &tracing::field::display(&delivery.event_type));
+
+	let timestamp = Utc::now().timestamp();
+	let secret = Secret::new(delivery.secret);
+	let outcome = http_client
+		.post(&delivery.url)
+		.header("Content-Type", "application/json")
+		.header("X-Emale-Event", &delivery.event_type)
+		.header("X-Emale-Delivery", delivery.delivery_id.to_string())
+		.header(
+			"X-Emale-Signature",
+			format!("t={},v1={}", timestamp, signature(&secret, timestamp, &delivery.payload))
+		)
+		.body(delivery.payload)
+		.send()
+		.await;
+	let (status, error) = match outcome {
+		Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+		Ok(response) => (
+			Some(response.status().as_u16()),
+			Some(format!("Unexpected status {}", response.status()))
+		),
+		Err(e) => (None, Some(e.to_string())),
+	};
+	let attempts = delivery.attempts + 1;
+	let next_attempt_at = match &error {
+		None => None,
+		Some(e) => {
+			tracing::warn!(error.message = %e, attempts, "Failed to deliver a webhook");
+			retry_delay(attempts).map(|delay| Utc::now() + delay)
+		}
+	};
+	sqlx::query!(
+		r#"
+		UPDATE webhook_deliveries
+		SET
+			attempts = $2,
+			next_attempt_at = $3,
+			delivered_at = CASE WHEN $4 THEN now() END,
+			last_status = $5,
+			last_error = $6
+		WHERE delivery_id = $1
+		"#,
+		delivery.delivery_id,
+		attempts,
+		next_attempt_at,
+		error.is_none(),
+		status.map(|s| s as i16),
+		error
+	)
+	.execute(&mut transaction)
+	.await?;
+	transaction.commit().await?;
+	Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{retry_delay, signature, MAX_ATTEMPTS};
+	use chrono::Duration;
+	use secrecy::Secret;
+
+	#[test]
+	fn signatures_cover_the_timestamp_and_the_body() {
+		let secret = Secret::new("whsec".to_string());
+		assert_eq!(
+			signature(&secret, 1670000000, r#"{"type":"subscriber.created"}"#),
+			"d925132b2657f00454a012b1c931a934663d10b5c4d52160d1ad3fa95473732a"
+		);
+		assert_ne!(
+			signature(&secret, 1670000001, r#"{"type":"subscriber.created"}"#),
+			signature(&secret, 1670000000, r#"{"type":"subscriber.created"}"#)
+		);
+	}
+
+	#[test]
+	fn retries_back_off_exponentially_up_to_a_cap() {
+		assert_eq!(retry_delay(1), Some(Duration::seconds(30)));
+		assert_eq!(retry_delay(2), Some(Duration::seconds(60)));
+		assert_eq!(retry_delay(3), Some(Duration::seconds(120)));
+		assert_eq!(retry_delay(MAX_ATTEMPTS - 1), Some(Duration::hours(6)));
+	}
+
+	#[test]
+	fn deliveries_are_given_up_after_the_last_attempt() {
+		assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+	}
+}
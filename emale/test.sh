@@ -1 +1,2 @@
-TEST_LOG=true cargo test | bunyan
\ No newline at end of file
+TEST_LOG=true cargo test | bunyan
+APP_SESSION_STORE=postgres TEST_LOG=true cargo test | bunyan
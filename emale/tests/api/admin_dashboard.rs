@@ -38,4 +38,27 @@ async fn logout() {
 	let response = app.get_admin_dashboard().await;
 	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
 
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn username_is_escaped_in_dashboard() {
+	let app = spawn_app().await;
+	let malicious_username = r#"<script>alert("pwned")</script>"#;
+	sqlx::query!(
+		"UPDATE users SET username = $1 WHERE user_id = $2",
+		malicious_username,
+		app.test_user.user_id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	app.post_login(&serde_json::json!({
+		"username": malicious_username,
+		"password": &app.test_user.password
+	}))
+	.await;
+
+	let html = app.get_admin_dashboard_html().await;
+	assert!(!html.contains(malicious_username));
+	assert!(html.contains("Welcome &lt;script&gt;alert(&quot;pwned&quot;)&lt;/script&gt;!"));
+}
@@ -0,0 +1,195 @@
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_issue(app: &TestApp, title: &str, is_private: bool) -> Uuid {
+	let issue_id = Uuid::new_v4();
+	sqlx::query!(
+		"INSERT INTO newsletter_issues
+			(newsletter_issue_id, title, text_content, html_content, published_at, is_private)
+		VALUES ($1, $2, 'text', '<p>Winter on Gethen</p>', now()::TEXT, $3)",
+		issue_id,
+		title,
+		is_private
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	issue_id
+}
+
+#[tokio::test]
+async fn the_archive_lists_public_issues_only() {
+	let app = spawn_app().await;
+	let public = create_issue(&app, "The Left Hand of Darkness", false).await;
+	create_issue(&app, "Staff only", true).await;
+
+	let response = app.get_archive("/archive").await;
+	assert_eq!(response.status().as_u16(), 200);
+
+	let html = response.text().await.unwrap();
+	assert!(html.contains(&format!(r#"<a href="/archive/{}">The Left Hand of Darkness</a>"#, public)));
+	assert!(!html.contains("Staff only"));
+}
+
+#[tokio::test]
+async fn permalinks_show_the_issue() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app, "The Left Hand of Darkness", false).await;
+
+	let html = app
+		.get_archive(&format!("/archive/{}", issue_id))
+		.await
+		.text()
+		.await
+		.unwrap();
+
+	assert!(html.contains("<h1>The Left Hand of Darkness</h1>"));
+	assert!(html.contains("<p>Winter on Gethen</p>"));
+}
+
+#[tokio::test]
+async fn permalinks_do_not_run_the_scripts_of_the_issue() {
+	let app = spawn_app().await;
+	let issue_id = Uuid::new_v4();
+	sqlx::query!(
+		r#"INSERT INTO newsletter_issues
+			(newsletter_issue_id, title, text_content, html_content, published_at, is_private)
+		VALUES ($1, 'Gethen', 'text', '<p onclick="steal()">Winter</p><script>steal()</script>', now()::TEXT, false)"#,
+		issue_id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	let html = app
+		.get_archive(&format!("/archive/{}", issue_id))
+		.await
+		.text()
+		.await
+		.unwrap();
+
+	assert!(html.contains("<p>Winter</p>"));
+	assert!(!html.contains("steal()"));
+}
+
+#[tokio::test]
+async fn private_and_unknown_issues_are_not_found() {
+	let app = spawn_app().await;
+	let private = create_issue(&app, "Staff only", true).await;
+
+	for issue_id in [private, Uuid::new_v4()] {
+		let response = app.get_archive(&format!("/archive/{}", issue_id)).await;
+		assert_eq!(response.status().as_u16(), 404);
+	}
+}
+
+#[tokio::test]
+async fn permalinks_show_the_translation_of_the_browser_language() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app, "The Left Hand of Darkness", false).await;
+	sqlx::query!(
+		"INSERT INTO newsletter_issue_variants
+			(newsletter_issue_id, locale, title, text_content, html_content)
+		VALUES ($1, 'fr', 'La Main gauche de la nuit', 'texte', '<p>L''hiver sur Gethen</p>')",
+		issue_id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	let html = app
+		.api_client
+		.get(&format!("{}/archive/{}", &app.address, issue_id))
+		.header("Accept-Language", "fr")
+		.send()
+		.await
+		.unwrap()
+		.text()
+		.await
+		.unwrap();
+
+	assert!(html.contains("<h1>La Main gauche de la nuit</h1>"));
+}
+
+#[tokio::test]
+async fn feeds_contain_public_issues_only() {
+	let app = spawn_app().await;
+	let public = create_issue(&app, "The Left Hand of Darkness", false).await;
+	create_issue(&app, "Staff only", true).await;
+
+	let rss = app.get_archive("/feed.rss").await;
+	assert_eq!(rss.status().as_u16(), 200);
+	assert!(rss
+		.headers()
+		.get("Content-Type")
+		.unwrap()
+		.to_str()
+		.unwrap()
+		.starts_with("application/rss+xml"));
+	let rss = rss.text().await.unwrap();
+	assert!(rss.contains("<title>The Left Hand of Darkness</title>"));
+	assert!(rss.contains(&format!("/archive/{}</link>", public)));
+	assert!(!rss.contains("Staff only"));
+
+	let atom = app.get_archive("/feed.atom").await;
+	assert!(atom
+		.headers()
+		.get("Content-Type")
+		.unwrap()
+		.to_str()
+		.unwrap()
+		.starts_with("application/atom+xml"));
+	let atom = atom.text().await.unwrap();
+	assert!(atom.contains(&format!("<id>urn:uuid:{}</id>", public)));
+	assert!(!atom.contains("Staff only"));
+}
+
+#[tokio::test]
+async fn admins_can_make_an_issue_private() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app, "The Left Hand of Darkness", false).await;
+	app.test_user.login(&app).await;
+
+	let response = app.post_issue_visibility(issue_id, true).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/issues");
+
+	let response = app.get_archive(&format!("/archive/{}", issue_id)).await;
+	assert_eq!(response.status().as_u16(), 404);
+
+	app.post_issue_visibility(issue_id, false).await;
+	let response = app.get_archive(&format!("/archive/{}", issue_id)).await;
+	assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn changing_the_visibility_requires_login() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app, "The Left Hand of Darkness", false).await;
+
+	let response = app.post_issue_visibility(issue_id, true).await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+	let response = app.get_archive(&format!("/archive/{}", issue_id)).await;
+	assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn issues_published_as_private_stay_out_of_the_archive() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	app.post_publish_newsletter(&serde_json::json!({
+		"title": "Staff only",
+		"text_content": "text content",
+		"html_content": "<p>HTML content</p>",
+		"private": "on",
+		"idempotency_key": Uuid::new_v4().to_string()
+	}))
+	.await;
+
+	let html = app.get_archive("/archive").await.text().await.unwrap();
+	assert!(!html.contains("Staff only"));
+}
@@ -0,0 +1,113 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn audit_actions(app: &TestApp) -> Vec<String> {
+	sqlx::query!(
+		"SELECT action FROM audit_log WHERE actor_user_id = $1 ORDER BY created_at",
+		app.test_user.user_id
+	)
+	.fetch_all(&app.db_pool)
+	.await
+	.unwrap()
+	.into_iter()
+	.map(|r| r.action)
+	.collect()
+}
+
+#[tokio::test]
+async fn must_logged_in_to_view_audit_log() {
+	let app = spawn_app().await;
+	let response = app.get_audit_log("").await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn login_and_logout_are_audited() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	app.post_logout().await;
+
+	assert_eq!(audit_actions(&app).await, vec!["login", "logout"]);
+}
+
+#[tokio::test]
+async fn password_change_is_audited() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let new_password = uuid::Uuid::new_v4().to_string();
+	app.post_change_password(&serde_json::json!({
+		"current_password": &app.test_user.password,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	}))
+	.await;
+
+	assert_eq!(audit_actions(&app).await, vec!["login", "password_change"]);
+}
+
+#[tokio::test]
+async fn newsletter_publish_is_audited_once() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+
+	let body = serde_json::json!({
+		"title": "Audited issue",
+		"text_content": "content",
+		"html_content": "<p>content</p>",
+		"idempotency_key": uuid::Uuid::new_v4().to_string()
+	});
+	app.post_publish_newsletter(&body).await;
+	// replaying the same request does not publish again
+	app.post_publish_newsletter(&body).await;
+
+	assert_eq!(audit_actions(&app).await, vec!["login", "newsletter_publish"]);
+	let html = app.get_audit_log("action=newsletter_publish").await.text().await.unwrap();
+	assert!(html.contains("Audited issue"));
+}
+
+#[tokio::test]
+async fn audit_log_can_be_filtered_by_action() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	app.post_logout().await;
+	app.test_user.login(&app).await;
+
+	let html = app.get_audit_log("action=logout").await.text().await.unwrap();
+	assert!(html.contains("<td>logout</td>"));
+	assert!(!html.contains("<td>login</td>"));
+
+	let html = app.get_audit_log(&format!("actor={}", app.test_user.username)).await.text().await.unwrap();
+	assert!(html.contains("<td>logout</td>"));
+	assert!(html.contains("<td>login</td>"));
+}
+
+#[tokio::test]
+async fn unknown_action_filter_is_rejected() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let response = app.get_audit_log("action=drop_table").await;
+	assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn audit_log_is_append_only() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	let result = sqlx::query!("DELETE FROM audit_log")
+		.execute(&app.db_pool)
+		.await;
+	assert!(result.is_err());
+	let result = sqlx::query!("UPDATE audit_log SET action = 'nothing'")
+		.execute(&app.db_pool)
+		.await;
+	assert!(result.is_err());
+}
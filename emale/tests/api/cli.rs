@@ -0,0 +1,117 @@
+use emale::authentication::{create_user, disable_user, reset_user_password};
+use emale::issue_delivery_worker::{queue_stats, requeue_issue};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn login_as(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+	app.post_login(&serde_json::json!({
+		"username": username,
+		"password": password
+	}))
+	.await
+}
+
+#[tokio::test]
+async fn created_users_can_log_in() {
+	let app = spawn_app().await;
+	let password = Uuid::new_v4().to_string();
+
+	create_user(
+		"operator",
+		Some("operator@example.com"),
+		Secret::new(password.clone()),
+		"default",
+		&app.db_pool
+	)
+	.await
+	.unwrap();
+
+	let response = login_as(&app, "operator", &password).await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn usernames_are_unique() {
+	let app = spawn_app().await;
+	let password = Secret::new(Uuid::new_v4().to_string());
+
+	let outcome = create_user(&app.test_user.username, None, password, "default", &app.db_pool).await;
+	assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn short_passwords_are_rejected() {
+	let app = spawn_app().await;
+
+	let outcome = create_user("operator", None, Secret::new("short".into()), "default", &app.db_pool).await;
+	assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn reset_password_logs_out_every_session() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let new_password = Uuid::new_v4().to_string();
+
+	reset_user_password(&app.test_user.username, Secret::new(new_password.clone()), &app.db_pool)
+		.await
+		.unwrap();
+
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+	let response = login_as(&app, &app.test_user.username, &new_password).await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn disabled_users_are_logged_out_and_cannot_log_in() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	disable_user(&app.test_user.username, &app.db_pool).await.unwrap();
+
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+	let response = login_as(&app, &app.test_user.username, &app.test_user.password).await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+	// disabling twice is reported
+	assert!(disable_user(&app.test_user.username, &app.db_pool).await.is_err());
+}
+
+#[tokio::test]
+async fn requeue_adds_the_missing_deliveries_only() {
+	let app = spawn_app().await;
+	let issue_id = Uuid::new_v4();
+	sqlx::query!(
+		"INSERT INTO newsletter_issues (newsletter_issue_id, title, text_content, html_content, published_at)
+		VALUES ($1, 'Issue title', 'text', '<p>html</p>', now()::TEXT)",
+		issue_id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	for email in ["first@example.com", "second@example.com"] {
+		sqlx::query!(
+			"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+			VALUES ($1, $2, 'confirmed', now(), 'confirmed')",
+			Uuid::new_v4(),
+			email
+		)
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+	}
+
+	let queued = requeue_issue(&app.db_pool, issue_id, Some("first@example.com")).await.unwrap();
+	assert_eq!(queued, 1);
+	let queued = requeue_issue(&app.db_pool, issue_id, None).await.unwrap();
+	assert_eq!(queued, 1);
+
+	let stats = queue_stats(&app.db_pool).await.unwrap();
+	assert_eq!(stats.len(), 1);
+	assert_eq!(stats[0].newsletter_issue_id, issue_id);
+	assert_eq!(stats[0].title, "Issue title");
+	assert_eq!(stats[0].pending_deliveries, 2);
+}
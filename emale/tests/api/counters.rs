@@ -0,0 +1,51 @@
+use emale::counters::{purge_expired_counters, CounterStore};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn postgres_hits_are_counted_within_their_window() {
+	let app = spawn_app().await;
+	let counters = CounterStore::Postgres(app.db_pool.clone());
+
+	for expected in 1..=3 {
+		assert_eq!(counters.hit("ip:203.0.113.1", 60).await.unwrap(), expected);
+	}
+	// other keys have their own window
+	assert_eq!(counters.hit("ip:203.0.113.2", 60).await.unwrap(), 1);
+	// expired hits are not counted
+	assert_eq!(counters.hit("ip:203.0.113.3", 0).await.unwrap(), 1);
+	assert_eq!(counters.hit("ip:203.0.113.3", 0).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn postgres_counters_expire() {
+	let app = spawn_app().await;
+	let counters = CounterStore::Postgres(app.db_pool.clone());
+
+	assert_eq!(counters.incr("lockouts", 60).await.unwrap(), 1);
+	assert_eq!(counters.incr("lockouts", 60).await.unwrap(), 2);
+	assert_eq!(counters.incr("expired", 0).await.unwrap(), 1);
+	assert_eq!(counters.incr("expired", 0).await.unwrap(), 1);
+
+	counters.set("locked", 1, 60).await.unwrap();
+	counters.set("unlocked", 1, 0).await.unwrap();
+	assert!(counters.exists(&["locked".into(), "missing".into()]).await.unwrap());
+	assert!(!counters.exists(&["unlocked".into(), "missing".into()]).await.unwrap());
+
+	counters.delete(&["locked".into()]).await.unwrap();
+	assert!(!counters.exists(&["locked".into()]).await.unwrap());
+}
+
+#[tokio::test]
+async fn expired_postgres_counters_get_purged() {
+	let app = spawn_app().await;
+	let counters = CounterStore::Postgres(app.db_pool.clone());
+	counters.hit("expired", 0).await.unwrap();
+	counters.set("expired", 1, 0).await.unwrap();
+	counters.hit("live", 60).await.unwrap();
+	counters.set("live", 1, 60).await.unwrap();
+
+	assert_eq!(purge_expired_counters(&app.db_pool).await.unwrap(), 2);
+	assert_eq!(counters.hit("live", 60).await.unwrap(), 2);
+	assert!(counters.exists(&["live".into()]).await.unwrap());
+}
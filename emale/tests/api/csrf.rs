@@ -0,0 +1,117 @@
+use uuid::Uuid;
+
+use crate::helpers::{extract_csrf_token, spawn_app};
+
+fn newsletter_body() -> serde_json::Value {
+	serde_json::json!({
+		"title": "Newsletter title",
+		"text_content": "Newsletter body as plain text",
+		"html_content": "<p>Newsletter body as HTML</p>",
+		"idempotency_key": Uuid::new_v4().to_string()
+	})
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_session_csrf_token() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	let token = app.csrf_token().await;
+	assert_eq!(token.len(), 32);
+
+	let newsletter_html = app.get_publish_newsletter().await.text().await.unwrap();
+	assert_eq!(extract_csrf_token(&newsletter_html), Some(token.clone()));
+	let password_html = app.get_change_password_html_response().await;
+	assert_eq!(extract_csrf_token(&password_html), Some(token));
+}
+
+#[tokio::test]
+async fn admin_post_without_csrf_token_is_rejected() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	let response = app.api_client
+		.post(&format!("{}/admin/newsletters", &app.address))
+		.form(&newsletter_body())
+		.send()
+		.await
+		.expect("Failed to execute request");
+	assert_eq!(response.status().as_u16(), 403);
+
+	let html = app.get_admin_dashboard_html().await;
+	assert!(html.contains("<p><i>Invalid or missing CSRF token, please try again</i></p>"));
+}
+
+#[tokio::test]
+async fn admin_post_with_wrong_csrf_token_is_rejected() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	app.csrf_token().await;
+
+	let response = app.api_client
+		.post(&format!("{}/admin/logout", &app.address))
+		.form(&serde_json::json!({ "csrf_token": "not-the-session-token" }))
+		.send()
+		.await
+		.expect("Failed to execute request");
+	assert_eq!(response.status().as_u16(), 403);
+
+	// still logged in
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn csrf_token_can_be_sent_as_form_field() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let token = app.csrf_token().await;
+
+	let mut body = newsletter_body();
+	body["csrf_token"] = serde_json::Value::String(token);
+	let response = app.api_client
+		.post(&format!("{}/admin/newsletters", &app.address))
+		.form(&body)
+		.send()
+		.await
+		.expect("Failed to execute request");
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn csrf_token_of_another_session_is_rejected() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let old_token = app.csrf_token().await;
+	app.post_logout().await;
+	app.test_user.login(&app).await;
+
+	let response = app.api_client
+		.post(&format!("{}/admin/logout", &app.address))
+		.header("X-CSRF-Token", old_token)
+		.send()
+		.await
+		.expect("Failed to execute request");
+	assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn session_cookie_is_same_site() {
+	let app = spawn_app().await;
+
+	let response = app.post_login(&serde_json::json!({
+		"username": &app.test_user.username,
+		"password": &app.test_user.password
+	}))
+	.await;
+
+	let session_cookie = response
+		.headers()
+		.get_all("Set-Cookie")
+		.iter()
+		.filter_map(|h| h.to_str().ok())
+		.find(|c| c.starts_with("id="))
+		.expect("No session cookie was set");
+	assert!(session_cookie.contains("SameSite=Lax"));
+}
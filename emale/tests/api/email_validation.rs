@@ -0,0 +1,89 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn expect_emails(app: &TestApp, n: u64) {
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(n)
+		.mount(&app.email_server)
+		.await;
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected_with_a_400() {
+	let app = spawn_app().await;
+	expect_emails(&app, 0).await;
+
+	let response = app
+		.post_subscriptions("name=le%20guin&email=ursula%40mailinator.com".into())
+		.await;
+
+	assert_eq!(response.status().as_u16(), 400);
+	assert_eq!(
+		response.text().await.unwrap(),
+		"Disposable email addresses are not accepted."
+	);
+}
+
+async fn stored_subscribers(app: &TestApp) -> i64 {
+	sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count
+}
+
+#[tokio::test]
+async fn likely_typos_come_back_with_a_suggestion() {
+	let app = spawn_app().await;
+	expect_emails(&app, 0).await;
+
+	let response = app
+		.post_subscriptions("name=le%20guin&email=ursula%40gmial.com".into())
+		.await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	let html = response.text().await.unwrap();
+	assert!(html.contains("Did you mean ursula@gmail.com?"));
+	assert!(html.contains(r#"value="ursula@gmial.com""#));
+	assert!(html.contains(r#"name="confirm_email" value="true""#));
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn likely_typos_are_accepted_once_confirmed() {
+	let app = spawn_app().await;
+	expect_emails(&app, 1).await;
+
+	let response = app
+		.post_subscriptions("name=le%20guin&email=ursula%40email.com&confirm_email=true".into())
+		.await;
+	app.dispatch_all_pending_emails().await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	let subscriber = sqlx::query!("SELECT email FROM subscriber")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(subscriber.email, "ursula@email.com");
+}
+
+#[tokio::test]
+async fn disabled_checks_let_the_address_through() {
+	let app = spawn_app_with(|c| {
+		c.email_validation.reject_disposable = false;
+		c.email_validation.suggest_typos = false;
+	})
+	.await;
+	expect_emails(&app, 1).await;
+
+	let response = app
+		.post_subscriptions("name=le%20guin&email=ursula%40mailinator.com".into())
+		.await;
+	app.dispatch_all_pending_emails().await;
+
+	assert_eq!(response.status().as_u16(), 200);
+}
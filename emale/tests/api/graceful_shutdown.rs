@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use emale::issue_delivery_worker::worker_loop;
+use emale::shutdown::{in_flight_drained, with_deadline, ShutdownState};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn enqueue_one_delivery(app: &TestApp) {
+	sqlx::query!(
+		"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+		VALUES ($1, 'confirmed@example.com', 'confirmed', now(), 'confirmed')",
+		Uuid::new_v4()
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	app.test_user.login(app).await;
+	let response = app.post_publish_newsletter(&serde_json::json!({
+		"title": "Newsletter title",
+		"text_content": "Newsletter body as plain text",
+		"html_content": "<p>Newsletter body as HTML</p>",
+		"idempotency_key": Uuid::new_v4().to_string()
+	}))
+	.await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(queued_deliveries(app).await, 1);
+}
+
+async fn queued_deliveries(app: &TestApp) -> i64 {
+	sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count
+}
+
+fn spawn_worker(
+	app: &TestApp,
+	shutdown: &ShutdownState,
+	deadline: Duration
+) -> JoinHandle<Option<Result<(), anyhow::Error>>> {
+	let worker = worker_loop(app.db_pool.clone(), app.email_client.clone(), shutdown.clone());
+	let shutdown = shutdown.clone();
+	tokio::spawn(async move { with_deadline(worker, &shutdown, deadline).await })
+}
+
+// Returns once the worker is in the middle of sending the email.
+async fn wait_for_email_request(app: &TestApp) {
+	for _ in 0..100 {
+		if !app.email_server.received_requests().await.unwrap().is_empty() {
+			return;
+		}
+		tokio::time::sleep(Duration::from_millis(50)).await;
+	}
+	panic!("The worker never sent the email");
+}
+
+#[tokio::test]
+async fn delivery_in_flight_at_shutdown_completes_exactly_once() {
+	let app = spawn_app().await;
+	enqueue_one_delivery(&app).await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+		.expect(1)
+		.mount(&app.email_server)
+		.await;
+
+	let shutdown = ShutdownState::default();
+	let worker = spawn_worker(&app, &shutdown, Duration::from_secs(10));
+	wait_for_email_request(&app).await;
+	shutdown.begin();
+
+	let outcome = worker.await.unwrap();
+	assert!(matches!(outcome, Some(Ok(()))));
+	assert_eq!(queued_deliveries(&app).await, 0);
+	// nothing is left for the next worker, the mock asserts on drop
+	// that the email was sent once
+	app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn delivery_past_the_shutdown_deadline_is_sent_exactly_once() {
+	let app = spawn_app().await;
+	enqueue_one_delivery(&app).await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+		.expect(1)
+		.mount(&app.email_server)
+		.await;
+
+	let shutdown = ShutdownState::default();
+	let worker = spawn_worker(&app, &shutdown, Duration::from_millis(100));
+	wait_for_email_request(&app).await;
+	shutdown.begin();
+
+	let outcome = worker.await.unwrap();
+	assert!(outcome.is_none());
+	// the worker is gone but the email was handed to the provider,
+	// the delivery is still recorded
+	in_flight_drained(&shutdown).await;
+	assert_eq!(queued_deliveries(&app).await, 0);
+	// nothing is left for the next worker, the mock asserts on drop
+	// that the email was sent once
+	app.dispatch_all_pending_emails().await;
+}
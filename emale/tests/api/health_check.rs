@@ -1,5 +1,9 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
+use emale::configuration::SessionStoreKind;
+use emale::issue_delivery_worker::record_worker_heartbeat;
 use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
 
 #[tokio::test]
 async fn health_check_works() {
@@ -34,4 +38,105 @@ async fn pow_works() {
 
     assert!(res.status().is_success());
     assert_eq!(4, res.json::<ResponseVal>().await.expect("Failed").num);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn liveness_does_not_depend_on_anything() {
+    let app = spawn_app().await;
+    app.shutdown.begin();
+
+    let res = app.api_client
+        .get(&format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Request Failed");
+
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_reports_every_dependency() {
+    let app = spawn_app().await;
+    record_worker_heartbeat(&app.db_pool, Uuid::new_v4()).await.unwrap();
+
+    let res = app.get_health_ready().await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["shutting_down"], false);
+    for check in ["database", "redis", "migrations", "worker"] {
+        assert_eq!(body["checks"][check]["status"], "up", "{} is not up", check);
+        assert!(body["checks"][check]["duration_ms"].is_u64());
+    }
+}
+
+#[tokio::test]
+async fn redis_is_not_needed_when_it_is_not_configured() {
+    let app = spawn_app_with(|c| {
+        c.redis_uri = None;
+        c.session_store = SessionStoreKind::Postgres;
+    })
+    .await;
+    record_worker_heartbeat(&app.db_pool, Uuid::new_v4()).await.unwrap();
+
+    let res = app.get_health_ready().await;
+    assert_eq!(res.status().as_u16(), 200);
+    let body: Value = res.json().await.unwrap();
+    assert!(body["checks"]["redis"].is_null());
+
+    // sessions, the login throttle and the subscribe counters are in postgres
+    app.test_user.login(&app).await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_fails_without_a_worker_heartbeat() {
+    let app = spawn_app().await;
+
+    let res = app.get_health_ready().await;
+    assert_eq!(res.status().as_u16(), 503);
+
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["worker"]["status"], "down");
+    assert_eq!(body["checks"]["worker"]["error"], "No worker has reported a heartbeat");
+}
+
+#[tokio::test]
+async fn readiness_fails_with_a_stale_worker_heartbeat() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "INSERT INTO worker_heartbeats (worker_id, last_seen_at) VALUES ($1, now() - interval '1 hour')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let res = app.get_health_ready().await;
+    assert_eq!(res.status().as_u16(), 503);
+
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["checks"]["worker"]["status"], "down");
+}
+
+#[tokio::test]
+async fn readiness_fails_while_shutting_down() {
+    let app = spawn_app().await;
+    record_worker_heartbeat(&app.db_pool, Uuid::new_v4()).await.unwrap();
+    app.shutdown.begin();
+
+    let res = app.get_health_ready().await;
+    assert_eq!(res.status().as_u16(), 503);
+
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["shutting_down"], true);
+    assert_eq!(body["checks"]["database"]["status"], "up");
+}
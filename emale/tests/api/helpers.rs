@@ -1,25 +1,43 @@
 use argon2::{password_hash::SaltString, Argon2, PasswordHasher, Algorithm, Version, Params};
 use emale::{
-    configuration::{get_config, DatabaseSettings},
+    automation::{schedule_due_steps, try_execute_step_task},
+    email_outbox::try_send_outbox_email,
+    configuration::{get_config, DatabaseSettings, SessionStoreKind, Settings},
+    preferences::{preferences_path, preferences_signature},
+    shutdown::ShutdownState,
     startup::Application,
     startup::get_connection_pool,
-    telemetry::{get_tracing_subscriber, init_tracing_subscriber}, email_client::EmailClient, issue_delivery_worker::{ExecutionOutcome, try_execute_task},
+    telemetry::{get_tracing_subscriber, init_tracing_subscriber},
+    webhooks::{try_deliver_webhook, webhook_client}, email_client::EmailClient, issue_delivery_worker::{ExecutionOutcome, try_execute_task},
 };
 use linkify::{LinkFinder, LinkKind};
 use once_cell::sync::Lazy;
+use opentelemetry::global;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::TracerProvider;
+use opentelemetry::trace::TracerProvider as _;
+use secrecy::Secret;
 use serde_json::{Value, from_slice};
 use sqlx::{migrate, Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 
+pub const METRICS_TOKEN: &str = "metrics-token";
+
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter = "info".to_string();
     let subscriber_name = "test".to_string();
+    // Spans are recorded, so that trace context is propagated,
+    // but never exported
+    let provider = TracerProvider::builder().build();
+    let tracer = provider.tracer("emale-tests");
+    global::set_tracer_provider(provider);
+    global::set_text_map_propagator(TraceContextPropagator::new());
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_tracing_subscriber(subscriber_name, default_filter, std::io::stdout);
+        let subscriber = get_tracing_subscriber(subscriber_name, default_filter, std::io::stdout, Some(tracer));
         init_tracing_subscriber(subscriber)
     } else {
-        let subscriber = get_tracing_subscriber(subscriber_name, default_filter, std::io::sink);
+        let subscriber = get_tracing_subscriber(subscriber_name, default_filter, std::io::sink, Some(tracer));
         init_tracing_subscriber(subscriber)
     }
 });
@@ -32,7 +50,8 @@ pub struct ConfirmationLink {
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
-    pub password: String
+    pub password: String,
+    pub email: String
 }
 
 impl TestUser {
@@ -40,12 +59,13 @@ impl TestUser {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
-            password: Uuid::new_v4().to_string()
+            password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4())
         }
     }
 
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -58,16 +78,24 @@ impl TestUser {
         .to_string();
         sqlx::query!(
             "
-            INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users (user_id, username, password_hash, email)
+            VALUES ($1, $2, $3, $4)
             ",
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.email
         )
         .execute(pool)
         .await
         .expect("Failed to store user for test");
+        sqlx::query!(
+            "INSERT INTO publication_members (publication_id, user_id) VALUES ('default', $1)",
+            self.user_id
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to add the test user to the default publication");
     }
 
 	pub async fn login(&self, app: &TestApp) {
@@ -86,15 +114,27 @@ pub struct TestApp {
     pub email_client: EmailClient,
     pub api_client: reqwest::Client,
     pub port: u16,
-    pub test_user: TestUser
+    pub test_user: TestUser,
+    pub shutdown: ShutdownState,
+    pub hmac_secret: Secret<String>
 }
 
 impl TestApp {
 
+	// Transactional emails that are due, then newsletter deliveries.
 	pub async fn dispatch_all_pending_emails(&self) {
 		loop {
 			if let ExecutionOutcome::EmptyQueue =
-				try_execute_task(&self.db_pool, &self.email_client)
+				try_send_outbox_email(&self.db_pool, &self.email_client)
+					.await
+					.unwrap()
+				{
+					break;
+				}
+		}
+		loop {
+			if let ExecutionOutcome::EmptyQueue =
+				try_execute_task(&self.db_pool, &self.email_client, &self.shutdown)
 					.await
 					.unwrap()
 				{
@@ -155,7 +195,15 @@ impl TestApp {
         }
     }
 
+    // Submits the form the way a browser would, with the token
+    // embedded in the home page.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let form_token = self.subscribe_form_token().await;
+        self.post_subscriptions_raw(format!("{}&form_token={}", body, form_token))
+            .await
+    }
+
+    pub async fn post_subscriptions_raw(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(&format!("{}/subscribe", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
@@ -165,6 +213,19 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn subscribe_form_token(&self) -> String {
+        let html = self
+            .api_client
+            .get(&self.address)
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap();
+        extract_form_token(&html).expect("The home page has no form token")
+    }
+
 
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
 		self.api_client
@@ -178,8 +239,10 @@ impl TestApp {
 		where
 			Body: serde::Serialize
 		{
+			let csrf_token = self.csrf_token().await;
 			self.api_client
 				.post(&format!("{}/admin/newsletters", &self.address))
+				.header("X-CSRF-Token", csrf_token)
 				.form(body)
 				.send()
 				.await
@@ -206,8 +269,10 @@ impl TestApp {
         where 
             Body: serde::Serialize
         {
+        let csrf_token = self.csrf_token().await;
         self.api_client
             .post(&format!("{}/admin/password", &self.address))
+            .header("X-CSRF-Token", csrf_token)
             .form(body)
             .send()
             .await
@@ -226,9 +291,389 @@ impl TestApp {
             .unwrap()
     }
 
+	// Token of the current session, as embedded in the admin forms.
+	// Empty when the client is not logged in.
+	pub async fn csrf_token(&self) -> String {
+		let html = self.get_admin_dashboard_html().await;
+		extract_csrf_token(&html).unwrap_or_default()
+	}
+
+	pub async fn get_two_factor_html(&self) -> String {
+		self.api_client
+			.get(&format!("{}/admin/two-factor", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+			.text()
+			.await
+			.unwrap()
+	}
+
+	pub async fn post_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+		where
+			Body: serde::Serialize
+		{
+			let csrf_token = self.csrf_token().await;
+			self.api_client
+				.post(&format!("{}/admin/two-factor", &self.address))
+				.header("X-CSRF-Token", csrf_token)
+				.form(body)
+				.send()
+				.await
+				.expect("Failed to execute request")
+		}
+
+	pub async fn post_login_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+		where
+			Body: serde::Serialize
+		{
+			self.api_client
+				.post(&format!("{}/login/two-factor", &self.address))
+				.form(body)
+				.send()
+				.await
+				.expect("Failed to execute request")
+		}
+
+	pub async fn get_login_two_factor_html(&self) -> String {
+		self.api_client
+			.get(&format!("{}/login/two-factor", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+			.text()
+			.await
+			.unwrap()
+	}
+
+	pub async fn post_password_reset<Body>(&self, body: &Body) -> reqwest::Response
+		where
+			Body: serde::Serialize
+		{
+			self.api_client
+				.post(&format!("{}/password-reset", &self.address))
+				.form(body)
+				.send()
+				.await
+				.expect("Failed to execute request")
+		}
+
+	pub async fn post_password_reset_confirm<Body>(&self, body: &Body) -> reqwest::Response
+		where
+			Body: serde::Serialize
+		{
+			self.api_client
+				.post(&format!("{}/password-reset/confirm", &self.address))
+				.form(body)
+				.send()
+				.await
+				.expect("Failed to execute request")
+		}
+
+	pub async fn get_sessions(&self) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}/admin/sessions", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_revoke_session(&self, session_id: Uuid) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/sessions/{}/revoke", &self.address, session_id))
+			.header("X-CSRF-Token", csrf_token)
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_revoke_other_sessions(&self) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/sessions/revoke-others", &self.address))
+			.header("X-CSRF-Token", csrf_token)
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}/admin/audit?{}", &self.address, query))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_metrics(&self, bearer_token: Option<&str>) -> reqwest::Response {
+		let mut request = self.api_client.get(&format!("{}/metrics", &self.address));
+		if let Some(token) = bearer_token {
+			request = request.bearer_auth(token);
+		}
+		request
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_metrics_text(&self) -> String {
+		self.get_metrics(Some(METRICS_TOKEN))
+			.await
+			.text()
+			.await
+			.unwrap()
+	}
+
+	pub async fn post_subscriber_data_request<Body>(&self, body: &Body) -> reqwest::Response
+	where
+		Body: serde::Serialize
+	{
+		self.api_client
+			.post(&format!("{}/subscriber-data", &self.address))
+			.form(body)
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_subscriber_data_export(&self, token: &str) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}/subscriber-data/export", &self.address))
+			.query(&[("token", token)])
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_subscriber_data_erase(&self, token: &str) -> reqwest::Response {
+		self.api_client
+			.post(&format!("{}/subscriber-data/erase", &self.address))
+			.form(&[("token", token)])
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_preferences(&self, subscriber_id: Uuid) -> reqwest::Response {
+		self.api_client
+			.get(&format!(
+				"{}{}",
+				&self.address,
+				preferences_path(&self.hmac_secret, subscriber_id)
+			))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_preferences_html(&self, subscriber_id: Uuid) -> String {
+		self.get_preferences(subscriber_id).await.text().await.unwrap()
+	}
+
+	// `list_ids` are the lists the subscriber keeps receiving.
+	pub async fn post_preferences(
+		&self,
+		subscriber_id: Uuid,
+		name: &str,
+		email: &str,
+		list_ids: &[&str]
+	) -> reqwest::Response {
+		let signature = preferences_signature(&self.hmac_secret, subscriber_id);
+		let mut form = vec![
+			("subscriber_id", subscriber_id.to_string()),
+			("signature", signature),
+			("name", name.to_owned()),
+			("email", email.to_owned()),
+		];
+		form.extend(list_ids.iter().map(|list_id| ("list_id", list_id.to_string())));
+		self.api_client
+			.post(&format!("{}/preferences", &self.address))
+			.form(&form)
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_archive(&self, path: &str) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}{}", &self.address, path))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_issue_visibility(&self, issue_id: Uuid, private: bool) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/issues/{}/visibility", &self.address, issue_id))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({ "private": private }))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_issues_html(&self) -> String {
+		self.api_client
+			.get(&format!("{}/admin/issues", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+			.text()
+			.await
+			.unwrap()
+	}
+
+	pub async fn post_issue_delivery(&self, issue_id: Uuid, action: &str) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/issues/{}/delivery", &self.address, issue_id))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({ "action": action }))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_sequences(&self) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}/admin/sequences", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_sequence(&self, name: &str) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/sequences", &self.address))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({ "name": name }))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_sequence_step(&self, sequence_id: Uuid, delay_days: i32, subject: &str) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/sequences/{}/steps", &self.address, sequence_id))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({
+				"delay_days": delay_days,
+				"subject": subject,
+				"text_content": "text content",
+				"html_content": "<p>HTML content</p>"
+			}))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_sequence_activity(&self, sequence_id: Uuid, active: bool) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/sequences/{}/active", &self.address, sequence_id))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({ "active": active }))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	// What the worker does on each scheduling run.
+	pub async fn run_automation(&self) {
+		schedule_due_steps(&self.db_pool).await.unwrap();
+		loop {
+			if let ExecutionOutcome::EmptyQueue =
+				try_execute_step_task(&self.db_pool, &self.email_client)
+					.await
+					.unwrap()
+				{
+					break;
+				}
+		}
+	}
+
+	pub async fn get_webhooks(&self) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}/admin/webhooks", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn get_webhook_deliveries_html(&self, endpoint_id: Uuid) -> String {
+		self.api_client
+			.get(&format!("{}/admin/webhooks/{}", &self.address, endpoint_id))
+			.send()
+			.await
+			.expect("Failed to execute request")
+			.text()
+			.await
+			.unwrap()
+	}
+
+	pub async fn post_webhook_endpoint(&self, url: &str) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/webhooks", &self.address))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({ "url": url }))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_webhook_activity(&self, endpoint_id: Uuid, active: bool) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/webhooks/{}/active", &self.address, endpoint_id))
+			.header("X-CSRF-Token", csrf_token)
+			.form(&serde_json::json!({ "active": active }))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	pub async fn post_resend_webhook(&self, delivery_id: Uuid) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
+		self.api_client
+			.post(&format!("{}/admin/webhooks/deliveries/{}/resend", &self.address, delivery_id))
+			.header("X-CSRF-Token", csrf_token)
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
+	// Attempts every delivery that is due, the way the worker does.
+	pub async fn dispatch_all_webhooks(&self) {
+		let http_client = webhook_client();
+		loop {
+			if let ExecutionOutcome::EmptyQueue =
+				try_deliver_webhook(&self.db_pool, &http_client)
+					.await
+					.unwrap()
+				{
+					break;
+				}
+		}
+	}
+
+	pub async fn get_health_ready(&self) -> reqwest::Response {
+		self.api_client
+			.get(&format!("{}/health/ready", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute request")
+	}
+
 	pub async fn post_logout(&self) -> reqwest::Response {
+		let csrf_token = self.csrf_token().await;
 		self.api_client
 			.post(&format!("{}/admin/logout", &self.address))
+			.header("X-CSRF-Token", csrf_token)
 			.send()
 			.await
 			.expect("Failed to execute request")
@@ -238,7 +683,31 @@ impl TestApp {
 
 }
 
+pub fn extract_csrf_token(html: &str) -> Option<String> {
+    let marker = r#"name="csrf_token" value=""#;
+    let start = html.find(marker)? + marker.len();
+    let end = html[start..].find('"')?;
+    Some(html[start..start + end].to_owned())
+}
+
+pub fn extract_form_token(html: &str) -> Option<String> {
+    let marker = r#"name="form_token" value=""#;
+    let start = html.find(marker)? + marker.len();
+    let end = html[start..].find('"')?;
+    Some(html[start..start + end].to_owned())
+}
+
+// `APP_SESSION_STORE=postgres` runs the whole suite against the Postgres session store.
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+pub async fn spawn_app_with_session_store(session_store: SessionStoreKind) -> TestApp {
+    spawn_app_with(|c| c.session_store = session_store).await
+}
+
+// `configure` runs last, on top of the randomised test configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -250,6 +719,17 @@ pub async fn spawn_app() -> TestApp {
         // Use random OS port
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Tests share one Redis instance and the same client address
+        c.login_throttle.key_prefix = Uuid::new_v4().to_string();
+        c.password_reset_throttle.key_prefix = Uuid::new_v4().to_string();
+        c.subscribe_protection.key_prefix = Uuid::new_v4().to_string();
+        // tests submit the subscribe form right after loading it
+        c.subscribe_protection.min_fill_seconds = 0;
+        // DNS checks are covered by unit tests with a fake resolver
+        c.email_validation.check_dns = false;
+        c.metrics.bind_address = None;
+        c.metrics.bearer_token = Some(Secret::new(METRICS_TOKEN.to_string()));
+        configure(&mut c);
         c
     };
 
@@ -267,6 +747,7 @@ pub async fn spawn_app() -> TestApp {
 
     
     let app_port = app.port();
+    let shutdown = app.shutdown_state();
     let _ = tokio::spawn(app.run_until_stopped());
     let test_app = TestApp {
         address: format!("http://localhost:{}", app_port),
@@ -275,7 +756,9 @@ pub async fn spawn_app() -> TestApp {
         email_client: config.email_client.client(),
         api_client,
         port: app_port,
-        test_user: TestUser::generate()
+        test_user: TestUser::generate(),
+        shutdown,
+        hmac_secret: config.application.hmac_secret.clone()
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -0,0 +1,168 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe_body(app: &TestApp, email: &str, idempotency_key: &str) -> String {
+	format!(
+		"name=le%20guin&email={}&form_token={}&idempotency_key={}",
+		email,
+		app.subscribe_form_token().await,
+		idempotency_key
+	)
+}
+
+#[tokio::test]
+async fn retried_subscriptions_are_handled_once() {
+	let app = spawn_app().await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&app.email_server)
+		.await;
+	let body = subscribe_body(&app, "ursula_le_guin%40gmail.com", &Uuid::new_v4().to_string()).await;
+
+	let response1 = app.post_subscriptions_raw(body.clone()).await;
+	let response2 = app.post_subscriptions_raw(body).await;
+
+	assert_eq!(response1.status().as_u16(), 200);
+	assert_eq!(response2.status().as_u16(), 200);
+	let subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(subscribers, 1);
+	app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn reusing_a_key_for_another_request_is_rejected() {
+	let app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+	let body = subscribe_body(&app, "ursula_le_guin%40gmail.com", &idempotency_key).await;
+	app.post_subscriptions_raw(body).await.error_for_status().unwrap();
+
+	let body = subscribe_body(&app, "le_guin%40gmail.com", &idempotency_key).await;
+	let response = app.post_subscriptions_raw(body).await;
+
+	assert_eq!(response.status().as_u16(), 422);
+	let subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(subscribers, 1);
+}
+
+#[tokio::test]
+async fn the_key_can_be_sent_as_a_header() {
+	let app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+	let body = format!(
+		"name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+		app.subscribe_form_token().await
+	);
+	let post = || {
+		app.api_client
+			.post(&format!("{}/subscribe", &app.address))
+			.header("Content-Type", "application/x-www-form-urlencoded")
+			.header("Idempotency-Key", &idempotency_key)
+			.body(body.clone())
+			.send()
+	};
+
+	post().await.unwrap().error_for_status().unwrap();
+	post().await.unwrap().error_for_status().unwrap();
+
+	let keys = sqlx::query!(
+		r#"SELECT COUNT(*) AS "count!" FROM idempotency WHERE idempotency_key = $1"#,
+		idempotency_key
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count;
+	assert_eq!(keys, 1);
+}
+
+#[tokio::test]
+async fn retried_password_changes_are_handled_once() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let new_password = Uuid::new_v4().to_string();
+	let body = serde_json::json!({
+		"current_password": &app.test_user.password,
+		"new_password": &new_password,
+		"new_password_check": &new_password,
+		"idempotency_key": Uuid::new_v4().to_string()
+	});
+
+	let response1 = app.post_change_password(&body).await;
+	let response2 = app.post_change_password(&body).await;
+
+	assert_eq!(response1.status().as_u16(), 303);
+	assert_eq!(
+		response1.headers().get("Location"),
+		response2.headers().get("Location")
+	);
+	let changes = sqlx::query!(
+		r#"SELECT COUNT(*) AS "count!" FROM audit_log WHERE action = 'password_change'"#
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count;
+	assert_eq!(changes, 1);
+}
+
+#[tokio::test]
+async fn keys_of_anonymous_clients_are_kept_apart() {
+	let app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+	let body = subscribe_body(&app, "ursula_le_guin%40gmail.com", &idempotency_key).await;
+	app.post_subscriptions_raw(body).await.error_for_status().unwrap();
+
+	let body = subscribe_body(&app, "le_guin%40gmail.com", &idempotency_key).await;
+	let response = app
+		.api_client
+		.post(&format!("{}/subscribe", &app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("User-Agent", "another browser")
+		.body(body)
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(response.status().as_u16(), 200);
+	let subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(subscribers, 2);
+}
+
+#[tokio::test]
+async fn publishing_without_a_key_is_rejected() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	let response = app
+		.post_publish_newsletter(&serde_json::json!({
+			"title": "Title",
+			"text_content": "content",
+			"html_content": "<p>content</p>"
+		}))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 400);
+	let issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(issues, 0);
+}
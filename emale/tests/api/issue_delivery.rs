@@ -0,0 +1,160 @@
+use emale::issue_delivery_worker::{requeue_issue, try_execute_task};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_confirmed_subscribers(app: &TestApp, count: usize) {
+	for _ in 0..count {
+		sqlx::query!(
+			"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+			VALUES ($1, $2, 'le guin', now(), 'confirmed')",
+			Uuid::new_v4(),
+			format!("{}@example.com", Uuid::new_v4())
+		)
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+	}
+}
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+	let response = app
+		.post_publish_newsletter(&serde_json::json!({
+			"title": "The Dispossessed",
+			"text_content": "content",
+			"html_content": "<p>content</p>",
+			"idempotency_key": Uuid::new_v4().to_string()
+		}))
+		.await;
+	assert_eq!(response.status().as_u16(), 303);
+	sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.newsletter_issue_id
+}
+
+async fn sent_emails(app: &TestApp) -> usize {
+	app.email_server.received_requests().await.unwrap().len()
+}
+
+async fn pending_deliveries(app: &TestApp) -> i64 {
+	sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count
+}
+
+async fn setup(app: &TestApp) -> Uuid {
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+	create_confirmed_subscribers(app, 3).await;
+	app.test_user.login(app).await;
+	publish_issue(app).await
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_a_delivery() {
+	let app = spawn_app().await;
+
+	let response = app.post_issue_delivery(Uuid::new_v4(), "cancel").await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn paused_issues_are_not_delivered_until_resumed() {
+	let app = spawn_app().await;
+	let issue_id = setup(&app).await;
+
+	let response = app.post_issue_delivery(issue_id, "pause").await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/admin/issues");
+	app.dispatch_all_pending_emails().await;
+	assert_eq!(sent_emails(&app).await, 0);
+	assert_eq!(pending_deliveries(&app).await, 3);
+	assert!(app.get_issues_html().await.contains("The delivery is paused"));
+
+	app.post_issue_delivery(issue_id, "resume").await;
+	app.dispatch_all_pending_emails().await;
+	assert_eq!(sent_emails(&app).await, 3);
+	assert_eq!(pending_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn cancelling_purges_the_queue_and_counts_the_sent_emails() {
+	let app = spawn_app().await;
+	let issue_id = setup(&app).await;
+	try_execute_task(&app.db_pool, &app.email_client, &app.shutdown)
+		.await
+		.unwrap();
+
+	app.post_issue_delivery(issue_id, "cancel").await;
+
+	let html = app.get_issues_html().await;
+	assert!(html.contains("The delivery is cancelled: 1 emails had been sent, 2 will not be"));
+	assert_eq!(pending_deliveries(&app).await, 0);
+	let issue = sqlx::query!(
+		"SELECT delivery_status, sent_deliveries, cancelled_deliveries FROM newsletter_issues"
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap();
+	assert_eq!(issue.delivery_status, "cancelled");
+	assert_eq!(issue.sent_deliveries, 1);
+	assert_eq!(issue.cancelled_deliveries, Some(2));
+	app.dispatch_all_pending_emails().await;
+	assert_eq!(sent_emails(&app).await, 1);
+}
+
+#[tokio::test]
+async fn paused_issues_can_be_cancelled() {
+	let app = spawn_app().await;
+	let issue_id = setup(&app).await;
+	app.post_issue_delivery(issue_id, "pause").await;
+
+	app.post_issue_delivery(issue_id, "cancel").await;
+	app.post_issue_delivery(issue_id, "resume").await;
+
+	app.dispatch_all_pending_emails().await;
+	assert_eq!(sent_emails(&app).await, 0);
+	assert!(app
+		.get_issues_html()
+		.await
+		.contains("Only paused issues can be resumed"));
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_not_requeued() {
+	let app = spawn_app().await;
+	let issue_id = setup(&app).await;
+	app.post_issue_delivery(issue_id, "cancel").await;
+
+	let queued = requeue_issue(&app.db_pool, issue_id, None).await.unwrap();
+
+	assert_eq!(queued, 0);
+	assert_eq!(pending_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn changes_to_a_delivery_are_audited() {
+	let app = spawn_app().await;
+	let issue_id = setup(&app).await;
+
+	app.post_issue_delivery(issue_id, "pause").await;
+
+	let target = sqlx::query!(
+		"SELECT target FROM audit_log WHERE action = 'issue_delivery_change'"
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.target;
+	assert_eq!(target, Some(format!("{} (pause)", issue_id)));
+}
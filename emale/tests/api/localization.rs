@@ -0,0 +1,229 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe_with_accept_language(app: &TestApp, body: &str, accept_language: &str) {
+	let form_token = app.subscribe_form_token().await;
+	app.api_client
+		.post(&format!("{}/subscribe", &app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("Accept-Language", accept_language)
+		.body(format!("{}&form_token={}", body, form_token))
+		.send()
+		.await
+		.unwrap()
+		.error_for_status()
+		.unwrap();
+	app.dispatch_all_pending_emails().await;
+}
+
+async fn confirmation_email(app: &TestApp) -> serde_json::Value {
+	let email_request = app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	serde_json::from_slice(&email_request.body).unwrap()
+}
+
+async fn stored_locale(app: &TestApp) -> String {
+	sqlx::query!("SELECT locale FROM subscriber")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.locale
+}
+
+async fn create_subscriber(app: &TestApp, locale: &str) -> String {
+	let email = format!("{}@example.com", Uuid::new_v4());
+	sqlx::query!(
+		"INSERT INTO subscriber (id, email, name, subscribed_at, status, locale)
+		VALUES ($1, $2, 'le guin', now(), 'confirmed', $3)",
+		Uuid::new_v4(),
+		email,
+		locale
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	email
+}
+
+#[tokio::test]
+async fn the_confirmation_email_follows_the_browser_language() {
+	let app = spawn_app().await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+
+	subscribe_with_accept_language(
+		&app,
+		"name=le%20guin&email=ursula_le_guin%40gmail.com",
+		"fr-FR, fr;q=0.9, en;q=0.8"
+	)
+	.await;
+
+	let email = confirmation_email(&app).await;
+	assert_eq!(email["Subject"], "Confirmez votre abonnement");
+	assert!(email["TextBody"].as_str().unwrap().contains("pour confirmer votre abonnement"));
+	assert_eq!(stored_locale(&app).await, "fr");
+}
+
+#[tokio::test]
+async fn the_language_picked_on_the_form_wins() {
+	let app = spawn_app().await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+
+	subscribe_with_accept_language(
+		&app,
+		"name=le%20guin&email=ursula_le_guin%40gmail.com&locale=en",
+		"fr"
+	)
+	.await;
+
+	let email = confirmation_email(&app).await;
+	assert_eq!(email["Subject"], "Confirm your subscription");
+	assert_eq!(stored_locale(&app).await, "en");
+}
+
+#[tokio::test]
+async fn unsupported_languages_fall_back_to_english() {
+	let app = spawn_app().await;
+
+	let html = app
+		.api_client
+		.get(&app.address)
+		.header("Accept-Language", "de-DE, de;q=0.9")
+		.send()
+		.await
+		.unwrap()
+		.text()
+		.await
+		.unwrap();
+
+	assert!(html.contains(r#"<html lang="en">"#));
+	assert!(html.contains("Subscribe"));
+}
+
+#[tokio::test]
+async fn the_home_page_is_translated() {
+	let app = spawn_app().await;
+
+	let html = app
+		.api_client
+		.get(&app.address)
+		.header("Accept-Language", "fr")
+		.send()
+		.await
+		.unwrap()
+		.text()
+		.await
+		.unwrap();
+
+	assert!(html.contains(r#"<html lang="fr">"#));
+	assert!(html.contains(r#"<option value="fr" selected>"#));
+	assert!(html.contains("Télécharger ou effacer vos données"));
+}
+
+#[tokio::test]
+async fn subscribers_receive_the_translation_of_their_locale() {
+	let app = spawn_app().await;
+	let french = create_subscriber(&app, "fr").await;
+	let english = create_subscriber(&app, "en").await;
+	app.test_user.login(&app).await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(2)
+		.mount(&app.email_server)
+		.await;
+
+	let response = app
+		.post_publish_newsletter(&serde_json::json!({
+			"title": "The Left Hand of Darkness",
+			"text_content": "text content",
+			"html_content": "<p>HTML content</p>",
+			"title.fr": "La Main gauche de la nuit",
+			"text_content.fr": "contenu texte",
+			"html_content.fr": "<p>contenu HTML</p>",
+			"idempotency_key": Uuid::new_v4().to_string()
+		}))
+		.await;
+	assert_eq!(response.status().as_u16(), 303);
+	app.dispatch_all_pending_emails().await;
+
+	let subjects: std::collections::HashMap<String, String> = app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.iter()
+		.map(|request| {
+			let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+			(
+				body["To"].as_str().unwrap().to_owned(),
+				body["Subject"].as_str().unwrap().to_owned()
+			)
+		})
+		.collect();
+	assert_eq!(subjects[&french], "La Main gauche de la nuit");
+	assert_eq!(subjects[&english], "The Left Hand of Darkness");
+}
+
+#[tokio::test]
+async fn partial_translations_are_refused() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	let response = app
+		.post_publish_newsletter(&serde_json::json!({
+			"title": "The Left Hand of Darkness",
+			"text_content": "text content",
+			"html_content": "<p>HTML content</p>",
+			"title.fr": "La Main gauche de la nuit",
+			"idempotency_key": Uuid::new_v4().to_string()
+		}))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_language_can_be_changed_from_the_preferences() {
+	let app = spawn_app().await;
+	let email = create_subscriber(&app, "en").await;
+	let subscriber_id = sqlx::query!("SELECT id FROM subscriber WHERE email = $1", email)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.id;
+
+	let signature = emale::preferences::preferences_signature(&app.hmac_secret, subscriber_id);
+	app.api_client
+		.post(&format!("{}/preferences", &app.address))
+		.form(&[
+			("subscriber_id", subscriber_id.to_string()),
+			("signature", signature),
+			("name", "le guin".into()),
+			("email", email),
+			("locale", "fr".into()),
+			("list_id", "newsletter".into()),
+		])
+		.send()
+		.await
+		.unwrap();
+
+	let html = app.get_preferences_html(subscriber_id).await;
+	assert!(html.contains(r#"<html lang="fr">"#));
+	assert!(html.contains("Vos préférences ont été enregistrées"));
+}
@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestUser};
 
 #[tokio::test]
 async fn set_erorr_flash_message_when_something_wrong() {
@@ -31,4 +31,125 @@ async fn redirect_to_admin_dashboard_after_login_success() {
 
 	let html_page = app.get_admin_dashboard_html().await;
 	assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)))
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn username_is_locked_out_after_too_many_failed_attempts() {
+	let app = spawn_app().await;
+	let wrong_login_body = serde_json::json!({
+		"username": &app.test_user.username,
+		"password": "wrong-password"
+	});
+	for _ in 0..5 {
+		app.post_login(&wrong_login_body).await;
+	}
+
+	// the right password is refused while locked out
+	let response = app.post_login(&serde_json::json!({
+		"username": &app.test_user.username,
+		"password": &app.test_user.password
+	})).await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+
+	let html_page = app.get_login_html().await;
+	assert!(html_page.contains(
+		"<p><i>Too many failed login attempts, please try again later</i></p>"
+	));
+
+	let n_lockouts = sqlx::query!(
+		"SELECT count(*) as \"count!\" FROM auth_events WHERE event_type = 'lockout' AND username = $1",
+		app.test_user.username
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count;
+	assert_eq!(n_lockouts, 1);
+}
+
+#[tokio::test]
+async fn successful_login_resets_failed_attempts() {
+	let app = spawn_app().await;
+	let wrong_login_body = serde_json::json!({
+		"username": &app.test_user.username,
+		"password": "wrong-password"
+	});
+	let login_body = serde_json::json!({
+		"username": &app.test_user.username,
+		"password": &app.test_user.password
+	});
+
+	for _ in 0..4 {
+		app.post_login(&wrong_login_body).await;
+	}
+	let response = app.post_login(&login_body).await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/admin/dashboard");
+	app.post_logout().await;
+
+	for _ in 0..4 {
+		app.post_login(&wrong_login_body).await;
+	}
+	let response = app.post_login(&login_body).await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn forwarded_headers_do_not_get_around_the_per_ip_limit() {
+	let app = spawn_app_with(|c| c.login_throttle.max_attempts_per_ip = 3).await;
+
+	for i in 0..3 {
+		app.api_client
+			.post(&format!("{}/login", &app.address))
+			.header("X-Forwarded-For", format!("203.0.113.{}", i))
+			.form(&serde_json::json!({
+				"username": format!("someone-{}", i),
+				"password": "wrong-password"
+			}))
+			.send()
+			.await
+			.expect("Failed to execute request");
+	}
+
+	// no proxy is trusted, the attempts all came from the same address
+	let response = app
+		.api_client
+		.post(&format!("{}/login", &app.address))
+		.header("X-Forwarded-For", "203.0.113.42")
+		.form(&serde_json::json!({
+			"username": &app.test_user.username,
+			"password": &app.test_user.password
+		}))
+		.send()
+		.await
+		.expect("Failed to execute request");
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn logging_into_another_account_does_not_reset_the_per_ip_limit() {
+	let app = spawn_app_with(|c| c.login_throttle.max_attempts_per_ip = 3).await;
+	let other_user = TestUser::generate();
+	other_user.store(&app.db_pool).await;
+	let wrong_login = |i: usize| {
+		serde_json::json!({
+			"username": format!("someone-{}", i),
+			"password": "wrong-password"
+		})
+	};
+
+	for i in 0..2 {
+		app.post_login(&wrong_login(i)).await;
+	}
+	app.test_user.login(&app).await;
+	app.post_logout().await;
+	app.post_login(&wrong_login(2)).await;
+
+	// the IP is locked out, whatever the account
+	let response = app
+		.post_login(&serde_json::json!({
+			"username": &other_user.username,
+			"password": &other_user.password
+		}))
+		.await;
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+}
@@ -5,4 +5,26 @@ mod subscribe_confirm;
 mod newsletter;
 mod login;
 mod admin_dashboard;
-mod change_password;
\ No newline at end of file
+mod change_password;
+mod two_factor;
+mod password_reset;
+mod sessions;
+mod audit;
+mod csrf;
+mod metrics;
+mod trace_propagation;
+mod graceful_shutdown;
+mod cli;
+mod session_store;
+mod subscribe_protection;
+mod email_validation;
+mod subscriber_data;
+mod preferences;
+mod localization;
+mod archive;
+mod sequences;
+mod webhooks;
+mod idempotency;
+mod issue_delivery;
+mod publications;
+mod counters;
\ No newline at end of file
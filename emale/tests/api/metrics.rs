@@ -0,0 +1,51 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn metrics_require_the_bearer_token() {
+	let app = spawn_app().await;
+
+	let response = app.get_metrics(None).await;
+	assert_eq!(response.status().as_u16(), 401);
+
+	let response = app.get_metrics(Some("wrong-token")).await;
+	assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn http_requests_are_counted_per_route() {
+	let app = spawn_app().await;
+	app.api_client
+		.get(&format!("{}/health_check", &app.address))
+		.send()
+		.await
+		.expect("Failed to execute request");
+
+	let metrics = app.get_metrics_text().await;
+	assert!(metrics.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#));
+	assert!(metrics.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/health_check""#));
+}
+
+#[tokio::test]
+async fn login_outcomes_are_counted() {
+	let app = spawn_app().await;
+	app.post_login(&serde_json::json!({
+		"username": &app.test_user.username,
+		"password": "wrong-password"
+	}))
+	.await;
+	app.test_user.login(&app).await;
+
+	let metrics = app.get_metrics_text().await;
+	assert!(metrics.contains(r#"login_attempts_total{outcome="failure"}"#));
+	assert!(metrics.contains(r#"login_attempts_total{outcome="success"}"#));
+}
+
+#[tokio::test]
+async fn queue_depth_and_pool_usage_are_exposed() {
+	let app = spawn_app().await;
+
+	let metrics = app.get_metrics_text().await;
+	assert!(metrics.contains("issue_delivery_queue_depth "));
+	assert!(metrics.contains("db_pool_connections "));
+	assert!(metrics.contains("db_pool_idle_connections "));
+}
@@ -27,6 +27,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLink {
         .await
         .error_for_status()
         .unwrap();
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app
         .email_server
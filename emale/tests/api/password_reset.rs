@@ -0,0 +1,241 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn request_reset_token(app: &TestApp) -> String {
+	let _mock_guard = Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount_as_scoped(&app.email_server)
+		.await;
+
+	app.post_password_reset(&serde_json::json!({
+		"email": &app.test_user.email
+	}))
+	.await;
+	app.dispatch_all_pending_emails().await;
+
+	let email_request = &app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	let reset_link = app.get_confirmation_link(email_request);
+	assert_eq!(reset_link.html, reset_link.plain_text);
+	reset_link.html
+		.query_pairs()
+		.find(|(key, _)| key == "token")
+		.map(|(_, token)| token.into_owned())
+		.unwrap()
+}
+
+#[tokio::test]
+async fn login_form_links_to_password_reset() {
+	let app = spawn_app().await;
+	let html = app.get_login_html().await;
+	assert!(html.contains(r#"<a href="/password-reset">"#));
+}
+
+#[tokio::test]
+async fn unknown_email_gets_the_same_response_without_an_email() {
+	let app = spawn_app().await;
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&app.email_server)
+		.await;
+
+	let response = app.post_password_reset(&serde_json::json!({
+		"email": "nobody@example.com"
+	}))
+	.await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+	app.dispatch_all_pending_emails().await;
+
+	let html = app.get_login_html().await;
+	assert!(html.contains("a link to reset the password has been sent to it"));
+}
+
+#[tokio::test]
+async fn password_can_be_reset_with_emailed_link() {
+	let app = spawn_app().await;
+	let token = request_reset_token(&app).await;
+	let new_password = uuid::Uuid::new_v4().to_string();
+
+	let response = app.post_password_reset_confirm(&serde_json::json!({
+		"token": &token,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	}))
+	.await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+
+	let response = app.post_login(&serde_json::json!({
+		"username": &app.test_user.username,
+		"password": &new_password
+	}))
+	.await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_token_can_be_used_only_once() {
+	let app = spawn_app().await;
+	let token = request_reset_token(&app).await;
+	let new_password = uuid::Uuid::new_v4().to_string();
+	let body = serde_json::json!({
+		"token": &token,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	});
+
+	app.post_password_reset_confirm(&body).await;
+	let response = app.post_password_reset_confirm(&body).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/password-reset");
+}
+
+#[tokio::test]
+async fn reset_token_is_stored_hashed() {
+	let app = spawn_app().await;
+	let token = request_reset_token(&app).await;
+
+	let row = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap();
+	assert_ne!(row.token_hash, token);
+}
+
+#[tokio::test]
+async fn new_password_must_follow_password_rules() {
+	let app = spawn_app().await;
+	let token = request_reset_token(&app).await;
+
+	let response = app.post_password_reset_confirm(&serde_json::json!({
+		"token": &token,
+		"new_password": "short",
+		"new_password_check": "short"
+	}))
+	.await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert!(response
+		.headers()
+		.get("LOCATION")
+		.unwrap()
+		.to_str()
+		.unwrap()
+		.starts_with("/password-reset/confirm?token="));
+
+	// the token has not been consumed
+	let new_password = uuid::Uuid::new_v4().to_string();
+	let response = app.post_password_reset_confirm(&serde_json::json!({
+		"token": &token,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	}))
+	.await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn password_reset_logs_out_existing_sessions() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.status().as_u16(), 200);
+
+	let token = request_reset_token(&app).await;
+	let new_password = uuid::Uuid::new_v4().to_string();
+	app.post_password_reset_confirm(&serde_json::json!({
+		"token": &token,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	}))
+	.await;
+
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn a_failed_reset_does_not_spend_the_token() {
+	let app = spawn_app().await;
+	let token = request_reset_token(&app).await;
+	let new_password = uuid::Uuid::new_v4().to_string();
+	let body = serde_json::json!({
+		"token": &token,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	});
+	// the password change fails after the token was consumed
+	sqlx::query(
+		"CREATE FUNCTION reject_password_change() RETURNS trigger AS $$
+		BEGIN
+			RAISE EXCEPTION 'password changes are rejected';
+		END;
+		$$ LANGUAGE plpgsql"
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	sqlx::query(
+		"CREATE TRIGGER reject_password_change BEFORE UPDATE ON users
+		FOR EACH ROW EXECUTE FUNCTION reject_password_change()"
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	let response = app.post_password_reset_confirm(&body).await;
+	assert_eq!(response.status().as_u16(), 500);
+
+	sqlx::query("DROP TRIGGER reject_password_change ON users")
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+	let response = app.post_password_reset_confirm(&body).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn reset_requests_beyond_the_per_address_limit_send_no_email() {
+	let app = spawn_app_with(|c| c.password_reset_throttle.max_requests_per_address = 1).await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&app.email_server)
+		.await;
+
+	for email in [app.test_user.email.clone(), app.test_user.email.to_uppercase()] {
+		let response = app.post_password_reset(&serde_json::json!({ "email": email })).await;
+		assert_eq!(response.status().as_u16(), 303);
+		assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+	}
+	app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn reset_requests_beyond_the_per_ip_limit_send_no_email() {
+	let app = spawn_app_with(|c| c.password_reset_throttle.max_requests_per_ip = 1).await;
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&app.email_server)
+		.await;
+
+	for email in ["nobody@example.com", app.test_user.email.as_str()] {
+		let response = app.post_password_reset(&serde_json::json!({ "email": email })).await;
+		assert_eq!(response.status().as_u16(), 303);
+	}
+	app.dispatch_all_pending_emails().await;
+}
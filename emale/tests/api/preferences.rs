@@ -0,0 +1,237 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+struct TestSubscriber {
+	id: Uuid,
+	email: String,
+}
+
+async fn create_subscriber(app: &TestApp) -> TestSubscriber {
+	let subscriber = TestSubscriber {
+		id: Uuid::new_v4(),
+		email: format!("{}@example.com", Uuid::new_v4()),
+	};
+	sqlx::query!(
+		"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+		VALUES ($1, $2, 'le guin', now(), 'confirmed')",
+		subscriber.id,
+		subscriber.email
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	subscriber
+}
+
+async fn subscriber_row(app: &TestApp, id: Uuid) -> (String, String) {
+	let row = sqlx::query!("SELECT name, email FROM subscriber WHERE id = $1", id)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap();
+	(row.name, row.email)
+}
+
+// Changes the address and returns the link sent to the new one.
+async fn request_email_change(app: &TestApp, subscriber: &TestSubscriber, new_email: &str) -> reqwest::Url {
+	let _mock_guard = Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount_as_scoped(&app.email_server)
+		.await;
+
+	let response = app
+		.post_preferences(subscriber.id, "le guin", new_email, &["newsletter"])
+		.await;
+	assert_eq!(response.status().as_u16(), 303);
+
+	let email_request = app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+	assert_eq!(body["To"], new_email);
+	app.get_confirmation_link(&email_request).html
+}
+
+#[tokio::test]
+async fn links_with_an_invalid_signature_are_rejected() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+
+	let response = app
+		.api_client
+		.get(&format!(
+			"{}/preferences?subscriber_id={}&signature=deadbeef",
+			&app.address, subscriber.id
+		))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_signed_link_shows_the_current_preferences() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+
+	let response = app.get_preferences(subscriber.id).await;
+	assert_eq!(response.status().as_u16(), 200);
+
+	let html = response.text().await.unwrap();
+	assert!(html.contains(&subscriber.email));
+	assert!(html.contains("le guin"));
+	assert!(html.contains(r#"value="newsletter" checked"#));
+}
+
+#[tokio::test]
+async fn the_name_can_be_changed() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+
+	let response = app
+		.post_preferences(subscriber.id, "ursula", &subscriber.email, &["newsletter"])
+		.await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(subscriber_row(&app, subscriber.id).await.0, "ursula");
+	let html = app.get_preferences_html(subscriber.id).await;
+	assert!(html.contains("Your preferences have been saved"));
+}
+
+#[tokio::test]
+async fn invalid_names_are_refused() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+
+	app.post_preferences(subscriber.id, "<ursula>", &subscriber.email, &["newsletter"])
+		.await;
+
+	assert_eq!(subscriber_row(&app, subscriber.id).await.0, "le guin");
+}
+
+#[tokio::test]
+async fn issues_are_not_queued_for_lists_opted_out_of() {
+	let app = spawn_app().await;
+	sqlx::query!("INSERT INTO mailing_lists (list_id, name) VALUES ('weekly', 'Weekly')")
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+	let subscriber = create_subscriber(&app).await;
+	app.post_preferences(subscriber.id, "le guin", &subscriber.email, &["weekly"])
+		.await;
+	app.test_user.login(&app).await;
+
+	for list_id in ["newsletter", "weekly"] {
+		let response = app
+			.post_publish_newsletter(&serde_json::json!({
+				"title": list_id,
+				"text_content": "text content",
+				"html_content": "<p>HTML CONTENT</p>",
+				"idempotency_key": Uuid::new_v4().to_string(),
+				"list_id": list_id
+			}))
+			.await;
+		assert_eq!(response.status().as_u16(), 303);
+	}
+
+	let queued: Vec<String> = sqlx::query!(
+		"SELECT i.title FROM issue_delivery_queue q JOIN newsletter_issues i USING (newsletter_issue_id)
+		WHERE q.subscriber_email = $1",
+		subscriber.email
+	)
+	.fetch_all(&app.db_pool)
+	.await
+	.unwrap()
+	.into_iter()
+	.map(|r| r.title)
+	.collect();
+	assert_eq!(queued, vec!["weekly".to_string()]);
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_refused() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	let response = app
+		.post_publish_newsletter(&serde_json::json!({
+			"title": "title",
+			"text_content": "text content",
+			"html_content": "<p>HTML CONTENT</p>",
+			"idempotency_key": Uuid::new_v4().to_string(),
+			"list_id": "unknown"
+		}))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_email_only_changes_once_the_new_address_is_confirmed() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+	let new_email = format!("{}@example.com", Uuid::new_v4());
+
+	let confirmation_link = request_email_change(&app, &subscriber, &new_email).await;
+	assert_eq!(subscriber_row(&app, subscriber.id).await.1, subscriber.email);
+	assert!(app
+		.get_preferences_html(subscriber.id)
+		.await
+		.contains(&format!("Waiting for {} to be confirmed", new_email)));
+
+	let response = app.api_client.get(confirmation_link.clone()).send().await.unwrap();
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(subscriber_row(&app, subscriber.id).await.1, new_email);
+
+	// the link is single use
+	let response = app.api_client.get(confirmation_link).send().await.unwrap();
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/");
+}
+
+#[tokio::test]
+async fn addresses_failing_validation_are_refused_without_an_email() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&app.email_server)
+		.await;
+
+	for email in ["not-an-email", "ursula@mailinator.com"] {
+		app.post_preferences(subscriber.id, "le guin", email, &["newsletter"])
+			.await;
+	}
+
+	assert_eq!(subscriber_row(&app, subscriber.id).await.1, subscriber.email);
+}
+
+#[tokio::test]
+async fn an_address_taken_in_the_meantime_is_not_switched_to() {
+	let app = spawn_app().await;
+	let subscriber = create_subscriber(&app).await;
+	let new_email = format!("{}@example.com", Uuid::new_v4());
+	let confirmation_link = request_email_change(&app, &subscriber, &new_email).await;
+	sqlx::query!(
+		"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+		VALUES ($1, $2, 'ursula', now(), 'confirmed')",
+		Uuid::new_v4(),
+		new_email
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	app.api_client.get(confirmation_link).send().await.unwrap();
+
+	assert_eq!(subscriber_row(&app, subscriber.id).await.1, subscriber.email);
+}
@@ -0,0 +1,307 @@
+use emale::publications::{add_member, create_publication, NewPublication};
+use wiremock::matchers::{method, path};
+use uuid::Uuid;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const HOST: &str = "letters.example.com";
+
+async fn create_letters(app: &TestApp) {
+	create_publication(
+		&app.db_pool,
+		NewPublication {
+			publication_id: "letters",
+			name: "Letters",
+			host: Some(HOST),
+			base_url: Some("https://letters.example.com"),
+			sender_email: Some("letters@example.com"),
+		},
+	)
+	.await
+	.unwrap();
+}
+
+async fn mock_email_server(app: &TestApp) {
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+}
+
+async fn subscribe_on(app: &TestApp, host: &str, email: &str) -> reqwest::Response {
+	let body = serde_urlencoded::to_string(&serde_json::json!({
+		"name": "le guin",
+		"email": email,
+		"form_token": app.subscribe_form_token().await
+	}))
+	.unwrap();
+	app.api_client
+		.post(&format!("{}/subscribe", &app.address))
+		.header("Host", host)
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.body(body)
+		.send()
+		.await
+		.expect("Failed to execute request")
+}
+
+async fn get_on(app: &TestApp, host: &str, path: &str) -> reqwest::Response {
+	app.api_client
+		.get(&format!("{}{}", &app.address, path))
+		.header("Host", host)
+		.send()
+		.await
+		.expect("Failed to execute request")
+}
+
+async fn confirm_everyone(app: &TestApp) {
+	sqlx::query!("UPDATE subscriber SET status = 'confirmed'")
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+}
+
+async fn publish_on(app: &TestApp, host: &str, title: &str) -> reqwest::Response {
+	let csrf_token = app.csrf_token().await;
+	app.api_client
+		.post(&format!("{}/admin/newsletters", &app.address))
+		.header("Host", host)
+		.header("X-CSRF-Token", csrf_token)
+		.form(&serde_json::json!({
+			"title": title,
+			"text_content": "Newsletter body as plain text",
+			"html_content": "<p>Newsletter body as HTML</p>",
+			"idempotency_key": Uuid::new_v4().to_string()
+		}))
+		.send()
+		.await
+		.expect("Failed to execute request")
+}
+
+fn email_body(request: &wiremock::Request) -> serde_json::Value {
+	serde_json::from_slice(&request.body).unwrap()
+}
+
+#[tokio::test]
+async fn subscribers_are_kept_apart_per_publication() {
+	let app = spawn_app().await;
+	create_letters(&app).await;
+
+	subscribe_on(&app, "127.0.0.1", "ursula_le_guin@gmail.com")
+		.await
+		.error_for_status()
+		.unwrap();
+	subscribe_on(&app, HOST, "ursula_le_guin@gmail.com")
+		.await
+		.error_for_status()
+		.unwrap();
+
+	let publications: Vec<String> = sqlx::query!(
+		"SELECT publication_id FROM subscriber ORDER BY publication_id"
+	)
+	.fetch_all(&app.db_pool)
+	.await
+	.unwrap()
+	.into_iter()
+	.map(|r| r.publication_id)
+	.collect();
+	assert_eq!(publications, vec!["default", "letters"]);
+}
+
+#[tokio::test]
+async fn unknown_hosts_get_the_default_publication() {
+	let app = spawn_app().await;
+	create_letters(&app).await;
+
+	subscribe_on(&app, "elsewhere.example.com", "ursula_le_guin@gmail.com")
+		.await
+		.error_for_status()
+		.unwrap();
+
+	let subscriber = sqlx::query!("SELECT publication_id FROM subscriber")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(subscriber.publication_id, "default");
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_sent_by_the_publication() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	create_letters(&app).await;
+
+	subscribe_on(&app, HOST, "ursula_le_guin@gmail.com")
+		.await
+		.error_for_status()
+		.unwrap();
+	app.dispatch_all_pending_emails().await;
+
+	let requests = app.email_server.received_requests().await.unwrap();
+	let body = email_body(&requests[0]);
+	assert_eq!(body["From"], "letters@example.com");
+	assert!(body["TextBody"]
+		.as_str()
+		.unwrap()
+		.contains("https://letters.example.com/subscribe/confirm"));
+}
+
+#[tokio::test]
+async fn issues_only_go_to_the_subscribers_of_their_publication() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	create_letters(&app).await;
+	add_member(&app.db_pool, "letters", app.test_user.user_id)
+		.await
+		.unwrap();
+	subscribe_on(&app, "127.0.0.1", "default@example.com").await;
+	subscribe_on(&app, HOST, "reader@example.com").await;
+	app.dispatch_all_pending_emails().await;
+	confirm_everyone(&app).await;
+	let confirmations = app.email_server.received_requests().await.unwrap().len();
+	app.test_user.login(&app).await;
+
+	let response = publish_on(&app, HOST, "Letter #1").await;
+	assert_eq!(response.status().as_u16(), 303);
+	app.dispatch_all_pending_emails().await;
+
+	let requests = app.email_server.received_requests().await.unwrap();
+	let issues: Vec<_> = requests[confirmations..].iter().map(email_body).collect();
+	assert_eq!(issues.len(), 1);
+	assert_eq!(issues[0]["To"], "reader@example.com");
+	assert_eq!(issues[0]["From"], "letters@example.com");
+}
+
+#[tokio::test]
+async fn the_archive_only_lists_the_issues_of_the_publication() {
+	let app = spawn_app().await;
+	create_letters(&app).await;
+	add_member(&app.db_pool, "letters", app.test_user.user_id)
+		.await
+		.unwrap();
+	app.test_user.login(&app).await;
+
+	publish_on(&app, HOST, "Letter #1").await;
+
+	let html = get_on(&app, HOST, "/archive").await.text().await.unwrap();
+	assert!(html.contains("Letter #1"));
+	let html = get_on(&app, "127.0.0.1", "/archive").await.text().await.unwrap();
+	assert!(!html.contains("Letter #1"));
+	let feed = get_on(&app, HOST, "/feed.rss").await.text().await.unwrap();
+	assert!(feed.contains("<title>Letters</title>"));
+	assert!(feed.contains("https://letters.example.com/archive/"));
+}
+
+#[tokio::test]
+async fn admins_cannot_manage_the_publications_they_are_not_members_of() {
+	let app = spawn_app().await;
+	create_letters(&app).await;
+	app.test_user.login(&app).await;
+
+	let response = get_on(&app, HOST, "/admin/dashboard").await;
+	assert_eq!(response.status().as_u16(), 403);
+	let response = publish_on(&app, HOST, "Letter #1").await;
+	assert_eq!(response.status().as_u16(), 403);
+
+	let issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(issues, 0);
+}
+
+#[tokio::test]
+async fn admins_only_see_the_audit_log_of_the_publication() {
+	let app = spawn_app().await;
+	create_letters(&app).await;
+	add_member(&app.db_pool, "letters", app.test_user.user_id)
+		.await
+		.unwrap();
+	app.test_user.login(&app).await;
+
+	publish_on(&app, HOST, "Letter #1").await;
+
+	let html = get_on(&app, HOST, "/admin/audit").await.text().await.unwrap();
+	assert!(html.contains("Letter #1"));
+	assert!(!html.contains("<td>login</td>"));
+	let html = get_on(&app, "127.0.0.1", "/admin/audit").await.text().await.unwrap();
+	assert!(!html.contains("Letter #1"));
+	assert!(html.contains("<td>login</td>"));
+}
+
+#[tokio::test]
+async fn password_reset_emails_are_sent_by_the_publication() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	create_letters(&app).await;
+
+	app.api_client
+		.post(&format!("{}/password-reset", &app.address))
+		.header("Host", HOST)
+		.form(&serde_json::json!({ "email": &app.test_user.email }))
+		.send()
+		.await
+		.expect("Failed to execute request");
+	app.dispatch_all_pending_emails().await;
+
+	let requests = app.email_server.received_requests().await.unwrap();
+	let body = email_body(&requests[0]);
+	assert_eq!(body["From"], "letters@example.com");
+	assert!(body["TextBody"]
+		.as_str()
+		.unwrap()
+		.contains("https://letters.example.com/password-reset/confirm"));
+}
+
+async fn subscribe_forwarded_to(app: &TestApp, forwarded_host: &str) {
+	let body = serde_urlencoded::to_string(&serde_json::json!({
+		"name": "le guin",
+		"email": "ursula_le_guin@gmail.com",
+		"form_token": app.subscribe_form_token().await
+	}))
+	.unwrap();
+	app.api_client
+		.post(&format!("{}/subscribe", &app.address))
+		.header("X-Forwarded-Host", forwarded_host)
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.body(body)
+		.send()
+		.await
+		.expect("Failed to execute request")
+		.error_for_status()
+		.unwrap();
+}
+
+async fn subscriber_publication(app: &TestApp) -> String {
+	sqlx::query!("SELECT publication_id FROM subscriber")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.publication_id
+}
+
+#[tokio::test]
+async fn forwarded_hosts_of_clients_are_ignored() {
+	let app = spawn_app().await;
+	create_letters(&app).await;
+
+	subscribe_forwarded_to(&app, HOST).await;
+
+	assert_eq!(subscriber_publication(&app).await, "default");
+}
+
+#[tokio::test]
+async fn forwarded_hosts_of_trusted_proxies_are_honoured() {
+	let app = spawn_app_with(|c| {
+		c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()]
+	})
+	.await;
+	create_letters(&app).await;
+
+	subscribe_forwarded_to(&app, HOST).await;
+
+	assert_eq!(subscriber_publication(&app).await, "letters");
+}
@@ -0,0 +1,259 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_sequence(app: &TestApp, name: &str, steps: &[(i32, &str)]) -> Uuid {
+	let response = app.post_sequence(name).await;
+	assert_eq!(response.status().as_u16(), 303);
+	let sequence_id = sqlx::query!(
+		"SELECT sequence_id FROM automation_sequences WHERE name = $1",
+		name
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.sequence_id;
+	for (delay_days, subject) in steps {
+		let response = app.post_sequence_step(sequence_id, *delay_days, subject).await;
+		assert_eq!(response.status().as_u16(), 303);
+	}
+	sequence_id
+}
+
+async fn subscribe_and_confirm(app: &TestApp) -> Uuid {
+	let email = format!("{}@example.com", Uuid::new_v4());
+	let body = serde_urlencoded::to_string(&serde_json::json!({
+		"name": "le guin",
+		"email": email
+	}))
+	.unwrap();
+	app.post_subscriptions(body).await.error_for_status().unwrap();
+	app.dispatch_all_pending_emails().await;
+	let email_request = app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	let confirmation_link = app.get_confirmation_link(&email_request);
+	reqwest::get(confirmation_link.html)
+		.await
+		.unwrap()
+		.error_for_status()
+		.unwrap();
+	sqlx::query!("SELECT id FROM subscriber WHERE email = $1", email)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.id
+}
+
+async fn mock_email_server(app: &TestApp) {
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+}
+
+// Subjects of the emails sent, confirmation emails left out.
+async fn sent_subjects(app: &TestApp) -> Vec<String> {
+	app.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.iter()
+		.map(|request| {
+			let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+			body["Subject"].as_str().unwrap().to_owned()
+		})
+		.filter(|subject| subject != "Confirm your subscription")
+		.collect()
+}
+
+async fn backdate_enrollment(app: &TestApp, subscriber_id: Uuid, days: i32) {
+	sqlx::query!(
+		"UPDATE subscriber_sequence_progress
+		SET started_at = started_at - make_interval(days => $2)
+		WHERE subscriber_id = $1",
+		subscriber_id,
+		days
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_sequences() {
+	let app = spawn_app().await;
+
+	let response = app.get_sequences().await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn the_welcome_step_is_sent_right_after_confirmation() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	app.test_user.login(&app).await;
+	create_sequence(&app, "Onboarding", &[(0, "Welcome"), (3, "Tips"), (14, "Survey")]).await;
+
+	let subscriber_id = subscribe_and_confirm(&app).await;
+	app.run_automation().await;
+
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome"]);
+	let progress = sqlx::query!(
+		"SELECT reached_position, completed_at FROM subscriber_sequence_progress WHERE subscriber_id = $1",
+		subscriber_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap();
+	assert_eq!(progress.reached_position, Some(1));
+	assert!(progress.completed_at.is_none());
+}
+
+#[tokio::test]
+async fn later_steps_are_sent_once_they_fall_due() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	app.test_user.login(&app).await;
+	create_sequence(&app, "Onboarding", &[(0, "Welcome"), (3, "Tips"), (14, "Survey")]).await;
+	let subscriber_id = subscribe_and_confirm(&app).await;
+	app.run_automation().await;
+
+	backdate_enrollment(&app, subscriber_id, 4).await;
+	app.run_automation().await;
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome", "Tips"]);
+
+	backdate_enrollment(&app, subscriber_id, 10).await;
+	app.run_automation().await;
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome", "Tips", "Survey"]);
+	let progress = sqlx::query!(
+		"SELECT reached_position, completed_at FROM subscriber_sequence_progress WHERE subscriber_id = $1",
+		subscriber_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap();
+	assert_eq!(progress.reached_position, Some(3));
+	assert!(progress.completed_at.is_some());
+}
+
+#[tokio::test]
+async fn overdue_steps_are_all_sent_in_order() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	app.test_user.login(&app).await;
+	create_sequence(&app, "Onboarding", &[(0, "Welcome"), (3, "Tips"), (14, "Survey")]).await;
+	let subscriber_id = subscribe_and_confirm(&app).await;
+
+	backdate_enrollment(&app, subscriber_id, 30).await;
+	app.run_automation().await;
+
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome", "Tips", "Survey"]);
+}
+
+#[tokio::test]
+async fn subscribers_no_longer_confirmed_are_skipped() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	app.test_user.login(&app).await;
+	create_sequence(&app, "Onboarding", &[(0, "Welcome"), (3, "Tips")]).await;
+	let subscriber_id = subscribe_and_confirm(&app).await;
+	app.run_automation().await;
+
+	sqlx::query!("UPDATE subscriber SET status = 'pending' WHERE id = $1", subscriber_id)
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+	backdate_enrollment(&app, subscriber_id, 4).await;
+	app.run_automation().await;
+
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome"]);
+	let progress = sqlx::query!(
+		"SELECT reached_position FROM subscriber_sequence_progress WHERE subscriber_id = $1",
+		subscriber_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap();
+	assert_eq!(progress.reached_position, Some(1));
+}
+
+#[tokio::test]
+async fn paused_sequences_send_nothing_until_resumed() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	app.test_user.login(&app).await;
+	let sequence_id = create_sequence(&app, "Onboarding", &[(0, "Welcome")]).await;
+	let subscriber_id = subscribe_and_confirm(&app).await;
+
+	app.post_sequence_activity(sequence_id, false).await;
+	app.run_automation().await;
+	assert!(sent_subjects(&app).await.is_empty());
+
+	app.post_sequence_activity(sequence_id, true).await;
+	app.run_automation().await;
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome"]);
+	let enrolled = sqlx::query!(
+		"SELECT COUNT(*) AS \"count!\" FROM subscriber_sequence_progress WHERE subscriber_id = $1",
+		subscriber_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count;
+	assert_eq!(enrolled, 1);
+}
+
+#[tokio::test]
+async fn confirming_twice_does_not_restart_the_sequence() {
+	let app = spawn_app().await;
+	mock_email_server(&app).await;
+	app.test_user.login(&app).await;
+	create_sequence(&app, "Onboarding", &[(0, "Welcome")]).await;
+	let subscriber_id = subscribe_and_confirm(&app).await;
+	app.run_automation().await;
+
+	let token = sqlx::query!(
+		"SELECT subscriber_token FROM subscriber_tokens WHERE subscriber_id = $1",
+		subscriber_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.subscriber_token;
+	reqwest::get(&format!("{}/subscribe/confirm?subscription_token={}", app.address, token))
+		.await
+		.unwrap()
+		.error_for_status()
+		.unwrap();
+	app.run_automation().await;
+
+	assert_eq!(sent_subjects(&app).await, vec!["Welcome"]);
+}
+
+#[tokio::test]
+async fn negative_delays_are_refused() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let sequence_id = create_sequence(&app, "Onboarding", &[]).await;
+
+	app.post_sequence_step(sequence_id, -1, "Welcome").await;
+
+	let steps = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM automation_steps"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(steps, 0);
+	let html = app.get_sequences().await.text().await.unwrap();
+	assert!(html.contains("The delay cannot be negative"));
+}
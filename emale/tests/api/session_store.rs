@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{SessionKey, SessionStore};
+use actix_web::cookie::time::Duration;
+use emale::configuration::SessionStoreKind;
+use emale::session_store::{cleanup_expired_sessions, PgSessionStore};
+
+use crate::helpers::{spawn_app, spawn_app_with_session_store, TestApp};
+
+fn state() -> HashMap<String, String> {
+	HashMap::from([("user_id".to_string(), "\"some-user\"".to_string())])
+}
+
+async fn stored_sessions(app: &TestApp) -> i64 {
+	sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM sessions"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count
+}
+
+async fn expires_at(app: &TestApp, session_key: &SessionKey) -> chrono::DateTime<chrono::Utc> {
+	sqlx::query!(
+		"SELECT expires_at FROM sessions WHERE session_key = $1",
+		session_key.as_ref()
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.expires_at
+}
+
+#[tokio::test]
+async fn login_and_logout_work_with_every_session_store() {
+	for session_store in [SessionStoreKind::Redis, SessionStoreKind::Postgres] {
+		let app = spawn_app_with_session_store(session_store).await;
+		app.test_user.login(&app).await;
+
+		let response = app.get_admin_dashboard().await;
+		assert_eq!(response.status().as_u16(), 200, "{:?}", session_store);
+
+		app.post_logout().await;
+		let response = app.get_admin_dashboard().await;
+		assert_eq!(response.status().as_u16(), 303, "{:?}", session_store);
+	}
+}
+
+#[tokio::test]
+async fn postgres_sessions_are_stored_in_the_database_and_deleted_on_logout() {
+	let app = spawn_app_with_session_store(SessionStoreKind::Postgres).await;
+
+	app.test_user.login(&app).await;
+	assert_eq!(stored_sessions(&app).await, 1);
+
+	app.post_logout().await;
+	assert_eq!(stored_sessions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn saved_state_can_be_loaded_until_deleted() {
+	let app = spawn_app().await;
+	let store = PgSessionStore::new(app.db_pool.clone());
+
+	let session_key = store.save(state(), &Duration::hours(1)).await.unwrap();
+	assert_eq!(store.load(&session_key).await.unwrap(), Some(state()));
+
+	store.delete(&session_key).await.unwrap();
+	assert_eq!(store.load(&session_key).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn expired_sessions_are_not_loaded_and_get_cleaned_up() {
+	let app = spawn_app().await;
+	let store = PgSessionStore::new(app.db_pool.clone());
+
+	let expired = store.save(state(), &Duration::ZERO).await.unwrap();
+	let live = store.save(state(), &Duration::hours(1)).await.unwrap();
+	assert_eq!(store.load(&expired).await.unwrap(), None);
+
+	assert_eq!(cleanup_expired_sessions(&app.db_pool).await.unwrap(), 1);
+	assert_eq!(stored_sessions(&app).await, 1);
+	assert_eq!(store.load(&live).await.unwrap(), Some(state()));
+}
+
+#[tokio::test]
+async fn updating_an_expired_session_saves_it_under_a_new_key() {
+	let app = spawn_app().await;
+	let store = PgSessionStore::new(app.db_pool.clone());
+
+	let expired = store.save(HashMap::new(), &Duration::ZERO).await.unwrap();
+	let expired_key = expired.as_ref().to_owned();
+	let session_key = store.update(expired, state(), &Duration::hours(1)).await.unwrap();
+
+	assert_ne!(session_key.as_ref(), expired_key);
+	assert_eq!(store.load(&session_key).await.unwrap(), Some(state()));
+}
+
+#[tokio::test]
+async fn recently_renewed_sessions_are_not_written_again() {
+	let app = spawn_app().await;
+	let store = PgSessionStore::new(app.db_pool.clone());
+	let session_key = store.save(state(), &Duration::hours(1)).await.unwrap();
+	let saved_expiry = expires_at(&app, &session_key).await;
+
+	store.update_ttl(&session_key, &Duration::hours(1)).await.unwrap();
+	assert_eq!(expires_at(&app, &session_key).await, saved_expiry);
+
+	store.update_ttl(&session_key, &Duration::hours(2)).await.unwrap();
+	assert!(expires_at(&app, &session_key).await > saved_expiry);
+}
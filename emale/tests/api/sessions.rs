@@ -0,0 +1,193 @@
+use emale::authentication::purge_user_sessions;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+// A second browser, logged in as the test user
+async fn second_device(app: &TestApp) -> reqwest::Client {
+	let client = reqwest::Client::builder()
+		.redirect(reqwest::redirect::Policy::none())
+		.cookie_store(true)
+		.user_agent("second-device")
+		.build()
+		.unwrap();
+	client
+		.post(&format!("{}/login", &app.address))
+		.form(&serde_json::json!({
+			"username": &app.test_user.username,
+			"password": &app.test_user.password
+		}))
+		.send()
+		.await
+		.unwrap();
+	client
+}
+
+async fn get_dashboard_status(app: &TestApp, client: &reqwest::Client) -> u16 {
+	client
+		.get(&format!("{}/admin/dashboard", &app.address))
+		.send()
+		.await
+		.unwrap()
+		.status()
+		.as_u16()
+}
+
+#[tokio::test]
+async fn must_logged_in_to_list_sessions() {
+	let app = spawn_app().await;
+	let response = app.get_sessions().await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn sessions_page_lists_other_devices() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	second_device(&app).await;
+
+	let html = app.get_sessions().await.text().await.unwrap();
+	assert!(html.contains("This session"));
+	assert!(html.contains("second-device"));
+}
+
+#[tokio::test]
+async fn user_agent_is_escaped_in_sessions_page() {
+	let app = spawn_app().await;
+	let client = reqwest::Client::builder()
+		.redirect(reqwest::redirect::Policy::none())
+		.user_agent("<script>alert(1)</script>")
+		.build()
+		.unwrap();
+	client
+		.post(&format!("{}/login", &app.address))
+		.form(&serde_json::json!({
+			"username": &app.test_user.username,
+			"password": &app.test_user.password
+		}))
+		.send()
+		.await
+		.unwrap();
+	app.test_user.login(&app).await;
+
+	let html = app.get_sessions().await.text().await.unwrap();
+	assert!(!html.contains("<script>alert(1)</script>"));
+	assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+}
+
+#[tokio::test]
+async fn single_session_can_be_revoked() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let other = second_device(&app).await;
+	assert_eq!(get_dashboard_status(&app, &other).await, 200);
+
+	let session_id: Uuid = sqlx::query!(
+		"SELECT session_id FROM user_sessions WHERE user_agent = 'second-device'"
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.session_id;
+
+	let response = app.post_revoke_session(session_id).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/sessions");
+
+	assert_eq!(get_dashboard_status(&app, &other).await, 303);
+	assert_eq!(get_dashboard_status(&app, &app.api_client).await, 200);
+}
+
+#[tokio::test]
+async fn all_other_sessions_can_be_revoked() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let other = second_device(&app).await;
+
+	let response = app.post_revoke_other_sessions().await;
+	assert_eq!(response.status().as_u16(), 303);
+
+	assert_eq!(get_dashboard_status(&app, &other).await, 303);
+	assert_eq!(get_dashboard_status(&app, &app.api_client).await, 200);
+}
+
+#[tokio::test]
+async fn password_change_revokes_other_sessions() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let other = second_device(&app).await;
+	let new_password = Uuid::new_v4().to_string();
+
+	app.post_change_password(&serde_json::json!({
+		"current_password": &app.test_user.password,
+		"new_password": &new_password,
+		"new_password_check": &new_password
+	}))
+	.await;
+
+	assert_eq!(get_dashboard_status(&app, &other).await, 303);
+	assert_eq!(get_dashboard_status(&app, &app.api_client).await, 200);
+}
+
+async fn expire_second_device(app: &TestApp) {
+	sqlx::query!(
+		"UPDATE user_sessions SET expires_at = now() WHERE user_agent = 'second-device'"
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+}
+
+#[tokio::test]
+async fn idle_sessions_expire() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let other = second_device(&app).await;
+
+	expire_second_device(&app).await;
+
+	assert_eq!(get_dashboard_status(&app, &other).await, 303);
+	let html = app.get_sessions().await.text().await.unwrap();
+	assert!(!html.contains("second-device"));
+}
+
+#[tokio::test]
+async fn using_a_session_pushes_its_expiry_back() {
+	let app = spawn_app().await;
+	let other = second_device(&app).await;
+	sqlx::query!(
+		"UPDATE user_sessions
+		SET last_seen_at = now() - interval '1 hour', expires_at = now() + interval '1 minute'"
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	assert_eq!(get_dashboard_status(&app, &other).await, 200);
+
+	let session = sqlx::query!(
+		r#"SELECT expires_at > now() + interval '1 hour' AS "renewed!" FROM user_sessions"#
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap();
+	assert!(session.renewed);
+}
+
+#[tokio::test]
+async fn expired_sessions_get_purged() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	second_device(&app).await;
+
+	expire_second_device(&app).await;
+
+	assert_eq!(purge_user_sessions(&app.db_pool).await.unwrap(), 1);
+	let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM user_sessions"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(remaining, 1);
+}
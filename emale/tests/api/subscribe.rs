@@ -94,6 +94,7 @@ async fn subscribe_sends_a_confirmation_with_a_link() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_link = app.get_confirmation_link(email_request);
@@ -115,4 +116,70 @@ async fn subscribe_fails_if_fatal_database_error() {
     let response = app.post_subscriptions(body.into()).await;
 
     assert_eq!(response.status().as_u16(), 500);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_when_the_email_provider_is_down() {
+    let app = spawn_app().await;
+    let body = "name=nc%20nocap&email=ncnocap%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    // nothing is sent inline
+    assert!(app.email_server.received_requests().await.unwrap().is_empty());
+
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!(
+        r#"SELECT attempts, last_error, next_attempt_at > now() AS "later!" FROM email_outbox"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.attempts, 1);
+    assert!(queued.last_error.is_some());
+    assert!(queued.later);
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_are_retried() {
+    let app = spawn_app().await;
+    let body = "name=nc%20nocap&email=ncnocap%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE email_outbox SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let confirmation_link = app.get_confirmation_link(&requests[1]);
+    assert_eq!(confirmation_link.html, confirmation_link.plain_text);
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 0);
+}
@@ -25,6 +25,7 @@ async fn link_returned_by_subscribe_return_200() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
 
     let confirmation_link = app.get_confirmation_link(email_request);
@@ -48,6 +49,7 @@ async fn click_confirmation_link_confirm_verification() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_link = app.get_confirmation_link(email_request);
 
@@ -66,3 +68,39 @@ async fn click_confirmation_link_confirm_verification() {
     assert_eq!(saved.name, "nc nocap");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmation_page_links_to_the_preferences() {
+    let app = spawn_app().await;
+    let body = "name=nc%20nocap&email=nc_nocap%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_link(email_request);
+
+    let html = reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriber")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    // askama escapes the `&` of the query string
+    let signature = emale::preferences::preferences_signature(&app.hmac_secret, subscriber_id);
+    assert!(html.contains(&format!(
+        "/preferences?subscriber_id={}&amp;signature={}",
+        subscriber_id, signature
+    )));
+}
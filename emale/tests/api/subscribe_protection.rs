@@ -0,0 +1,212 @@
+use emale::configuration::ChallengeSettings;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn stored_subscribers(app: &TestApp) -> i64 {
+	sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count
+}
+
+async fn expect_emails(app: &TestApp, n: u64) {
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(n)
+		.mount(&app.email_server)
+		.await;
+}
+
+#[tokio::test]
+async fn filled_honeypot_is_silently_rejected() {
+	let app = spawn_app().await;
+	expect_emails(&app, 0).await;
+
+	let response = app
+		.post_subscriptions(format!("{}&website=http%3A%2F%2Fspam.example", BODY))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn submissions_without_a_form_token_are_silently_rejected() {
+	let app = spawn_app().await;
+	expect_emails(&app, 0).await;
+
+	let response = app.post_subscriptions_raw(BODY.into()).await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forged_form_tokens_are_silently_rejected() {
+	let app = spawn_app().await;
+	expect_emails(&app, 0).await;
+	let issued_at = chrono::Utc::now().timestamp() - 60;
+
+	let response = app
+		.post_subscriptions_raw(format!("{}&form_token={}.{}", BODY, issued_at, "ab".repeat(32)))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forms_submitted_too_fast_are_silently_rejected() {
+	let app = spawn_app_with(|c| c.subscribe_protection.min_fill_seconds = 60).await;
+	expect_emails(&app, 0).await;
+
+	let response = app.post_subscriptions(BODY.into()).await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn attempts_beyond_the_per_ip_limit_are_silently_rejected() {
+	let app = spawn_app_with(|c| c.subscribe_protection.max_attempts_per_ip = 2).await;
+	expect_emails(&app, 2).await;
+
+	for i in 0..3 {
+		let response = app
+			.post_subscriptions(format!("name=le%20guin&email=ursula{}%40gmail.com", i))
+			.await;
+		assert_eq!(response.status().as_u16(), 200);
+	}
+	app.dispatch_all_pending_emails().await;
+
+	assert_eq!(stored_subscribers(&app).await, 2);
+}
+
+#[tokio::test]
+async fn forwarded_headers_do_not_get_around_the_per_ip_limit() {
+	let app = spawn_app_with(|c| c.subscribe_protection.max_attempts_per_ip = 2).await;
+	expect_emails(&app, 2).await;
+
+	for i in 0..3 {
+		let body = format!(
+			"name=le%20guin&email=ursula{}%40gmail.com&form_token={}",
+			i,
+			app.subscribe_form_token().await
+		);
+		let response = app
+			.api_client
+			.post(&format!("{}/subscribe", &app.address))
+			.header("Content-Type", "application/x-www-form-urlencoded")
+			.header("X-Forwarded-For", format!("203.0.113.{}", i))
+			.body(body)
+			.send()
+			.await
+			.expect("Failed to execute request");
+		assert_eq!(response.status().as_u16(), 200);
+	}
+	app.dispatch_all_pending_emails().await;
+
+	assert_eq!(stored_subscribers(&app).await, 2);
+}
+
+#[tokio::test]
+async fn rejected_attempts_count_against_the_ip() {
+	let app = spawn_app_with(|c| c.subscribe_protection.max_attempts_per_ip = 2).await;
+	expect_emails(&app, 0).await;
+
+	for _ in 0..2 {
+		app.post_subscriptions_raw(BODY.into()).await;
+	}
+	let response = app.post_subscriptions(BODY.into()).await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn emails_beyond_the_per_domain_limit_are_silently_rejected() {
+	let app = spawn_app_with(|c| c.subscribe_protection.max_emails_per_domain = 1).await;
+	expect_emails(&app, 2).await;
+
+	for email in ["a%40victim.example", "b%40victim.example", "c%40other.example"] {
+		let response = app
+			.post_subscriptions(format!("name=le%20guin&email={}", email))
+			.await;
+		assert_eq!(response.status().as_u16(), 200);
+	}
+	app.dispatch_all_pending_emails().await;
+
+	let stored: Vec<String> = sqlx::query!("SELECT email FROM subscriber ORDER BY email")
+		.fetch_all(&app.db_pool)
+		.await
+		.unwrap()
+		.into_iter()
+		.map(|r| r.email)
+		.collect();
+	assert_eq!(stored, vec!["a@victim.example", "c@other.example"]);
+}
+
+#[tokio::test]
+async fn a_failed_challenge_is_silently_rejected() {
+	let app = spawn_app_with(|c| {
+		c.subscribe_protection.challenge = ChallengeSettings::Local {
+			expected_response: "human".into(),
+		}
+	})
+	.await;
+	expect_emails(&app, 0).await;
+
+	let response = app
+		.post_subscriptions(format!("{}&challenge_response=robot", BODY))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_passed_challenge_lets_the_subscription_through() {
+	let app = spawn_app_with(|c| {
+		c.subscribe_protection.challenge = ChallengeSettings::Local {
+			expected_response: "human".into(),
+		}
+	})
+	.await;
+	expect_emails(&app, 1).await;
+
+	let response = app
+		.post_subscriptions(format!("{}&challenge_response=human", BODY))
+		.await;
+	app.dispatch_all_pending_emails().await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert_eq!(stored_subscribers(&app).await, 1);
+}
+
+#[tokio::test]
+async fn invalid_data_is_still_rejected_with_a_400() {
+	let app = spawn_app().await;
+
+	let response = app
+		.post_subscriptions_raw("name=le%20guin&email=not-an-email&website=spam".into())
+		.await;
+
+	assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn rejections_are_counted_per_reason() {
+	let app = spawn_app().await;
+	expect_emails(&app, 0).await;
+
+	app.post_subscriptions(format!("{}&website=spam", BODY)).await;
+
+	let metrics = app.get_metrics_text().await;
+	assert!(metrics.contains(r#"subscribe_rejections_total{reason="honeypot"}"#));
+}
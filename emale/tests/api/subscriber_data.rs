@@ -0,0 +1,242 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+struct TestSubscriber {
+	id: Uuid,
+	email: String,
+	confirmation_token: String,
+}
+
+// A confirmed subscriber with one issue waiting to be delivered to them.
+async fn create_subscriber(app: &TestApp, issue_id: Uuid) -> TestSubscriber {
+	let subscriber = TestSubscriber {
+		id: Uuid::new_v4(),
+		email: format!("{}@example.com", Uuid::new_v4()),
+		confirmation_token: Uuid::new_v4().to_string(),
+	};
+	sqlx::query!(
+		"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+		VALUES ($1, $2, 'le guin', now(), 'confirmed')",
+		subscriber.id,
+		subscriber.email
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	sqlx::query!(
+		"INSERT INTO subscriber_tokens (subscriber_token, subscriber_id) VALUES ($1, $2)",
+		subscriber.confirmation_token,
+		subscriber.id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	sqlx::query!(
+		"INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) VALUES ($1, $2)",
+		issue_id,
+		subscriber.email
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	subscriber
+}
+
+async fn create_issue(app: &TestApp) -> Uuid {
+	let issue_id = Uuid::new_v4();
+	sqlx::query!(
+		"INSERT INTO newsletter_issues (newsletter_issue_id, title, text_content, html_content, published_at)
+		VALUES ($1, 'The Dispossessed', 'text', '<p>html</p>', now()::TEXT)",
+		issue_id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	issue_id
+}
+
+async fn request_access_token(app: &TestApp, email: &str) -> String {
+	let _mock_guard = Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount_as_scoped(&app.email_server)
+		.await;
+
+	app.post_subscriber_data_request(&serde_json::json!({ "email": email }))
+		.await;
+
+	let email_request = &app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	let access_link = app.get_confirmation_link(email_request);
+	assert_eq!(access_link.html, access_link.plain_text);
+	access_link.html
+		.query_pairs()
+		.find(|(key, _)| key == "token")
+		.map(|(_, token)| token.into_owned())
+		.unwrap()
+}
+
+async fn pending_deliveries(app: &TestApp, email: &str) -> i64 {
+	sqlx::query!(
+		r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+		email
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count
+}
+
+#[tokio::test]
+async fn unknown_email_gets_the_same_response_without_an_email() {
+	let app = spawn_app().await;
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&app.email_server)
+		.await;
+
+	let response = app
+		.post_subscriber_data_request(&serde_json::json!({ "email": "nobody@example.com" }))
+		.await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/subscriber-data");
+}
+
+#[tokio::test]
+async fn export_contains_everything_held_about_the_subscriber() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app).await;
+	let subscriber = create_subscriber(&app, issue_id).await;
+	create_subscriber(&app, issue_id).await;
+	let token = request_access_token(&app, &subscriber.email).await;
+
+	let response = app.get_subscriber_data_export(&token).await;
+
+	assert_eq!(response.status().as_u16(), 200);
+	assert!(response
+		.headers()
+		.get("Content-Disposition")
+		.unwrap()
+		.to_str()
+		.unwrap()
+		.starts_with("attachment"));
+	let export: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(export["subscriber"]["id"], subscriber.id.to_string());
+	assert_eq!(export["subscriber"]["email"], subscriber.email);
+	assert_eq!(export["subscriber"]["status"], "confirmed");
+	assert_eq!(
+		export["confirmation_tokens"],
+		serde_json::json!([subscriber.confirmation_token])
+	);
+	assert_eq!(
+		export["pending_deliveries"],
+		serde_json::json!([{
+			"newsletter_issue_id": issue_id.to_string(),
+			"title": "The Dispossessed"
+		}])
+	);
+	assert_eq!(export["list_opt_outs"], serde_json::json!([]));
+	assert_eq!(export["pending_email_change"], serde_json::Value::Null);
+	assert_eq!(export["sequence_progress"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn export_requires_a_valid_token() {
+	let app = spawn_app().await;
+
+	let response = app.get_subscriber_data_export("not-a-token").await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/subscriber-data");
+}
+
+#[tokio::test]
+async fn erasure_deletes_the_subscriber_rows() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app).await;
+	let subscriber = create_subscriber(&app, issue_id).await;
+	let other = create_subscriber(&app, issue_id).await;
+	let token = request_access_token(&app, &subscriber.email).await;
+
+	let response = app.post_subscriber_data_erase(&token).await;
+	assert_eq!(response.status().as_u16(), 303);
+
+	let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriber WHERE id = $1"#, subscriber.id)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(remaining, 0);
+	let tokens = sqlx::query!(
+		r#"SELECT COUNT(*) AS "count!" FROM subscriber_tokens WHERE subscriber_id = $1"#,
+		subscriber.id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count;
+	assert_eq!(tokens, 0);
+	assert_eq!(pending_deliveries(&app, &subscriber.email).await, 0);
+	// other subscribers are left alone
+	assert_eq!(pending_deliveries(&app, &other.email).await, 1);
+}
+
+#[tokio::test]
+async fn dropped_deliveries_are_counted_as_erased() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app).await;
+	let subscriber = create_subscriber(&app, issue_id).await;
+	let token = request_access_token(&app, &subscriber.email).await;
+
+	app.post_subscriber_data_erase(&token).await;
+
+	let metrics = app.get_metrics_text().await;
+	assert!(metrics.contains(&format!(
+		r#"issue_deliveries_total{{newsletter_issue_id="{}",outcome="erased"}} 1"#,
+		issue_id
+	)));
+	let issue = sqlx::query!(
+		"SELECT erased_deliveries FROM newsletter_issues WHERE newsletter_issue_id = $1",
+		issue_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap();
+	assert_eq!(issue.erased_deliveries, 1);
+}
+
+#[tokio::test]
+async fn access_links_stop_working_after_erasure() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app).await;
+	let subscriber = create_subscriber(&app, issue_id).await;
+	let token = request_access_token(&app, &subscriber.email).await;
+
+	app.post_subscriber_data_erase(&token).await;
+	let response = app.get_subscriber_data_export(&token).await;
+
+	assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn a_new_link_invalidates_the_previous_one() {
+	let app = spawn_app().await;
+	let issue_id = create_issue(&app).await;
+	let subscriber = create_subscriber(&app, issue_id).await;
+	let first_token = request_access_token(&app, &subscriber.email).await;
+	let second_token = request_access_token(&app, &subscriber.email).await;
+
+	assert_eq!(app.get_subscriber_data_export(&first_token).await.status().as_u16(), 303);
+	assert_eq!(app.get_subscriber_data_export(&second_token).await.status().as_u16(), 200);
+}
@@ -0,0 +1,81 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::spawn_app;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+fn incoming_traceparent() -> String {
+	format!("00-{}-00f067aa0ba902b7-01", TRACE_ID)
+}
+
+#[tokio::test]
+async fn queued_confirmation_emails_keep_the_trace_context_of_the_subscribe_request() {
+	let app = spawn_app().await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&app.email_server)
+		.await;
+
+	let form_token = app.subscribe_form_token().await;
+	app.api_client
+		.post(&format!("{}/subscribe", &app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("traceparent", incoming_traceparent())
+		.body(format!("name=nc%20nocap&email=nc_nocap%40gmail.com&form_token={}", form_token))
+		.send()
+		.await
+		.expect("Failed to execute request");
+
+	let queued = sqlx::query!("SELECT traceparent FROM email_outbox")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap();
+	let traceparent = queued.traceparent.expect("The queued email has no trace context");
+	// same trace, but a child span
+	assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+	assert_ne!(traceparent, incoming_traceparent());
+
+	// the worker sends it in its own trace, propagated to the provider
+	app.dispatch_all_pending_emails().await;
+	let email_request = &app.email_server.received_requests().await.unwrap()[0];
+	assert!(email_request.headers.get(&"traceparent".into()).is_some());
+}
+
+#[tokio::test]
+async fn queued_deliveries_keep_the_trace_context_of_the_publish_request() {
+	let app = spawn_app().await;
+	sqlx::query!(
+		"INSERT INTO subscriber (id, email, name, subscribed_at, status)
+		VALUES ($1, 'confirmed@example.com', 'confirmed', now(), 'confirmed')",
+		uuid::Uuid::new_v4()
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+	app.test_user.login(&app).await;
+	let csrf_token = app.csrf_token().await;
+
+	app.api_client
+		.post(&format!("{}/admin/newsletters", &app.address))
+		.header("X-CSRF-Token", csrf_token)
+		.header("traceparent", incoming_traceparent())
+		.form(&serde_json::json!({
+			"title": "Newsletter title",
+			"text_content": "Newsletter body as plain text",
+			"html_content": "<p>Newsletter body as HTML</p>",
+			"idempotency_key": uuid::Uuid::new_v4().to_string()
+		}))
+		.send()
+		.await
+		.expect("Failed to execute request");
+
+	let queued = sqlx::query!("SELECT traceparent FROM issue_delivery_queue")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap();
+	let traceparent = queued.traceparent.expect("The queued delivery has no trace context");
+	assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+}
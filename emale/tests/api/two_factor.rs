@@ -0,0 +1,199 @@
+use emale::authentication::two_factor::{encrypt_legacy_totp_secrets, generate_totp_secret, totp_code};
+use emale::configuration::get_config;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn extract_secret(html: &str) -> Secret<String> {
+	let start = html.find("secret=").expect("No otpauth secret in page") + "secret=".len();
+	let end = start + html[start..].find('&').unwrap();
+	Secret::new(html[start..end].to_owned())
+}
+
+fn extract_recovery_codes(html: &str) -> Vec<String> {
+	html.split("<li><code>")
+		.skip(1)
+		.map(|s| s.split("</code>").next().unwrap().to_owned())
+		.collect()
+}
+
+fn current_code(secret: &Secret<String>) -> String {
+	totp_code(secret, chrono::Utc::now().timestamp() as u64).unwrap()
+}
+
+// Logs the test user in and enables 2FA, returning the secret
+// and the recovery codes.
+async fn enable_two_factor(app: &TestApp) -> (Secret<String>, Vec<String>) {
+	app.test_user.login(app).await;
+	let html = app.get_two_factor_html().await;
+	let secret = extract_secret(&html);
+
+	let response = app.post_two_factor(&serde_json::json!({
+		"code": current_code(&secret)
+	})).await;
+	assert_eq!(response.status().as_u16(), 200);
+	let recovery_codes = extract_recovery_codes(&response.text().await.unwrap());
+	assert_eq!(recovery_codes.len(), 10);
+
+	app.post_logout().await;
+	(secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn must_logged_in_to_get_two_factor_form() {
+	let app = spawn_app().await;
+	let response = app.api_client
+		.get(&format!("{}/admin/two-factor", &app.address))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn invalid_code_does_not_enable_two_factor() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+	let html = app.get_two_factor_html().await;
+	let secret = extract_secret(&html);
+
+	let response = app.post_two_factor(&serde_json::json!({
+		"code": "000000"
+	})).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/two-factor");
+
+	// the pending secret is kept until it is confirmed
+	let html = app.get_two_factor_html().await;
+	assert!(html.contains("<p><i>The authentication code is invalid</i></p>"));
+	assert_eq!(
+		secrecy::ExposeSecret::expose_secret(&extract_secret(&html)),
+		secrecy::ExposeSecret::expose_secret(&secret)
+	);
+}
+
+#[tokio::test]
+async fn login_requires_second_factor_when_enabled() {
+	let app = spawn_app().await;
+	let (secret, _) = enable_two_factor(&app).await;
+
+	let response = app.post_login(&serde_json::json!({
+		"username": &app.test_user.username,
+		"password": &app.test_user.password
+	})).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login/two-factor");
+
+	// password alone does not grant access to the admin area
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login/two-factor");
+
+	let response = app.post_login_two_factor(&serde_json::json!({
+		"code": current_code(&secret)
+	})).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/dashboard");
+
+	let html = app.get_admin_dashboard_html().await;
+	assert!(html.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn wrong_second_factor_is_rejected() {
+	let app = spawn_app().await;
+	enable_two_factor(&app).await;
+	app.test_user.login(&app).await;
+
+	let response = app.post_login_two_factor(&serde_json::json!({
+		"code": "not-a-code"
+	})).await;
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login/two-factor");
+
+	let html = app.get_login_two_factor_html().await;
+	assert!(html.contains("<p><i>Authentication failed</i></p>"));
+
+	let response = app.get_admin_dashboard().await;
+	assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn totp_code_cannot_be_replayed() {
+	let app = spawn_app().await;
+	let (secret, _) = enable_two_factor(&app).await;
+	let code = current_code(&secret);
+
+	app.test_user.login(&app).await;
+	let response = app.post_login_two_factor(&serde_json::json!({ "code": &code })).await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/dashboard");
+	app.post_logout().await;
+
+	app.test_user.login(&app).await;
+	let response = app.post_login_two_factor(&serde_json::json!({ "code": &code })).await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login/two-factor");
+}
+
+#[tokio::test]
+async fn recovery_code_can_be_used_only_once() {
+	let app = spawn_app().await;
+	let (_, recovery_codes) = enable_two_factor(&app).await;
+
+	app.test_user.login(&app).await;
+	let response = app.post_login_two_factor(&serde_json::json!({
+		"code": &recovery_codes[0]
+	})).await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/dashboard");
+	app.post_logout().await;
+
+	app.test_user.login(&app).await;
+	let response = app.post_login_two_factor(&serde_json::json!({
+		"code": &recovery_codes[0]
+	})).await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/login/two-factor");
+}
+
+#[tokio::test]
+async fn totp_secret_is_not_stored_in_plain_text() {
+	let app = spawn_app().await;
+	let (secret, _) = enable_two_factor(&app).await;
+
+	let stored = sqlx::query!(
+		r#"SELECT totp_secret AS "totp_secret!" FROM users WHERE user_id = $1"#,
+		app.test_user.user_id
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.totp_secret;
+	assert!(!String::from_utf8_lossy(&stored).contains(secret.expose_secret()));
+}
+
+#[tokio::test]
+async fn legacy_plain_text_secret_keeps_working_once_encrypted() {
+	let app = spawn_app().await;
+	let secret = generate_totp_secret();
+	sqlx::query!(
+		r#"
+		UPDATE users
+		SET legacy_totp_secret = $1, totp_enabled = true
+		WHERE user_id = $2
+		"#,
+		secret.expose_secret(),
+		app.test_user.user_id
+	)
+	.execute(&app.db_pool)
+	.await
+	.unwrap();
+
+	// as done by the application on startup
+	let key = get_config().unwrap().application.totp_encryption_key;
+	encrypt_legacy_totp_secrets(&key, &app.db_pool).await.unwrap();
+
+	app.test_user.login(&app).await;
+	let response = app.post_login_two_factor(&serde_json::json!({
+		"code": current_code(&secret)
+	})).await;
+	assert_eq!(response.headers().get("LOCATION").unwrap(), "/admin/dashboard");
+}
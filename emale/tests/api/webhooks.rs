@@ -0,0 +1,268 @@
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_endpoint(app: &TestApp, receiver: &MockServer) -> Uuid {
+	let response = app
+		.post_webhook_endpoint(&format!("{}/hooks", receiver.uri()))
+		.await;
+	assert_eq!(response.status().as_u16(), 303);
+	sqlx::query!("SELECT endpoint_id FROM webhook_endpoints")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.endpoint_id
+}
+
+async fn subscribe_and_confirm(app: &TestApp) -> Uuid {
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&app.email_server)
+		.await;
+	app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+		.await
+		.error_for_status()
+		.unwrap();
+	app.dispatch_all_pending_emails().await;
+	let email_request = app
+		.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	let confirmation_link = app.get_confirmation_link(&email_request);
+	reqwest::get(confirmation_link.html)
+		.await
+		.unwrap()
+		.error_for_status()
+		.unwrap();
+	sqlx::query!("SELECT id FROM subscriber")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.id
+}
+
+fn event(request: &Request) -> serde_json::Value {
+	serde_json::from_slice(&request.body).unwrap()
+}
+
+async fn mount_receiver(receiver: &MockServer, status: u16) {
+	Mock::given(path("/hooks"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(status))
+		.mount(receiver)
+		.await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_webhooks() {
+	let app = spawn_app().await;
+
+	let response = app.get_webhooks().await;
+
+	assert_eq!(response.status().as_u16(), 303);
+	assert_eq!(response.headers().get("Location").unwrap(), "/login");
+}
+
+#[tokio::test]
+async fn lifecycle_events_are_delivered_signed() {
+	let app = spawn_app().await;
+	let receiver = MockServer::start().await;
+	mount_receiver(&receiver, 200).await;
+	app.test_user.login(&app).await;
+	create_endpoint(&app, &receiver).await;
+
+	let subscriber_id = subscribe_and_confirm(&app).await;
+	app.dispatch_all_webhooks().await;
+
+	let requests = receiver.received_requests().await.unwrap();
+	let types: Vec<_> = requests.iter().map(|r| event(r)["type"].clone()).collect();
+	assert_eq!(types, vec!["subscriber.created", "subscriber.confirmed"]);
+	let confirmed = event(&requests[1]);
+	assert_eq!(confirmed["data"]["subscriber_id"], subscriber_id.to_string());
+	assert_eq!(confirmed["data"]["email"], "ursula_le_guin@gmail.com");
+	assert_eq!(confirmed["data"]["status"], "confirmed");
+
+	let secret = sqlx::query!("SELECT secret FROM webhook_endpoints")
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.secret;
+	for request in &requests {
+		let header = request
+			.headers
+			.get(&"x-emale-signature".into())
+			.expect("The delivery is not signed")
+			.last()
+			.as_str();
+		let (timestamp, signature) = header
+			.strip_prefix("t=")
+			.and_then(|rest| rest.split_once(",v1="))
+			.unwrap();
+		let body = std::str::from_utf8(&request.body).unwrap();
+		assert_eq!(
+			signature,
+			emale::webhooks::signature(&Secret::new(secret.clone()), timestamp.parse().unwrap(), body)
+		);
+	}
+}
+
+#[tokio::test]
+async fn nothing_is_recorded_without_an_endpoint() {
+	let app = spawn_app().await;
+
+	subscribe_and_confirm(&app).await;
+
+	let events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM webhook_events"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(events, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+	let app = spawn_app().await;
+	let receiver = MockServer::start().await;
+	Mock::given(path("/hooks"))
+		.respond_with(ResponseTemplate::new(500))
+		.up_to_n_times(2)
+		.mount(&receiver)
+		.await;
+	mount_receiver(&receiver, 200).await;
+	app.test_user.login(&app).await;
+	let endpoint_id = create_endpoint(&app, &receiver).await;
+	subscribe_and_confirm(&app).await;
+
+	app.dispatch_all_webhooks().await;
+	let deliveries = sqlx::query!(
+		"SELECT attempts, last_status, next_attempt_at > now() AS \"later!\" FROM webhook_deliveries"
+	)
+	.fetch_all(&app.db_pool)
+	.await
+	.unwrap();
+	assert!(deliveries
+		.iter()
+		.all(|d| d.attempts == 1 && d.last_status == Some(500) && d.later));
+
+	sqlx::query!("UPDATE webhook_deliveries SET next_attempt_at = now()")
+		.execute(&app.db_pool)
+		.await
+		.unwrap();
+	app.dispatch_all_webhooks().await;
+	let delivered = sqlx::query!(
+		r#"SELECT COUNT(*) AS "count!" FROM webhook_deliveries WHERE delivered_at IS NOT NULL AND attempts = 2"#
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.count;
+	assert_eq!(delivered, 2);
+	let html = app.get_webhook_deliveries_html(endpoint_id).await;
+	assert!(html.contains("delivered"));
+}
+
+#[tokio::test]
+async fn admins_can_resend_an_event() {
+	let app = spawn_app().await;
+	let receiver = MockServer::start().await;
+	mount_receiver(&receiver, 200).await;
+	app.test_user.login(&app).await;
+	let endpoint_id = create_endpoint(&app, &receiver).await;
+	subscribe_and_confirm(&app).await;
+	app.dispatch_all_webhooks().await;
+	let delivery_id = sqlx::query!(
+		"SELECT d.delivery_id FROM webhook_deliveries d
+		JOIN webhook_events e USING (event_id)
+		WHERE e.event_type = 'subscriber.confirmed'"
+	)
+	.fetch_one(&app.db_pool)
+	.await
+	.unwrap()
+	.delivery_id;
+
+	let response = app.post_resend_webhook(delivery_id).await;
+	assert_eq!(
+		response.headers().get("Location").unwrap(),
+		&format!("/admin/webhooks/{}", endpoint_id)
+	);
+	app.dispatch_all_webhooks().await;
+
+	let requests = receiver.received_requests().await.unwrap();
+	assert_eq!(requests.len(), 3);
+	assert_eq!(event(&requests[2]), event(&requests[1]));
+	assert_ne!(
+		requests[2].headers.get(&"x-emale-delivery".into()),
+		requests[1].headers.get(&"x-emale-delivery".into())
+	);
+}
+
+#[tokio::test]
+async fn paused_endpoints_receive_nothing() {
+	let app = spawn_app().await;
+	let receiver = MockServer::start().await;
+	mount_receiver(&receiver, 200).await;
+	app.test_user.login(&app).await;
+	let endpoint_id = create_endpoint(&app, &receiver).await;
+	app.post_webhook_activity(endpoint_id, false).await;
+
+	subscribe_and_confirm(&app).await;
+	app.dispatch_all_webhooks().await;
+
+	assert!(receiver.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn erasure_sends_an_unsubscribed_event_and_drops_earlier_ones() {
+	let app = spawn_app().await;
+	let receiver = MockServer::start().await;
+	mount_receiver(&receiver, 200).await;
+	app.test_user.login(&app).await;
+	create_endpoint(&app, &receiver).await;
+	let subscriber_id = subscribe_and_confirm(&app).await;
+
+	assert!(emale::subscriber_data::erase_subscriber(subscriber_id, &app.db_pool)
+		.await
+		.unwrap());
+	app.dispatch_all_webhooks().await;
+
+	let requests = receiver.received_requests().await.unwrap();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(event(&requests[0])["type"], "subscriber.unsubscribed");
+	// nothing personal outlives the subscriber
+	assert_eq!(
+		event(&requests[0])["data"],
+		serde_json::json!({ "subscriber_id": subscriber_id })
+	);
+	let payloads: Vec<String> = sqlx::query!("SELECT payload::TEXT AS \"payload!\" FROM webhook_events")
+		.fetch_all(&app.db_pool)
+		.await
+		.unwrap()
+		.into_iter()
+		.map(|r| r.payload)
+		.collect();
+	assert_eq!(payloads.len(), 1);
+	assert!(!payloads[0].contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn endpoints_must_be_http_urls() {
+	let app = spawn_app().await;
+	app.test_user.login(&app).await;
+
+	app.post_webhook_endpoint("ftp://crm.example.com").await;
+
+	let endpoints = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM webhook_endpoints"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count;
+	assert_eq!(endpoints, 0);
+}